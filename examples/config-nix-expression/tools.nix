@@ -0,0 +1,5 @@
+{ writeShellScriptBin }:
+
+writeShellScriptBin "greet" ''
+  echo "Hello from tools.nix"
+''
@@ -0,0 +1,12 @@
+from flask import Flask
+
+app = Flask(__name__, static_folder="dist")
+
+
+@app.route("/")
+def index():
+    return app.send_static_file("index.html")
+
+
+if __name__ == "__main__":
+    app.run()
@@ -0,0 +1 @@
+print("Generating assets")
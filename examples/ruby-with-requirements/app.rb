@@ -0,0 +1,7 @@
+require 'sinatra'
+
+puts "Hello from Sinatra"
+
+get '/' do
+  'Choo Choo! Welcome to your Sinatra server 🚅'
+end
@@ -0,0 +1 @@
+port ENV['PORT'] || 4567
@@ -21,18 +21,35 @@
 use crate::nixpacks::{
     app::App,
     builder::{
-        docker::{docker_image_builder::DockerImageBuilder, DockerBuilderOptions},
-        ImageBuilder,
+        docker::{
+            cache::get_phase_cache_keys,
+            cache_metadata::{self, CacheMetadata, CacheReport, MAX_AGE_UNITS},
+            docker_image_builder::DockerImageBuilder,
+            DockerBuilderOptions,
+        },
+        oci::OciImageBuilder,
+        ImageBuilder, ImageBuilderKind,
     },
     environment::Environment,
     logger::Logger,
-    nix::pkg::Pkg,
+    nix::{
+        flake::create_flake_for_plan,
+        index::NixpkgsIndex,
+        lock::{NixLock, NIX_LOCK_FILE},
+        mirror::{prefetch, NixMirror, NixSources},
+        nix_file_names_for_phases,
+        optimize::ArchiveReport,
+        pkg::Pkg,
+        NIXPKGS_ARCHIVE,
+    },
     plan::{
-        generator::{GeneratePlanOptions, NixpacksBuildPlanGenerator},
+        explain::PlanExplanation,
+        generator::{GeneratePlanOptions, NixpacksBuildPlanGenerator, ProviderSelection},
+        utils::parse_duration,
         BuildPlan, PlanGenerator,
     },
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use providers::{
     clojure::ClojureProvider, cobol::CobolProvider, crystal::CrystalProvider,
     csharp::CSharpProvider, dart::DartProvider, deno::DenoProvider, elixir::ElixirProvider,
@@ -41,8 +58,8 @@ use providers::{
     node::NodeProvider, php::PhpProvider, python::PythonProvider, ruby::RubyProvider,
     rust::RustProvider, scala::ScalaProvider, scheme::HauntProvider,
     staticfile::StaticfileProvider, swift::SwiftProvider, zig::ZigProvider, Provider,
+    ProviderVersion,
 };
-use std::process::Command;
 
 mod chain;
 #[macro_use]
@@ -107,6 +124,216 @@ pub fn get_plan_providers(
     generator.get_plan_providers(&app, &environment)
 }
 
+/// Explains which providers are used to build an app, and why the others are not.
+pub fn get_provider_detections(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<ProviderSelection> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let generator = NixpacksBuildPlanGenerator::new(get_providers(), options.clone());
+
+    generator.get_provider_detections(&app, &environment)
+}
+
+/// Checks the app's config file in strict mode and its Nix packages, returning the path of the file that was checked.
+pub fn validate_config(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<Option<String>> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let options = GeneratePlanOptions {
+        strict: true,
+        check_packages: true,
+        ..options.clone()
+    };
+    let mut generator = NixpacksBuildPlanGenerator::new(get_providers(), options);
+    generator.generate_plan(&app, &environment)?;
+
+    generator.get_config_file_path(&app, &environment)
+}
+
+/// Reads a saved build plan (`.json` or `.toml`), or generates one if the path is an app source directory.
+pub fn read_or_generate_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<BuildPlan> {
+    let plan_path = std::path::Path::new(path);
+    if !plan_path.is_file() {
+        return generate_build_plan(path, envs, options);
+    }
+
+    let contents = std::fs::read_to_string(plan_path)
+        .with_context(|| format!("Failed to read build plan `{path}`"))?;
+    let plan = match plan_path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => BuildPlan::from_json(contents),
+        Some("toml") => BuildPlan::from_toml(contents),
+        _ => bail!("Unknown build plan file type: {}", path),
+    };
+
+    plan.with_context(|| format!("Failed to parse build plan `{path}`"))
+}
+
+/// Generates a build plan and explains which source set each of its values.
+pub fn explain_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<PlanExplanation> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let generator = NixpacksBuildPlanGenerator::new(get_providers(), options.clone());
+
+    generator.explain_plan(&app, &environment)
+}
+
+/// Generates a `flake.nix` whose development shells match the Nix packages and variables of the build plan.
+pub fn generate_dev_env_flake(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<String> {
+    let plan = generate_build_plan(path, envs, options)?;
+    if nix_file_names_for_phases(&plan.phases.clone().unwrap_or_default()).is_empty() {
+        bail!("The build plan does not install any Nix packages");
+    }
+
+    Ok(create_flake_for_plan(&plan))
+}
+
+/// Resolves the Nix dependencies of the build plan and writes them to the app's `nixpacks.lock`.
+///
+/// Entries that are already locked are kept, unless `update` is set.
+pub fn lock_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+    update: bool,
+) -> Result<NixLock> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    // An outdated lock can't be read, but is replaced when updating
+    let options = GeneratePlanOptions {
+        ignore_lock: update,
+        ..options.clone()
+    };
+    let mut generator = NixpacksBuildPlanGenerator::new(get_providers(), options);
+    let (plan, app) = generator.generate_plan(&app, &environment)?;
+
+    let lock = NixLock::resolve(
+        &plan.phases.clone().unwrap_or_default(),
+        plan.nix_lock.as_ref(),
+    )?;
+    std::fs::write(app.source.join(NIX_LOCK_FILE), lock.to_toml()?)
+        .with_context(|| format!("Writing {NIX_LOCK_FILE}"))?;
+
+    Ok(lock)
+}
+
+/// Downloads the nixpkgs archives and overlays of the build plan into a mirror directory, for builds without internet access.
+///
+/// Returns the paths of the tarballs that weren't in the directory yet.
+pub fn prefetch_build_plan(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+    out: &std::path::Path,
+) -> Result<Vec<String>> {
+    let app = App::new(path)?;
+    let environment = Environment::from_envs(envs)?;
+
+    let mut generator = NixpacksBuildPlanGenerator::new(get_providers(), options.clone());
+    let (plan, _) = generator.generate_plan(&app, &environment)?;
+
+    std::fs::create_dir_all(out).with_context(|| format!("Creating {}", out.display()))?;
+    prefetch(
+        &plan.phases.clone().unwrap_or_default(),
+        NixSources::new(plan.nix_lock.as_ref(), None),
+        out,
+    )
+}
+
+/// Reports which of the nixpkgs archives of the build plan could be collapsed into another, without changing the plan.
+pub fn get_archive_report(
+    path: &str,
+    envs: Vec<&str>,
+    options: &GeneratePlanOptions,
+) -> Result<ArchiveReport> {
+    let options = GeneratePlanOptions {
+        optimize_archives: false,
+        ..options.clone()
+    };
+    let plan = generate_build_plan(path, envs, &options)?;
+
+    ArchiveReport::load(&plan.phases.clone().unwrap_or_default(), plan.nix_sources())
+}
+
+/// The versions of the languages and tools each provider can install, by provider name.
+pub fn get_provider_versions() -> Vec<(String, Vec<ProviderVersion>)> {
+    get_providers()
+        .iter()
+        .map(|provider| (provider.name().to_string(), provider.versions()))
+        .filter(|(_, versions)| !versions.is_empty())
+        .collect()
+}
+
+/// Searches the package names of a nixpkgs archive, the default one if none is given.
+///
+/// The archive is fetched from `NIXPACKS_NIXPKGS_MIRROR` when it is set, like in builds. Returns `None` when the package
+/// names aren't cached and Nix isn't installed to list them.
+pub fn search_nix_packages(
+    query: &str,
+    archive: Option<&str>,
+    envs: Vec<&str>,
+) -> Result<Option<Vec<String>>> {
+    let environment = Environment::from_envs(envs)?;
+    let mirror = environment
+        .get_config_variable("NIXPKGS_MIRROR")
+        .map(|url| NixMirror::new(&url))
+        .transpose()?;
+
+    let archive = archive.unwrap_or(NIXPKGS_ARCHIVE);
+    let index = NixpkgsIndex::load(archive, NixSources::new(None, mirror.as_ref()))?;
+
+    Ok(index.map(|index| index.search(query)))
+}
+
+/// Lists the caches of past builds, with the apps and phases they belong to.
+pub fn get_cache_report() -> Result<CacheReport> {
+    let metadata = CacheMetadata::load(&get_cache_metadata_path()?)?;
+    Ok(CacheReport::new(&metadata))
+}
+
+/// Removes the caches no build used within a duration like `7d`, returning their keys.
+pub fn prune_caches(older_than: &str) -> Result<Vec<String>> {
+    let max_age = parse_duration(older_than, &MAX_AGE_UNITS)?.as_secs();
+    CacheMetadata::update(&get_cache_metadata_path()?, |metadata| {
+        let keys = metadata.unused_since(cache_metadata::now().saturating_sub(max_age));
+        metadata.remove(&keys)?;
+        Ok(keys)
+    })
+}
+
+/// Removes the caches with the given keys, from `nixpacks cache ls`.
+pub fn remove_caches(keys: &[String]) -> Result<()> {
+    CacheMetadata::update(&get_cache_metadata_path()?, |metadata| {
+        metadata.remove(keys)
+    })
+}
+
+fn get_cache_metadata_path() -> Result<std::path::PathBuf> {
+    CacheMetadata::default_path()
+        .context("Set HOME or XDG_CACHE_HOME to find the caches of past builds")
+}
+
 /// Builds a Docker image based on environment data and build options from config files or existing build plans.
 pub async fn create_docker_image(
     path: &str,
@@ -128,17 +355,59 @@ pub async fn create_docker_image(
         }
     }
 
+    let caching = !build_options.no_cache && !environment.is_config_variable_truthy("NO_CACHE");
+    let cache_keys = if caching {
+        get_phase_cache_keys(&plan, &app, build_options.cache_key.as_deref())?
+    } else {
+        std::collections::BTreeMap::new()
+    };
+    if build_options.explain_cache {
+        if !caching {
+            println!("Caching is disabled");
+        } else if cache_keys.is_empty() {
+            println!("No phases have cache directories");
+        }
+        for (phase, key) in &cache_keys {
+            print!("{phase}: {key}");
+        }
+        return Ok(());
+    }
+
+    let build_options = DockerBuilderOptions {
+        cache_keys: cache_keys
+            .into_iter()
+            .map(|(phase, key)| (phase, key.key))
+            .collect(),
+        ..build_options.clone()
+    };
+
     let logger = Logger::new();
-    let builder = DockerImageBuilder::new(logger, build_options.clone());
+    let builder: Box<dyn ImageBuilder> = match build_options.builder {
+        ImageBuilderKind::Docker => {
+            Box::new(DockerImageBuilder::new(logger, build_options.clone()))
+        }
+        ImageBuilderKind::Oci => Box::new(OciImageBuilder::new(logger, build_options.clone())),
+    };
 
     let phase_count = plan.phases.clone().map_or(0, |phases| phases.len());
     if phase_count > 0 {
         println!("{}", plan.get_build_string()?);
 
         let start = plan.start_phase.clone().unwrap_or_default();
-        if start.cmd.is_none() && !build_options.no_error_without_start {
+        if start.cmd.is_none() && start.processes.is_none() && !build_options.no_error_without_start
+        {
             bail!("No start command could be found")
         }
+
+        if let Some(lock) = &plan.nix_lock {
+            let missing = lock.missing_entries(&plan.phases.clone().unwrap_or_default());
+            if !missing.is_empty() {
+                eprintln!(
+                    "Warning: {NIX_LOCK_FILE} is missing {}, run `nixpacks lock` to add them",
+                    missing.join(", ")
+                );
+            }
+        }
     } else {
         println!("\nNixpacks was unable to generate a build plan for this app.\nPlease check the documentation for supported languages: https://nixpacks.com");
         println!("\nThe contents of the app directory are:\n");
@@ -155,8 +424,8 @@ pub async fn create_docker_image(
         std::process::exit(1);
     }
 
-    if build_options.out_dir.is_none() {
-        ensure_docker_exists()?;
+    if build_options.out_dir.is_none() && build_options.builder == ImageBuilderKind::Docker {
+        build_options.engine.ensure_exists()?;
     }
 
     builder
@@ -165,13 +434,3 @@ pub async fn create_docker_image(
 
     Ok(())
 }
-
-fn ensure_docker_exists() -> Result<()> {
-    let mut docker_build_cmd = Command::new("docker");
-
-    if docker_build_cmd.output().is_err() {
-        bail!("Please install Docker to build the app https://docs.docker.com/engine/install/");
-    }
-
-    Ok(())
-}
@@ -1,24 +1,31 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use clap::{arg, Parser, Subcommand, ValueEnum};
 use nixpacks::{
-    create_docker_image, generate_build_plan, get_plan_providers,
+    create_docker_image, explain_build_plan, generate_build_plan, generate_dev_env_flake,
+    get_archive_report, get_cache_report, get_provider_detections, get_provider_versions,
+    lock_build_plan,
     nixpacks::{
-        builder::docker::DockerBuilderOptions,
-        nix::pkg::Pkg,
+        builder::{
+            docker::{container_engine::ContainerEngine, DockerBuilderOptions},
+            ImageBuilderKind,
+        },
+        nix::{cache::NixCacheConfig, pkg::Pkg},
         plan::{
-            generator::GeneratePlanOptions,
+            diff::PlanDiff,
+            generator::{DetectionOutcome, GeneratePlanOptions},
             phase::{Phase, StartPhase},
+            secrets::Secret,
+            validate::get_plan_json_schema,
             BuildPlan,
         },
     },
+    prefetch_build_plan, prune_caches, read_or_generate_build_plan, remove_caches,
+    search_nix_packages, validate_config,
 };
-use std::{
-    collections::hash_map::DefaultHasher,
-    env,
-    hash::{Hash, Hasher},
-    ops::Deref,
-    string::ToString,
-};
+use std::{fs, ops::Deref, path::Path, string::ToString};
+
+/// The most Nix packages `nixpacks search` lists.
+const MAX_SEARCH_RESULTS: usize = 50;
 
 /// The build plan config file format to use.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -27,6 +34,42 @@ enum PlanFormat {
     Toml,
 }
 
+/// The image builder to use.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum BuilderKind {
+    /// Build with the Docker daemon
+    Docker,
+    /// Assemble an OCI image without Docker. Requires Nix
+    Oci,
+}
+
+/// The container engine that builds the Dockerfile.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Engine {
+    Docker,
+    Podman,
+    Buildah,
+}
+
+impl From<Engine> for ContainerEngine {
+    fn from(engine: Engine) -> Self {
+        match engine {
+            Engine::Docker => ContainerEngine::Docker,
+            Engine::Podman => ContainerEngine::Podman,
+            Engine::Buildah => ContainerEngine::Buildah,
+        }
+    }
+}
+
+impl From<BuilderKind> for ImageBuilderKind {
+    fn from(kind: BuilderKind) -> Self {
+        match kind {
+            BuilderKind::Docker => ImageBuilderKind::Docker,
+            BuilderKind::Oci => ImageBuilderKind::Oci,
+        }
+    }
+}
+
 /// Arguments passed to `nixpacks`.
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -67,9 +110,29 @@ struct Args {
     #[arg(long, short, global = true)]
     env: Vec<String>,
 
+    /// Provide a secret to the build phases without storing it in the image, e.g. `id=NPM_TOKEN,env=NPM_TOKEN`
+    #[arg(long, global = true)]
+    secret: Vec<String>,
+
+    /// Add a binary cache to substitute Nix packages from, e.g. `https://cache.example.com` or `file:///var/cache/nix`
+    #[arg(long, global = true)]
+    nix_substituter: Vec<String>,
+
+    /// Trust Nix packages signed with a public key, e.g. `cache.example.com-1:<key>`
+    #[arg(long, global = true)]
+    nix_trusted_public_key: Vec<String>,
+
     /// Path to config file
     #[arg(long, short, global = true)]
     config: Option<String>,
+
+    /// Fail on unknown fields and invalid phase dependencies in the config file
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Install Nix packages from fewer nixpkgs archives where the other archives have them too
+    #[arg(long, global = true)]
+    optimize_archives: bool,
 }
 
 /// The valid subcommands passed to `nixpacks`, and their arguments.
@@ -82,17 +145,96 @@ enum Commands {
         /// App source
         path: String,
 
-        /// Specify the output format of the build plan.
-        #[arg(short, long, value_enum, default_value = "json")]
-        format: PlanFormat,
+        /// Specify the output format of the build plan, or of the differences when using --diff.
+        #[arg(short, long, value_enum)]
+        format: Option<PlanFormat>,
+
+        /// Show the differences to another app source or saved plan instead of the plan itself
+        #[arg(long)]
+        diff: Option<String>,
     },
 
-    /// List all of the providers that will be used to build the app
+    /// List all of the providers that will be used to build the app, and explain why on stderr
     Detect {
         /// App source
         path: String,
     },
 
+    /// Show which source set each value of the build plan
+    Explain {
+        /// App source
+        path: String,
+    },
+
+    /// Check the config file of an app for unknown fields and invalid phase dependencies, and its Nix packages for typos
+    Validate {
+        /// App source
+        path: String,
+    },
+
+    /// List the language versions the providers can install, and search the Nix packages of a nixpkgs archive
+    Search {
+        /// Only list the versions and packages whose name contains this
+        query: Option<String>,
+
+        /// Only list the versions of this provider
+        #[arg(long)]
+        provider: Option<String>,
+
+        /// The nixpkgs archive to search the packages of. Defaults to the archive builds use
+        #[arg(long)]
+        archive: Option<String>,
+    },
+
+    /// Print the JSON Schema of nixpacks.toml and nixpacks.json config files
+    Schema,
+
+    /// Pin the nixpkgs archives, overlays and package versions of the build plan in nixpacks.lock
+    Lock {
+        /// App source
+        path: String,
+
+        /// Resolve every entry again, instead of only adding the missing ones
+        #[arg(long)]
+        update: bool,
+    },
+
+    /// Show which nixpkgs archives of the build plan could be collapsed with --optimize-archives
+    Archives {
+        /// App source
+        path: String,
+    },
+
+    /// Download the nixpkgs archives and overlays of an app into a directory, to use as NIXPACKS_NIXPKGS_MIRROR
+    Prefetch {
+        /// App source
+        path: String,
+
+        /// Directory to download the tarballs to
+        #[arg(short, long)]
+        out: String,
+    },
+
+    /// Write a flake.nix with the packages and variables of the build plan, for use with `nix develop`
+    DevEnv {
+        /// App source
+        path: String,
+
+        /// Directory to write flake.nix to. Defaults to the app source
+        #[arg(short, long)]
+        out: Option<String>,
+
+        /// Overwrite an existing flake.nix
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// List and remove the build caches of past builds
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+
     /// Build an app
     Build {
         /// App source
@@ -122,10 +264,14 @@ enum Commands {
         #[arg(long)]
         platform: Vec<String>,
 
-        /// Unique identifier to key cache by. Defaults to the current directory
+        /// Prefix for the cache keys of the phases, which are derived from their lockfiles and Nix packages. Defaults to a digest of the app's directory
         #[arg(long)]
         cache_key: Option<String>,
 
+        /// Print the cache key of each phase and what it is derived from, without building
+        #[arg(long)]
+        explain_cache: bool,
+
         /// Output Nixpacks related files to the current directory
         #[arg(long)]
         current_dir: bool,
@@ -138,6 +284,14 @@ enum Commands {
         #[arg(long)]
         incremental_cache_image: Option<String>,
 
+        /// Directory to hold the cached directories between builds, as tarballs or as an OCI layout with `oci:<dir>`
+        #[arg(long)]
+        incremental_cache_dir: Option<String>,
+
+        /// Largest cache directory to keep in the incremental cache, in megabytes after compression
+        #[arg(long)]
+        incremental_cache_max_size: Option<u64>,
+
         /// Image to consider as cache sources
         #[arg(long)]
         cache_from: Option<String>,
@@ -184,6 +338,43 @@ enum Commands {
         /// Display more info during build
         #[arg(long, short)]
         verbose: bool,
+
+        /// The image builder to use
+        #[arg(long, value_enum, default_value = "docker")]
+        builder: BuilderKind,
+
+        /// The container engine that builds the image with the docker builder
+        #[arg(long, value_enum, default_value = "docker")]
+        engine: Engine,
+
+        /// Push the Nix packages of the built image to a binary cache, e.g. `file:///var/cache/nix`
+        #[arg(long)]
+        push_nix_cache: Option<String>,
+
+        /// Secret key file to sign the pushed Nix packages with
+        #[arg(long)]
+        nix_cache_secret_key: Option<String>,
+    },
+}
+
+/// The subcommands of `nixpacks cache`.
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// List the cache mounts and incremental caches of past builds, with the apps and phases they belong to
+    Ls,
+
+    /// Remove the caches no build used within a duration
+    Prune {
+        /// How long a cache has to be unused to be removed, e.g. `12h`, `7d` or `2w`
+        #[arg(long)]
+        older_than: String,
+    },
+
+    /// Remove caches by their key
+    Rm {
+        /// Keys from `nixpacks cache ls`
+        #[arg(required = true)]
+        keys: Vec<String>,
     },
 }
 
@@ -221,6 +412,22 @@ async fn main() -> Result<()> {
         cli_plan.set_start_phase(start);
     }
 
+    if !args.nix_substituter.is_empty() || !args.nix_trusted_public_key.is_empty() {
+        cli_plan.nix_cache = Some(NixCacheConfig {
+            substituters: Some(args.nix_substituter),
+            trusted_public_keys: Some(args.nix_trusted_public_key),
+            push: None,
+        });
+    }
+
+    for secret in &args.secret {
+        let (id, secret) = Secret::parse(secret)?;
+        cli_plan
+            .secrets
+            .get_or_insert_with(Default::default)
+            .insert(id, secret);
+    }
+
     let json_plan = args.json_plan.map(BuildPlan::from_json).transpose()?;
 
     // Merge the CLI build plan with the json build plan
@@ -234,25 +441,198 @@ async fn main() -> Result<()> {
     let options = GeneratePlanOptions {
         plan: Some(cli_plan),
         config_file: args.config,
+        strict: args.strict,
+        check_packages: false,
+        optimize_archives: args.optimize_archives,
+        ignore_lock: false,
     };
 
     match args.command {
         // Produce a build plan for a project and print it to stdout.
-        Commands::Plan { path, format } => {
-            let plan = generate_build_plan(&path, env, &options)?;
+        Commands::Plan { path, format, diff } => {
+            if let Some(other) = diff {
+                let old = read_or_generate_build_plan(&path, env.clone(), &options)?;
+                let new = read_or_generate_build_plan(&other, env, &options)?;
+                let plan_diff = PlanDiff::new(&old, &new);
+
+                match format {
+                    None => print!("{plan_diff}"),
+                    Some(PlanFormat::Json) => {
+                        println!("{}", serde_json::to_string_pretty(&plan_diff)?);
+                    }
+                    Some(PlanFormat::Toml) => bail!("Plan differences can't be output as TOML"),
+                }
+            } else {
+                let plan = generate_build_plan(&path, env, &options)?;
 
-            let plan_s = match format {
-                PlanFormat::Json => plan.to_json()?,
-                PlanFormat::Toml => plan.to_toml()?,
-            };
+                let plan_s = match format.unwrap_or(PlanFormat::Json) {
+                    PlanFormat::Json => plan.to_json()?,
+                    PlanFormat::Toml => plan.to_toml()?,
+                };
 
-            println!("{plan_s}");
+                println!("{plan_s}");
+            }
         }
         // Detect which providers should be used to build a project and print them to stdout.
         Commands::Detect { path } => {
-            let providers = get_plan_providers(&path, env, &options)?;
-            println!("{}", providers.join(", "));
+            let selection = get_provider_detections(&path, env, &options)?;
+            println!("{}", selection.names.join(", "));
+
+            let (not_detected, detected): (Vec<_>, Vec<_>) = selection
+                .detections
+                .iter()
+                .partition(|detection| detection.outcome == DetectionOutcome::NotDetected);
+
+            for detection in detected {
+                eprintln!("{detection}");
+            }
+
+            if !not_detected.is_empty() {
+                let names = not_detected
+                    .iter()
+                    .map(|detection| detection.name.as_str())
+                    .collect::<Vec<_>>();
+                eprintln!("not detected: {}", names.join(", "));
+            }
+        }
+        // Print where every value of the build plan came from.
+        Commands::Explain { path } => {
+            let explanation = explain_build_plan(&path, env, &options)?;
+            print!("{explanation}");
+        }
+        // Check the config file in strict mode.
+        Commands::Validate { path } => match validate_config(&path, env, &options)? {
+            Some(config_file) => println!("{config_file} is valid"),
+            None => println!("No config file found"),
+        },
+        // List the provider versions and Nix packages matching the query.
+        Commands::Search {
+            query,
+            provider,
+            archive,
+        } => {
+            let matches = |name: &str| query.as_ref().map_or(true, |query| name.contains(query));
+            for (name, versions) in get_provider_versions() {
+                if provider
+                    .as_ref()
+                    .map_or(false, |provider| provider != &name)
+                {
+                    continue;
+                }
+
+                let versions = versions
+                    .iter()
+                    .filter(|v| matches(&name) || matches(&v.tool) || matches(&v.nix_pkg))
+                    .collect::<Vec<_>>();
+                if versions.is_empty() {
+                    continue;
+                }
+
+                println!("{name}");
+                for v in versions {
+                    let default = if v.is_default { " (default)" } else { "" };
+                    println!(
+                        "  {:<24} {:<12} {}",
+                        format!("{} {}{default}", v.tool, v.version),
+                        v.nix_pkg,
+                        v.archive
+                    );
+                }
+            }
+
+            if let Some(query) = &query {
+                let archive = archive.as_deref();
+                match search_nix_packages(query, archive, env)? {
+                    Some(packages) if packages.is_empty() => {
+                        println!("\nNo Nix packages match `{query}`");
+                    }
+                    Some(packages) => {
+                        println!("\nNix packages matching `{query}`:");
+                        for package in packages.iter().take(MAX_SEARCH_RESULTS) {
+                            println!("  {package}");
+                        }
+                        if packages.len() > MAX_SEARCH_RESULTS {
+                            println!("  and {} more", packages.len() - MAX_SEARCH_RESULTS);
+                        }
+                    }
+                    None => eprintln!(
+                        "Warning: Install Nix to search the packages of nixpkgs, they are cached after the first search"
+                    ),
+                }
+            }
+        }
+        // Print the JSON Schema of the config file.
+        Commands::Schema => {
+            println!("{}", get_plan_json_schema()?);
+        }
+        // Resolve the Nix dependencies and write them to nixpacks.lock.
+        Commands::Lock { path, update } => {
+            let lock = lock_build_plan(&path, env, &options, update)?;
+            println!(
+                "Locked {} nixpkgs archives and {} overlays in nixpacks.lock",
+                lock.archives.len(),
+                lock.overlays.len()
+            );
         }
+        // Report the nixpkgs archives that could be collapsed into another.
+        Commands::Archives { path } => {
+            let report = get_archive_report(&path, env, &options)?;
+            print!("{report}");
+        }
+        // Download everything the Nix dependencies need, for builds without internet access.
+        Commands::Prefetch { path, out } => {
+            let downloaded = prefetch_build_plan(&path, env, &options, Path::new(&out))?;
+            println!("Downloaded {} tarballs to {out}", downloaded.len());
+            for path in &downloaded {
+                println!("  {path}");
+            }
+
+            let dir = fs::canonicalize(&out)?;
+            println!(
+                "\nBuild with NIXPACKS_NIXPKGS_MIRROR=file://{} once the directory is on the build machine",
+                dir.display()
+            );
+        }
+        // Write a Nix flake with development shells matching the build plan.
+        Commands::DevEnv { path, out, force } => {
+            let flake = generate_dev_env_flake(&path, env, &options)?;
+
+            let flake_path = Path::new(out.as_ref().unwrap_or(&path)).join("flake.nix");
+            if flake_path.exists() && !force {
+                bail!(
+                    "{} already exists, use --force to overwrite it",
+                    flake_path.display()
+                );
+            }
+            fs::write(&flake_path, flake)
+                .with_context(|| format!("Writing {}", flake_path.display()))?;
+
+            println!(
+                "Wrote {}, run `nix develop` in its directory to enter the environment",
+                flake_path.display()
+            );
+        }
+        // List or remove the caches builds recorded.
+        Commands::Cache { command } => match command {
+            CacheCommands::Ls => print!("{}", get_cache_report()?),
+            CacheCommands::Prune { older_than } => {
+                let keys = prune_caches(&older_than)?;
+                if keys.is_empty() {
+                    println!("No caches are unused for {older_than}");
+                } else {
+                    println!("Removed {} caches:", keys.len());
+                    for key in &keys {
+                        println!("  {key}");
+                    }
+                }
+            }
+            CacheCommands::Rm { keys } => {
+                remove_caches(&keys)?;
+                for key in &keys {
+                    println!("Removed {key}");
+                }
+            }
+        },
         // Generate a Dockerfile and builds a container, using any specified build options.
         Commands::Build {
             path,
@@ -263,9 +643,12 @@ async fn main() -> Result<()> {
             label,
             platform,
             cache_key,
+            explain_cache,
             current_dir,
             no_cache,
             incremental_cache_image,
+            incremental_cache_dir,
+            incremental_cache_max_size,
             cache_from,
             docker_host,
             docker_tls_verify,
@@ -277,16 +660,13 @@ async fn main() -> Result<()> {
             cpu_quota,
             memory,
             verbose,
+            builder,
+            engine,
+            push_nix_cache,
+            nix_cache_secret_key,
         } => {
             let verbose = verbose || args.env.contains(&"NIXPACKS_VERBOSE=1".to_string());
 
-            // Default to absolute `path` of the source that is being built as the cache-key if not disabled
-            let cache_key = if !no_cache && cache_key.is_none() {
-                get_default_cache_key(&path)?
-            } else {
-                cache_key
-            };
-
             let build_options = &DockerBuilderOptions {
                 name,
                 tags: tag,
@@ -294,6 +674,7 @@ async fn main() -> Result<()> {
                 out_dir: out,
                 quiet: false,
                 cache_key,
+                explain_cache,
                 no_cache,
                 platform,
                 print_dockerfile: dockerfile,
@@ -306,10 +687,21 @@ async fn main() -> Result<()> {
                 docker_cert_path,
                 no_error_without_start,
                 incremental_cache_image,
+                incremental_cache_dir,
+                incremental_cache_max_size,
                 cpu_quota,
                 add_host,
                 memory,
                 verbose,
+                builder: builder.into(),
+                engine: engine.into(),
+                push_nix_cache,
+                nix_cache_secret_key,
+                ..Default::default()
+            };
+            let options = GeneratePlanOptions {
+                check_packages: true,
+                ..options
             };
             create_docker_image(&path, env, &options, build_options).await?;
         }
@@ -317,21 +709,3 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
-
-/// Creates a key for storing image layers in the Docker cache.
-fn get_default_cache_key(path: &str) -> Result<Option<String>> {
-    let current_dir = env::current_dir()?;
-    let source = current_dir.join(path).canonicalize();
-    if let Ok(source) = source {
-        let source_str = source.to_string_lossy().to_string();
-        let mut hasher = DefaultHasher::new();
-        source_str.hash(&mut hasher);
-
-        let encoded_source = base64::encode(hasher.finish().to_be_bytes())
-            .replace(|c: char| !c.is_alphanumeric(), "");
-
-        Ok(Some(encoded_source))
-    } else {
-        Ok(None)
-    }
-}
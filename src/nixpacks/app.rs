@@ -1,5 +1,5 @@
 use path_slash::PathBufExt;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::ffi::OsStr;
 use std::path::Path;
 use std::{env, fs, path::PathBuf};
@@ -55,6 +55,28 @@ impl App {
         Ok(directories)
     }
 
+    /// The paths relative to the app of the files matching any of the glob patterns, without those in `node_modules`.
+    ///
+    /// # Errors
+    /// Creating a Glob fails
+    pub fn find_relative_files(&self, patterns: &[&str]) -> Result<Vec<String>> {
+        let mut files = BTreeSet::new();
+        for pattern in patterns {
+            for file in self.find_files(pattern)? {
+                let relative = self.strip_source_path(&file)?;
+                if relative
+                    .components()
+                    .any(|component| component.as_os_str() == "node_modules")
+                {
+                    continue;
+                }
+                files.insert(relative.to_slash_lossy().into_owned());
+            }
+        }
+
+        Ok(files.into_iter().collect())
+    }
+
     /// Returns a list of directory paths matching a glob pattern
     ///
     /// # Errors
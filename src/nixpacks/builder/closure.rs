@@ -0,0 +1,90 @@
+use super::supervisor;
+use crate::nixpacks::plan::phase::StartPhase;
+
+/// Where the script that collects the closure is stored in the build image.
+pub const CLOSURE_DIR: &str = "/nixpacks/closure/";
+
+/// The directory of the build image the closure-only image is copied from.
+pub const CLOSURE_ROOT: &str = "/nixpacks-root";
+
+/// Sourced by every `bash -c` of the closure-only image, as it has no login profile.
+pub const CLOSURE_ENV_FILE: &str = "/etc/nixpacks-env";
+
+const CLOSURE_SCRIPT: &str = include_str!("closure.sh");
+
+/// The files to write to the closure directory, if the final image only gets the Nix closure of the start command.
+pub fn get_closure_files(start: &StartPhase) -> Vec<(String, String)> {
+    if start.is_closure_only() {
+        vec![("closure.sh".to_string(), CLOSURE_SCRIPT.to_string())]
+    } else {
+        Vec::new()
+    }
+}
+
+/// The command that copies the closure of the start command and the app files into `CLOSURE_ROOT`.
+pub fn get_closure_cmd(start: &StartPhase, app_dir: &str) -> Option<String> {
+    if !start.is_closure_only() {
+        return None;
+    }
+
+    // The start command is the supervisor when there are processes, which runs their commands
+    let commands = supervisor::get_start_cmd(start)
+        .iter()
+        .chain(
+            start
+                .processes
+                .iter()
+                .flat_map(|processes| processes.values()),
+        )
+        .map(|cmd| shell_quote(cmd))
+        .collect::<Vec<_>>();
+
+    Some(format!(
+        "bash -l {CLOSURE_DIR}closure.sh {CLOSURE_ROOT} {app_dir} {}",
+        commands.join(" ")
+    ))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_not_closure_only() {
+        let start = StartPhase::new("node index.js");
+        assert!(get_closure_files(&start).is_empty());
+        assert_eq!(get_closure_cmd(&start, "/app/"), None);
+    }
+
+    #[test]
+    fn test_closure_cmd() {
+        let mut start = StartPhase::new("node index.js");
+        start.run_closure_only();
+        assert_eq!(get_closure_files(&start).len(), 1);
+        assert_eq!(
+            get_closure_cmd(&start, "/app/"),
+            Some(
+                "bash -l /nixpacks/closure/closure.sh /nixpacks-root /app/ 'node index.js'"
+                    .to_string()
+            )
+        );
+
+        let mut start = StartPhase::from_processes(BTreeMap::from([(
+            "web".to_string(),
+            "echo 'hi'".to_string(),
+        )]));
+        start.run_closure_only();
+        assert_eq!(
+            get_closure_cmd(&start, "/app/"),
+            Some(
+                "bash -l /nixpacks/closure/closure.sh /nixpacks-root /app/ 'bash /nixpacks/supervisor/supervisor.sh never' 'echo '\\''hi'\\'''"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,107 @@
+#!/usr/bin/env bash
+# Copies the Nix store paths the start command needs into a root directory for a closure-only image.
+#
+# Usage: closure.sh <root> <app dir> <command>...
+#
+# The programs the commands run are looked up on the PATH, preferring the ones in the Nix store,
+# and the app files are scanned for the store paths they refer to, e.g. in shebangs or RPATHs.
+# The root gets the closure of those store paths, the store paths of LD_LIBRARY_PATH, /bin/bash
+# and /usr/bin/env, and /etc/nixpacks-env, which sets PATH and LD_LIBRARY_PATH for `bash -c`.
+
+set -euo pipefail
+# The commands are split into words, which must not be expanded as globs
+set -f
+
+root="$1"
+app_dir="$2"
+shift 2
+
+# The store path a file is in, if it is in the Nix store
+store_path() {
+  local file
+  file="$(readlink -f "$1")"
+  case "$file" in
+  /nix/store/*) echo "$file" | cut -d/ -f1-4 ;;
+  esac
+}
+
+# The first program with the name on the PATH that is in the Nix store
+nix_program() {
+  local program
+  for program in $(type -aP -- "$1" 2>/dev/null); do
+    if [ -n "$(store_path "$program")" ]; then
+      echo "$program"
+      return 0
+    fi
+  done
+  return 1
+}
+
+paths=()
+bin_dirs=()
+
+# bash runs the start command, and the supervisor uses dirname and sleep
+for word in bash env dirname sleep $*; do
+  program="$(nix_program "$word")" || continue
+  path="$(store_path "$program")"
+  paths+=("$path")
+  if [ -d "$path/bin" ]; then
+    bin_dirs+=("$path/bin")
+  fi
+done
+
+# Directories of the app on the PATH, e.g. node_modules/.bin
+IFS=: read -ra path_dirs <<<"$PATH"
+for dir in "${path_dirs[@]}"; do
+  case "$dir" in
+  "$app_dir"*) bin_dirs+=("$dir") ;;
+  esac
+done
+
+IFS=: read -ra lib_dirs <<<"${LD_LIBRARY_PATH:-}"
+for dir in "${lib_dirs[@]}"; do
+  if [ -e "$dir" ] && [ -n "$(store_path "$dir")" ]; then
+    paths+=("$(store_path "$dir")")
+  fi
+done
+
+while IFS= read -r path; do
+  if [ -e "$path" ]; then
+    paths+=("$path")
+  fi
+done < <(grep -raoh '/nix/store/[0-9a-z]\{32\}-[^/[:space:]"'"'"':;]*' "$app_dir" 2>/dev/null | sort -u || true)
+
+bash_path="$(nix_program bash)" || {
+  echo "bash is not installed with Nix, so the closure-only image can't run the start command" >&2
+  exit 1
+}
+
+mkdir -p "$root/nix/store" "$root/bin" "$root/usr/bin" "$root/etc/ssl/certs"
+mkdir -p -m 1777 "$root/tmp"
+
+nix-store --query --requisites "${paths[@]}" | sort -u >"$root/etc/nixpacks-closure"
+while IFS= read -r path; do
+  cp -a "$path" "$root/nix/store/"
+done <"$root/etc/nixpacks-closure"
+
+ln -s "$(readlink -f "$bash_path")" "$root/bin/bash"
+ln -s "$(readlink -f "$bash_path")" "$root/bin/sh"
+if env_path="$(nix_program env)"; then
+  ln -s "$(readlink -f "$env_path")" "$root/usr/bin/env"
+fi
+
+if [ -f /etc/ssl/certs/ca-certificates.crt ]; then
+  cp -L /etc/ssl/certs/ca-certificates.crt "$root/etc/ssl/certs/"
+fi
+
+{
+  echo "export PATH=\"$(
+    IFS=:
+    echo "${bin_dirs[*]}"
+  )\""
+  if [ -n "${LD_LIBRARY_PATH:-}" ]; then
+    echo "export LD_LIBRARY_PATH=\"$LD_LIBRARY_PATH\""
+  fi
+} >"$root/etc/nixpacks-env"
+
+echo "Copied $(wc -l <"$root/etc/nixpacks-closure") store paths ($(du -sh "$root/nix/store" | cut -f1)) into the closure-only image"
@@ -1,3 +1,187 @@
+use crate::nixpacks::{
+    app::App,
+    nix::NIXPKGS_ARCHIVE,
+    plan::{phase::Phases, BuildPlan},
+};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    path::Path,
+};
+
+/// Files whose contents decide what ends up in the cache directories of the phase that installs from them.
+const LOCKFILES: &[&str] = &[
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "bun.lock",
+    "deno.lock",
+    "poetry.lock",
+    "Pipfile.lock",
+    "pdm.lock",
+    "uv.lock",
+    "requirements.txt",
+    "Cargo.lock",
+    "go.sum",
+    "Gemfile.lock",
+    "composer.lock",
+    "mix.lock",
+    "pubspec.lock",
+    "Package.resolved",
+    "gradle.lockfile",
+    "packages.lock.json",
+];
+
+/// The number of hex characters of a digest kept in cache keys.
+const KEY_DIGEST_LEN: usize = 16;
+
+/// The cache key of a phase, and the inputs it is derived from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseCacheKey {
+    pub key: String,
+    /// What keeps the caches of different apps apart: the `--cache-key`, or the directory of the app.
+    pub scope: String,
+    /// The lockfiles the phase uses, with the sha256 of their contents.
+    pub lockfiles: BTreeMap<String, String>,
+    /// The nixpkgs archives of the phase and the phases it depends on.
+    pub archives: BTreeSet<String>,
+    /// The Nix packages of the phase and the phases it depends on, which pin the language versions.
+    pub nix_pkgs: BTreeSet<String>,
+}
+
+impl fmt::Display for PhaseCacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.key)?;
+        writeln!(f, "  app: {}", self.scope)?;
+        for (lockfile, digest) in &self.lockfiles {
+            writeln!(f, "  {lockfile}: sha256:{}", &digest[..KEY_DIGEST_LEN])?;
+        }
+        for archive in &self.archives {
+            writeln!(f, "  nixpkgs: {archive}")?;
+        }
+        if !self.nix_pkgs.is_empty() {
+            let nix_pkgs = self.nix_pkgs.iter().cloned().collect::<Vec<_>>();
+            writeln!(f, "  packages: {}", nix_pkgs.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The cache key of each phase with cache directories.
+///
+/// Keys change with the lockfiles the phase copies in, all of them when it copies the whole app, and with
+/// the Nix packages and archives of the phase and the phases it depends on. They start with the `--cache-key`,
+/// or a digest of the app's directory, so apps with the same lockfiles don't share caches.
+pub fn get_phase_cache_keys(
+    plan: &BuildPlan,
+    app: &App,
+    prefix: Option<&str>,
+) -> Result<BTreeMap<String, PhaseCacheKey>> {
+    let (scope, prefix) = if let Some(prefix) = prefix {
+        (prefix.to_string(), prefix.to_string())
+    } else {
+        let source = app.source.to_string_lossy().to_string();
+        let digest = format!("{:x}", Sha256::digest(&source));
+        (source, format!("app-{}", &digest[..KEY_DIGEST_LEN]))
+    };
+
+    let phases = plan.phases.clone().unwrap_or_default();
+    let mut keys = BTreeMap::new();
+    for (name, phase) in &phases {
+        if phase
+            .cache_directories
+            .clone()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            continue;
+        }
+
+        let files = phase
+            .only_include_files
+            .clone()
+            .unwrap_or_else(|| LOCKFILES.iter().map(ToString::to_string).collect());
+        let mut lockfiles = BTreeMap::new();
+        for file in files {
+            let file = file.trim_start_matches("./");
+            let is_lockfile = Path::new(file).file_name().map_or(false, |name| {
+                LOCKFILES.iter().any(|lockfile| name == *lockfile)
+            });
+            if is_lockfile && app.includes_file(file) {
+                let contents =
+                    fs::read(app.source.join(file)).with_context(|| format!("Reading {file}"))?;
+                lockfiles.insert(file.to_string(), format!("{:x}", Sha256::digest(contents)));
+            }
+        }
+
+        let mut archives = BTreeSet::new();
+        let mut nix_pkgs = BTreeSet::new();
+        for dependency in get_dependencies(&phases, name) {
+            let dependency = &phases[&dependency];
+            if !dependency.uses_nix() {
+                continue;
+            }
+            archives.insert(
+                dependency
+                    .nixpkgs_archive
+                    .clone()
+                    .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string()),
+            );
+            nix_pkgs.extend(
+                dependency
+                    .nix_pkgs
+                    .iter()
+                    .chain(dependency.nix_libs.iter())
+                    .flatten()
+                    .cloned(),
+            );
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(name);
+        for (lockfile, digest) in &lockfiles {
+            hasher.update(format!("\0{lockfile}\0{digest}"));
+        }
+        for value in archives.iter().chain(nix_pkgs.iter()) {
+            hasher.update(format!("\0{value}"));
+        }
+        let digest = format!("{:x}", hasher.finalize());
+
+        let key = format!("{prefix}-{name}-{}", &digest[..KEY_DIGEST_LEN]);
+        keys.insert(
+            name.clone(),
+            PhaseCacheKey {
+                key,
+                scope: scope.clone(),
+                lockfiles,
+                archives,
+                nix_pkgs,
+            },
+        );
+    }
+
+    Ok(keys)
+}
+
+/// The phase and every phase it depends on, directly or not.
+fn get_dependencies(phases: &Phases, name: &str) -> BTreeSet<String> {
+    let mut dependencies = BTreeSet::new();
+    let mut pending = vec![name.to_string()];
+    while let Some(name) = pending.pop() {
+        if !phases.contains_key(&name) || !dependencies.insert(name.clone()) {
+            continue;
+        }
+        pending.extend(phases[&name].depends_on.clone().unwrap_or_default());
+    }
+
+    dependencies
+}
+
 /// Remove space and period characters from the Docker build cache key.
 pub fn sanitize_cache_key(cache_key: &str) -> String {
     cache_key
@@ -13,6 +197,7 @@ pub fn sanitize_cache_key(cache_key: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nixpacks::{nix::pkg::Pkg, plan::phase::Phase};
 
     #[test]
     fn test_sanitizing_cache_key() {
@@ -24,4 +209,57 @@ mod tests {
         );
         assert_eq!(sanitize_cache_key("/.m2"), "/m2".to_string());
     }
+
+    fn get_plan(lockfile_phase_files: Option<Vec<String>>) -> BuildPlan {
+        let setup = Phase::setup(Some(vec![Pkg::new("nodejs_18")]));
+        let mut install = Phase::install(Some("npm ci".to_string()));
+        install.add_cache_directory("/root/.npm");
+        install.only_include_files = lockfile_phase_files;
+        let mut build = Phase::build(Some("npm run build".to_string()));
+        build.add_cache_directory("node_modules/.cache");
+
+        BuildPlan::new(&[setup, install, build], None)
+    }
+
+    #[test]
+    fn test_phase_cache_keys() -> Result<()> {
+        let app = App::new("./examples/node-npm")?;
+        let keys = get_phase_cache_keys(&get_plan(None), &app, None)?;
+
+        assert_eq!(keys.keys().collect::<Vec<_>>(), vec!["build", "install"]);
+        let install = &keys["install"];
+        assert!(install.key.starts_with("app-"));
+        assert!(install.key.contains("-install-"));
+        assert_eq!(install.scope, app.source.to_string_lossy());
+        assert_eq!(
+            install.lockfiles.keys().collect::<Vec<_>>(),
+            vec!["package-lock.json"]
+        );
+        assert_eq!(
+            install.archives,
+            BTreeSet::from([NIXPKGS_ARCHIVE.to_string()])
+        );
+        assert_eq!(install.nix_pkgs, BTreeSet::from(["nodejs_18".to_string()]));
+
+        // The key is the same for the same inputs, and a prefix replaces the app's directory
+        let prefixed = get_phase_cache_keys(&get_plan(None), &app, Some("my-app"))?;
+        let (_, digest) = install.key.rsplit_once('-').unwrap();
+        assert_eq!(prefixed["install"].key, format!("my-app-install-{digest}"));
+
+        // Another app with the same lockfiles and packages doesn't share the cache
+        let other = App::new("./examples/node-npm-old-lockfile")?;
+        let other_keys = get_phase_cache_keys(&get_plan(None), &other, None)?;
+        assert_ne!(other_keys["build"].key, keys["build"].key);
+
+        // Phases that only copy in other files don't depend on the lockfile
+        let keys = get_phase_cache_keys(
+            &get_plan(Some(vec!["package.json".to_string()])),
+            &app,
+            None,
+        )?;
+        assert!(keys["install"].lockfiles.is_empty());
+        assert_ne!(keys["install"].key, install.key);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,648 @@
+use super::{
+    cache_store::IncrementalCacheStore, container_engine::ContainerEngine,
+    file_server::format_size, utils::get_cache_mount_id, DockerBuilderOptions,
+};
+use crate::nixpacks::{environment::Environment, plan::BuildPlan};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const METADATA_FILE: &str = "caches.json";
+
+/// How long to wait for another Nixpacks process to finish updating the metadata.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Locks older than this are left over from a process that didn't finish, and are taken over.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(120);
+
+/// The caches a build used, so `nixpacks cache` can list and remove them later.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    /// The phase the cache directories belong to. Incremental caches hold the directories of every phase.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+    /// The sources of the apps built with the cache.
+    pub apps: BTreeSet<String>,
+    pub engine: ContainerEngine,
+    /// The ids of the BuildKit cache mounts, by the directory they are mounted at.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mounts: BTreeMap<String, String>,
+    /// The incremental cache store, and the directories saved to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<IncrementalCacheStore>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub dirs: BTreeSet<String>,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    pub last_used_at: u64,
+}
+
+/// The caches of past builds by their key, kept in a local file every build adds to.
+///
+/// Cache mounts are keyed by the cache key of their phase, incremental caches by their image or directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMetadata {
+    pub caches: BTreeMap<String, CacheEntry>,
+}
+
+impl CacheMetadata {
+    /// Where the metadata is kept, in the user's cache directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_home = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_home.join("nixpacks").join(METADATA_FILE))
+    }
+
+    /// Read the metadata, which is empty until the first build.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(CacheMetadata::default());
+        }
+
+        serde_json::from_slice(&fs::read(path)?)
+            .with_context(|| format!("Reading {}", path.display()))
+    }
+
+    /// Write the metadata to a temporary file first, so readers never see a partly written file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+        }
+        let temp_path = path.with_extension(format!("json.{}.tmp", process::id()));
+        fs::write(&temp_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Writing {}", temp_path.display()))?;
+        fs::rename(&temp_path, path).with_context(|| format!("Writing {}", path.display()))
+    }
+
+    /// Read, change and save the metadata while holding a lock, so builds running at the same time don't drop each
+    /// other's caches.
+    pub fn update<T, F>(path: &Path, change: F) -> Result<T>
+    where
+        F: FnOnce(&mut CacheMetadata) -> Result<T>,
+    {
+        let _lock = MetadataLock::acquire(path)?;
+        let mut metadata = CacheMetadata::load(path)?;
+        let result = change(&mut metadata)?;
+        metadata.save(path)?;
+
+        Ok(result)
+    }
+
+    /// Add the caches of the phases of a build of `app`, marking them as used at `now`.
+    pub fn record_build(
+        &mut self,
+        app: &str,
+        plan: &BuildPlan,
+        options: &DockerBuilderOptions,
+        env: &Environment,
+        now: u64,
+    ) -> Result<()> {
+        let caching = !options.no_cache && !env.is_config_variable_truthy("NO_CACHE");
+        let store = IncrementalCacheStore::new(
+            options.incremental_cache_image.as_ref(),
+            options.incremental_cache_dir.as_ref(),
+        )?;
+
+        for (name, phase) in &plan.phases.clone().unwrap_or_default() {
+            let dirs = phase.cache_directories.clone().unwrap_or_default();
+            if dirs.is_empty() {
+                continue;
+            }
+
+            if let Some(store) = &store {
+                let entry = self.get_entry(store.to_string(), options.engine, now);
+                entry.apps.insert(app.to_string());
+                entry.store = Some(store.clone());
+                entry
+                    .dirs
+                    .extend(dirs.iter().map(|dir| dir.replace('~', "/root")));
+            } else if caching {
+                let key = match options.cache_keys.get(name).or(options.cache_key.as_ref()) {
+                    Some(key) => key.clone(),
+                    None => continue,
+                };
+
+                let entry = self.get_entry(key.clone(), options.engine, now);
+                entry.apps.insert(app.to_string());
+                entry.phase = Some(name.clone());
+                for dir in &dirs {
+                    let (id, target) = get_cache_mount_id(&key, dir);
+                    entry.mounts.insert(target, id);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The keys of the caches last used before `time`.
+    pub fn unused_since(&self, time: u64) -> Vec<String> {
+        self.caches
+            .iter()
+            .filter(|(_, entry)| entry.last_used_at < time)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Delete the cache mounts and incremental caches of the keys, and forget them.
+    pub fn remove(&mut self, keys: &[String]) -> Result<()> {
+        for key in keys {
+            if !self.caches.contains_key(key) {
+                bail!("No cache has the key {key}, list them with `nixpacks cache ls`");
+            }
+        }
+
+        let uses_buildkit = keys.iter().any(|key| {
+            let entry = &self.caches[key];
+            !entry.mounts.is_empty() && entry.engine.is_buildkit()
+        });
+        let records = if uses_buildkit {
+            get_buildkit_records()?
+        } else {
+            Vec::new()
+        };
+
+        for key in keys {
+            let entry = self.caches.remove(key).unwrap();
+            if !entry.mounts.is_empty() {
+                if entry.engine.is_buildkit() {
+                    for record in get_mount_records(&records, &entry) {
+                        remove_buildkit_record(&record.id)?;
+                    }
+                } else {
+                    eprintln!(
+                        "Warning: The cache mounts of {key} are kept, run `buildah prune` to remove the build cache of {}",
+                        entry.engine.program()
+                    );
+                }
+            }
+
+            if let Some(store) = &entry.store {
+                store.remove(entry.engine)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_entry(&mut self, key: String, engine: ContainerEngine, now: u64) -> &mut CacheEntry {
+        let entry = self.caches.entry(key).or_insert_with(|| CacheEntry {
+            engine,
+            created_at: now,
+            ..Default::default()
+        });
+        entry.last_used_at = now;
+        entry
+    }
+}
+
+/// The caches of past builds, with their size where the engine or store can tell it.
+#[derive(Debug, Clone, Default)]
+pub struct CacheReport {
+    pub caches: Vec<(String, CacheEntry, Option<u64>)>,
+    pub now: u64,
+}
+
+impl CacheReport {
+    pub fn new(metadata: &CacheMetadata) -> Self {
+        let uses_buildkit = metadata
+            .caches
+            .values()
+            .any(|entry| !entry.mounts.is_empty() && entry.engine.is_buildkit());
+        // Sizes are left out when BuildKit can't be asked, e.g. because Docker isn't running
+        let records = if uses_buildkit {
+            get_buildkit_records().ok()
+        } else {
+            None
+        };
+
+        let caches = metadata
+            .caches
+            .iter()
+            .map(|(key, entry)| {
+                let size = match (&entry.store, &records) {
+                    (Some(store), _) => store.size(),
+                    (None, Some(records)) if entry.engine.is_buildkit() => Some(
+                        get_mount_records(records, entry)
+                            .iter()
+                            .map(|record| record.size)
+                            .sum(),
+                    ),
+                    (None, _) => None,
+                };
+                (key.clone(), entry.clone(), size)
+            })
+            .collect();
+
+        CacheReport { caches, now: now() }
+    }
+}
+
+impl fmt::Display for CacheReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.caches.is_empty() {
+            return writeln!(f, "No caches yet, they are listed once an app is built");
+        }
+
+        let key_width = self
+            .caches
+            .iter()
+            .map(|(key, _, _)| key.len())
+            .chain(["KEY".len()])
+            .max()
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "{:<key_width$}  {:<10}  {:>10}  {:<10}  APPS",
+            "KEY", "PHASE", "SIZE", "LAST USED"
+        )?;
+        for (key, entry, size) in &self.caches {
+            let phase = match (&entry.phase, &entry.store) {
+                (_, Some(_)) => "all",
+                (Some(phase), None) => phase.as_str(),
+                (None, None) => "-",
+            };
+            let size = size.map_or_else(|| "-".to_string(), format_size);
+            let apps = entry.apps.iter().cloned().collect::<Vec<_>>().join(", ");
+            writeln!(
+                f,
+                "{key:<key_width$}  {phase:<10}  {size:>10}  {:<10}  {apps}",
+                format_age(self.now.saturating_sub(entry.last_used_at))
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A lock file next to the metadata, removed when dropped.
+struct MetadataLock {
+    path: PathBuf,
+}
+
+impl MetadataLock {
+    fn acquire(metadata_path: &Path) -> Result<Self> {
+        if let Some(dir) = metadata_path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+        }
+        let path = metadata_path.with_extension("json.lock");
+
+        let started = SystemTime::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(MetadataLock { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let age = fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|modified| modified.elapsed().ok());
+                    if age.map_or(false, |age| age > STALE_LOCK_AGE) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed().unwrap_or_default() > LOCK_TIMEOUT {
+                        bail!(
+                            "Timed out waiting for {}, remove it if no other Nixpacks process is running",
+                            path.display()
+                        );
+                    }
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Creating {}", path.display()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MetadataLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// The units `--older-than` durations can use.
+pub const MAX_AGE_UNITS: [&str; 5] = ["s", "m", "h", "d", "w"];
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+/// A record of the BuildKit build cache, as listed by `docker buildx du --verbose`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BuildKitRecord {
+    id: String,
+    /// The id of the cache mount the record holds, without the namespace BuildKit prefixes it with.
+    mount_id: Option<String>,
+    size: u64,
+}
+
+fn get_buildkit_records() -> Result<Vec<BuildKitRecord>> {
+    let output = Command::new("docker")
+        .arg("buildx")
+        .arg("du")
+        .arg("--verbose")
+        .arg("--filter")
+        .arg("type=exec.cachemount")
+        .output()
+        .context("Listing the BuildKit cache mounts")?;
+    if !output.status.success() {
+        bail!(
+            "Listing the BuildKit cache mounts failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(parse_buildkit_records(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+fn remove_buildkit_record(id: &str) -> Result<()> {
+    let output = Command::new("docker")
+        .arg("builder")
+        .arg("prune")
+        .arg("--force")
+        .arg("--all")
+        .arg("--filter")
+        .arg(format!("id={id}"))
+        .output()
+        .context("Removing a BuildKit cache mount")?;
+    if !output.status.success() {
+        bail!(
+            "Removing BuildKit cache mount {id} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// The records of the cache mounts of an entry.
+fn get_mount_records<'a>(
+    records: &'a [BuildKitRecord],
+    entry: &CacheEntry,
+) -> Vec<&'a BuildKitRecord> {
+    records
+        .iter()
+        .filter(|record| {
+            record.mount_id.as_ref().map_or(false, |id| {
+                entry.mounts.values().any(|mount_id| mount_id == id)
+            })
+        })
+        .collect()
+}
+
+/// Parse the blocks of `Field: value` lines `docker buildx du --verbose` prints for each record.
+fn parse_buildkit_records(output: &str) -> Vec<BuildKitRecord> {
+    output
+        .split("\n\n")
+        .filter_map(|block| {
+            let fields = block
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .collect::<BTreeMap<_, _>>();
+
+            let id = fields.get("ID")?;
+            // e.g. `cached mount /root/.npm from exec /bin/sh -c npm ci with id "//install-0123-/root/npm"`
+            let mount_id = fields.get("Description").and_then(|description| {
+                let (_, id) = description.rsplit_once(" with id \"")?;
+                Some(id.trim_end_matches('"').trim_start_matches('/').to_string())
+            });
+            let size = fields.get("Size").map_or(0, |size| parse_size(size));
+
+            Some(BuildKitRecord {
+                id: (*id).to_string(),
+                mount_id,
+                size,
+            })
+        })
+        .collect()
+}
+
+/// Parse a size in the decimal units Docker prints, like `75.23MB`.
+fn parse_size(size: &str) -> u64 {
+    let unit_start = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let (amount, unit) = size.split_at(unit_start);
+    let multiplier = match unit.to_uppercase().as_str() {
+        "KB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "TB" => 1e12,
+        _ => 1.0,
+    };
+
+    amount
+        .trim()
+        .parse::<f64>()
+        .map_or(0, |amount| (amount * multiplier) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::plan::phase::Phase;
+    use tempdir::TempDir;
+
+    fn get_plan() -> BuildPlan {
+        let mut install = Phase::install(Some("npm ci".to_string()));
+        install.add_cache_directory("/root/.npm");
+        let mut build = Phase::build(Some("npm run build".to_string()));
+        build.add_cache_directory("node_modules/.cache");
+        let start = Phase::new("start");
+
+        BuildPlan::new(&[install, build, start], None)
+    }
+
+    fn get_options() -> DockerBuilderOptions {
+        DockerBuilderOptions {
+            cache_keys: BTreeMap::from([
+                ("install".to_string(), "install-0123".to_string()),
+                ("build".to_string(), "build-4567".to_string()),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record_build() -> Result<()> {
+        let mut metadata = CacheMetadata::default();
+        let env = Environment::default();
+        metadata.record_build("/src/app", &get_plan(), &get_options(), &env, 100)?;
+        metadata.record_build("/src/other", &get_plan(), &get_options(), &env, 200)?;
+
+        assert_eq!(
+            metadata.caches.keys().collect::<Vec<_>>(),
+            vec!["build-4567", "install-0123"]
+        );
+        let install = &metadata.caches["install-0123"];
+        assert_eq!(install.phase, Some("install".to_string()));
+        assert_eq!(
+            install.mounts,
+            BTreeMap::from([(
+                "/root/.npm".to_string(),
+                "install-0123-/root/npm".to_string()
+            )])
+        );
+        assert_eq!(
+            install.apps,
+            BTreeSet::from(["/src/app".to_string(), "/src/other".to_string()])
+        );
+        assert_eq!((install.created_at, install.last_used_at), (100, 200));
+        assert_eq!(
+            metadata.caches["build-4567"].mounts,
+            BTreeMap::from([(
+                "/app/node_modules/.cache".to_string(),
+                "build-4567-node_modules/cache".to_string()
+            )])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_updates() -> Result<()> {
+        let temp = TempDir::new("nixpacks-cache-metadata")?;
+        let path = temp.path().join("nixpacks").join(METADATA_FILE);
+
+        let builds = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    CacheMetadata::update(&path, |metadata| {
+                        metadata.record_build(
+                            &format!("/src/app-{i}"),
+                            &get_plan(),
+                            &get_options(),
+                            &Environment::default(),
+                            100,
+                        )
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for build in builds {
+            build.join().unwrap()?;
+        }
+
+        // Every build is kept, and the lock is released
+        let metadata = CacheMetadata::load(&path)?;
+        assert_eq!(metadata.caches.len(), 2);
+        assert!(metadata.caches.values().all(|entry| entry.apps.len() == 8));
+        assert!(!path.with_extension("json.lock").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_build_without_cache() -> Result<()> {
+        let mut metadata = CacheMetadata::default();
+        let options = DockerBuilderOptions {
+            no_cache: true,
+            ..get_options()
+        };
+        metadata.record_build(
+            "/src/app",
+            &get_plan(),
+            &options,
+            &Environment::default(),
+            100,
+        )?;
+
+        assert_eq!(metadata, CacheMetadata::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_incremental_cache() -> Result<()> {
+        let temp = TempDir::new("nixpacks-cache-metadata")?;
+        let store_dir = temp.path().join("incremental-cache");
+        fs::create_dir_all(store_dir.join("blobs"))?;
+        fs::write(store_dir.join("index.json"), "{}")?;
+
+        let options = DockerBuilderOptions {
+            incremental_cache_dir: Some(store_dir.display().to_string()),
+            ..get_options()
+        };
+        let mut metadata = CacheMetadata::default();
+        metadata.record_build(
+            "/src/app",
+            &get_plan(),
+            &options,
+            &Environment::default(),
+            100,
+        )?;
+
+        let key = store_dir.display().to_string();
+        let entry = &metadata.caches[&key];
+        assert!(entry.mounts.is_empty());
+        assert_eq!(
+            entry.dirs,
+            BTreeSet::from(["/root/.npm".to_string(), "node_modules/.cache".to_string()])
+        );
+        assert_eq!(CacheReport::new(&metadata).caches[0].2, Some(2));
+
+        // The store survives being written and read back
+        let path = temp.path().join("nixpacks").join(METADATA_FILE);
+        metadata.save(&path)?;
+        let mut metadata = CacheMetadata::load(&path)?;
+
+        assert!(metadata.remove(&["unknown".to_string()]).is_err());
+        assert_eq!(metadata.unused_since(100), Vec::<String>::new());
+        assert_eq!(metadata.unused_since(101), vec![key.clone()]);
+        metadata.remove(&[key])?;
+        assert!(metadata.caches.is_empty());
+        assert!(!store_dir.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_buildkit_records() {
+        let output = "ID:\t\tq8y0jxhm3ts7k1xrcn6g9blqv\nCreated at:\t2024-01-02 10:00:00.000 +0000 UTC\nMutable:\ttrue\nReclaimable:\ttrue\nShared:\t\tfalse\nSize:\t\t75.23MB\nDescription:\tcached mount /root/.npm from exec /bin/sh -c npm ci with id \"//install-0123-/root/npm\"\nUsage count:\t2\nLast used:\t3 days ago\nType:\t\texec.cachemount\n\nID:\t\t7zgnsv1p0wfl2d3k8q0dd5z8v\nSize:\t\t512B\nDescription:\tcached mount /app/.cache from exec /bin/sh -c make\nType:\t\texec.cachemount\n\nReclaimable:\t75.23MB\nTotal:\t\t75.23MB\n";
+
+        assert_eq!(
+            parse_buildkit_records(output),
+            vec![
+                BuildKitRecord {
+                    id: "q8y0jxhm3ts7k1xrcn6g9blqv".to_string(),
+                    mount_id: Some("install-0123-/root/npm".to_string()),
+                    size: 75_230_000,
+                },
+                BuildKitRecord {
+                    id: "7zgnsv1p0wfl2d3k8q0dd5z8v".to_string(),
+                    mount_id: None,
+                    size: 512,
+                }
+            ]
+        );
+    }
+}
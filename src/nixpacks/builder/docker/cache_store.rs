@@ -0,0 +1,403 @@
+use super::{container_engine::ContainerEngine, incremental_cache::IncrementalCache};
+use crate::nixpacks::builder::oci::layout::{ImageConfig, OciLayout};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use walkdir::WalkDir;
+
+/// The named build context cached directories are copied from, when they are stored on disk.
+pub const INCREMENTAL_CACHE_BUILD_CONTEXT: &str = "nixpacks-incremental-cache";
+
+/// The image name the cache is stored under in an OCI layout.
+const OCI_REF_NAME: &str = "nixpacks-incremental-cache";
+
+const OCI_PREFIX: &str = "oci:";
+const INDEX_FILE: &str = "index.json";
+
+/// Where the incremental cache is kept between builds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IncrementalCacheStore {
+    /// An image in a registry, which every cached directory is imported into.
+    Image(String),
+    /// A local directory of tarballs stored by their digest, with an index of the directories they hold.
+    Dir(PathBuf),
+    /// An OCI image layout on disk, with a layer for each cached directory.
+    Oci(PathBuf),
+}
+
+impl IncrementalCacheStore {
+    /// The store of `--incremental-cache-image` or `--incremental-cache-dir`, if either is used.
+    pub fn new(image: Option<&String>, dir: Option<&String>) -> Result<Option<Self>> {
+        match (image, dir) {
+            (Some(_), Some(_)) => {
+                bail!(
+                    "Only one of --incremental-cache-image and --incremental-cache-dir can be used"
+                )
+            }
+            (Some(image), None) => Ok(Some(IncrementalCacheStore::Image(image.clone()))),
+            (None, Some(dir)) => Ok(Some(IncrementalCacheStore::from_dir(dir))),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Directories prefixed with `oci:`, or that already are an OCI layout, store the cache as an OCI layout.
+    pub fn from_dir(dir: &str) -> Self {
+        if let Some(dir) = dir.strip_prefix(OCI_PREFIX) {
+            IncrementalCacheStore::Oci(PathBuf::from(dir))
+        } else if OciLayout::open(dir).is_some() {
+            IncrementalCacheStore::Oci(PathBuf::from(dir))
+        } else {
+            IncrementalCacheStore::Dir(PathBuf::from(dir))
+        }
+    }
+
+    /// The cached tarballs, by the directory of the build image they hold.
+    ///
+    /// Images in a registry can't be listed, so they have no entries.
+    pub fn entries(&self) -> Result<BTreeMap<String, PathBuf>> {
+        match self {
+            IncrementalCacheStore::Image(_) => Ok(BTreeMap::new()),
+            IncrementalCacheStore::Dir(root) => Ok(read_index(root)?
+                .into_iter()
+                .map(|(dir, digest)| (dir, blob_path(root, &digest)))
+                .collect()),
+            IncrementalCacheStore::Oci(root) => match OciLayout::open(root) {
+                Some(layout) => Ok(layout
+                    .read_layers()?
+                    .into_iter()
+                    .map(|layer| {
+                        let path = layout.blob_path(&layer.descriptor.digest);
+                        (layer.description, path)
+                    })
+                    .collect()),
+                None => Ok(BTreeMap::new()),
+            },
+        }
+    }
+
+    /// What the build copies cached directories from, the image or the named build context, if anything is cached yet.
+    pub fn get_copy_source(&self, engine: ContainerEngine) -> Result<Option<String>> {
+        match self {
+            IncrementalCacheStore::Image(image) => {
+                if IncrementalCache::is_image_exists(image, engine)? {
+                    Ok(Some(image.clone()))
+                } else {
+                    Ok(None)
+                }
+            }
+            IncrementalCacheStore::Dir(_) | IncrementalCacheStore::Oci(_) => {
+                if self.entries()?.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(INCREMENTAL_CACHE_BUILD_CONTEXT.to_string()))
+                }
+            }
+        }
+    }
+
+    /// Extract the cached directories into `dir`, which is passed to the build as a named build context.
+    ///
+    /// Returns whether anything was restored. Images are copied from directly, so nothing is restored for them.
+    pub fn restore(&self, dir: &Path) -> Result<bool> {
+        let entries = self.entries()?;
+        if entries.is_empty() {
+            return Ok(false);
+        }
+
+        fs::create_dir_all(dir).context("Creating incremental cache restore dir")?;
+        for (cache_dir, path) in entries {
+            tar::Archive::new(File::open(&path)?)
+                .unpack(dir)
+                .with_context(|| format!("Restoring {cache_dir} from the incremental cache"))?;
+        }
+
+        Ok(true)
+    }
+
+    /// Store the tarballs the build uploaded, replacing the cached versions of the same directories.
+    pub fn save(&self, uploads_dir: &Path, engine: ContainerEngine) -> Result<()> {
+        let uploads = get_uploads(uploads_dir)?;
+
+        match self {
+            IncrementalCacheStore::Image(tag) => {
+                // There are three options to create a filesystem image that contains only tar files
+                // #1 Use a Rust crate to create the image: 30+ seconds in a sample test, Also no clear winner Crate for creating OCI image
+                // #2 Create minimal Dockerfile: 6 seconds in a sample test
+                // #3 Use Docker import: Provide 3 seconds in a sample test
+                for path in uploads.values() {
+                    engine
+                        .import(path, tag)
+                        .context("Creating incremental cache image")?;
+                }
+            }
+            IncrementalCacheStore::Dir(root) => {
+                fs::create_dir_all(root.join("blobs").join("sha256"))
+                    .context("Creating incremental cache dir")?;
+
+                let mut index = read_index(root)?;
+                for (dir, path) in &uploads {
+                    let digest = write_blob(root, path)?;
+                    index.insert(dir.clone(), digest);
+                }
+                fs::write(root.join(INDEX_FILE), serde_json::to_string_pretty(&index)?)?;
+
+                remove_unreferenced_blobs(root, &index)?;
+            }
+            IncrementalCacheStore::Oci(root) => {
+                let layout = match OciLayout::open(root) {
+                    Some(layout) => layout,
+                    None => OciLayout::create(root)?,
+                };
+
+                let mut layers = layout
+                    .read_layers()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|layer| (layer.description.clone(), layer))
+                    .collect::<BTreeMap<_, _>>();
+                for (dir, path) in &uploads {
+                    layers.insert(dir.clone(), layout.write_tar_layer(dir, path)?);
+                }
+
+                let layers = layers.into_values().collect::<Vec<_>>();
+                layout.write_image(
+                    &ImageConfig::default(),
+                    &layers,
+                    &[OCI_REF_NAME.to_string()],
+                )?;
+                layout.remove_unreferenced_blobs()?;
+            }
+        }
+
+        println!("Incremental cache saved to {self}");
+        Ok(())
+    }
+
+    /// The size of the cached tarballs on disk. The size of images in a registry isn't known.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            IncrementalCacheStore::Image(_) => None,
+            IncrementalCacheStore::Dir(root) | IncrementalCacheStore::Oci(root) => Some(
+                WalkDir::new(root)
+                    .into_iter()
+                    .filter_map(Result::ok)
+                    .filter_map(|entry| entry.metadata().ok())
+                    .filter(fs::Metadata::is_file)
+                    .map(|metadata| metadata.len())
+                    .sum(),
+            ),
+        }
+    }
+
+    /// Delete the cached directories. Images are only removed locally, the registry keeps them.
+    pub fn remove(&self, engine: ContainerEngine) -> Result<()> {
+        match self {
+            IncrementalCacheStore::Image(image) => {
+                // The image may never have been pulled, which is fine
+                engine
+                    .remove_image_command(image)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .context("Removing incremental cache image")?;
+                eprintln!(
+                    "Warning: {image} is kept in its registry, delete it there to free its space"
+                );
+            }
+            IncrementalCacheStore::Dir(root) | IncrementalCacheStore::Oci(root) => {
+                if root.exists() {
+                    fs::remove_dir_all(root)
+                        .with_context(|| format!("Removing {}", root.display()))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for IncrementalCacheStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IncrementalCacheStore::Image(image) => write!(f, "{image}"),
+            IncrementalCacheStore::Dir(root) => write!(f, "{}", root.display()),
+            IncrementalCacheStore::Oci(root) => write!(f, "{OCI_PREFIX}{}", root.display()),
+        }
+    }
+}
+
+/// The uploaded tarballs, by the directory of the build image they hold.
+fn get_uploads(uploads_dir: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let mut uploads = BTreeMap::new();
+    for entry in fs::read_dir(uploads_dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if let Some(dir) = name.strip_suffix(".tar") {
+            uploads.insert(dir.replace("%2f", "/"), path);
+        }
+    }
+
+    Ok(uploads)
+}
+
+/// The digests of the tarballs in a cache directory, by the directory they hold.
+fn read_index(root: &Path) -> Result<BTreeMap<String, String>> {
+    let index_file = root.join(INDEX_FILE);
+    if !index_file.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    serde_json::from_slice(&fs::read(&index_file)?)
+        .with_context(|| format!("Reading {}", index_file.display()))
+}
+
+/// Copy a file into the blobs of a cache directory, returning its digest.
+fn write_blob(root: &Path, path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let digest = format!("sha256:{:x}", hasher.finalize());
+
+    let blob = blob_path(root, &digest);
+    if !blob.exists() {
+        fs::copy(path, &blob).context("Writing incremental cache blob")?;
+    }
+
+    Ok(digest)
+}
+
+fn remove_unreferenced_blobs(root: &Path, index: &BTreeMap<String, String>) -> Result<()> {
+    for entry in fs::read_dir(root.join("blobs").join("sha256"))? {
+        let entry = entry?;
+        let digest = format!("sha256:{}", entry.file_name().to_string_lossy());
+        if !index.values().any(|d| d == &digest) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn blob_path(root: &Path, digest: &str) -> PathBuf {
+    root.join("blobs")
+        .join("sha256")
+        .join(digest.trim_start_matches("sha256:"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// Write an upload the way the build does, a tarball of the directory named after its path.
+    fn upload(uploads_dir: &Path, dir: &str, file: &str, contents: &str) -> Result<()> {
+        let mut builder = tar::Builder::new(File::create(
+            uploads_dir.join(format!("{}.tar", dir.replace('/', "%2f"))),
+        )?);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(
+            &mut header,
+            format!("{}/{file}", dir.trim_start_matches('/')),
+            contents.as_bytes(),
+        )?;
+        builder.finish()?;
+
+        Ok(())
+    }
+
+    fn test_store(store: &IncrementalCacheStore, temp: &TempDir) -> Result<()> {
+        let uploads_dir = temp.path().join("uploads");
+        fs::create_dir_all(&uploads_dir)?;
+        assert_eq!(store.get_copy_source(ContainerEngine::Docker)?, None);
+
+        upload(&uploads_dir, "/root/.cache/pip", "wheel", "v1")?;
+        upload(&uploads_dir, "/root/.npm", "package", "v1")?;
+        store.save(&uploads_dir, ContainerEngine::Docker)?;
+        assert_eq!(
+            store.entries()?.keys().collect::<Vec<_>>(),
+            vec!["/root/.cache/pip", "/root/.npm"]
+        );
+        assert_eq!(
+            store.get_copy_source(ContainerEngine::Docker)?,
+            Some(INCREMENTAL_CACHE_BUILD_CONTEXT.to_string())
+        );
+
+        // A later build only replaces the directories it uploads
+        fs::remove_dir_all(&uploads_dir)?;
+        fs::create_dir_all(&uploads_dir)?;
+        upload(&uploads_dir, "/root/.npm", "package", "v2")?;
+        store.save(&uploads_dir, ContainerEngine::Docker)?;
+
+        let restore_dir = temp.path().join("restore");
+        assert!(store.restore(&restore_dir)?);
+        assert_eq!(
+            fs::read_to_string(restore_dir.join("root/.cache/pip/wheel"))?,
+            "v1"
+        );
+        assert_eq!(
+            fs::read_to_string(restore_dir.join("root/.npm/package"))?,
+            "v2"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_store() -> Result<()> {
+        let temp = TempDir::new("nixpacks-cache-store")?;
+        let root = temp.path().join("cache");
+        let store = IncrementalCacheStore::from_dir(&root.display().to_string());
+        assert_eq!(store, IncrementalCacheStore::Dir(root.clone()));
+
+        test_store(&store, &temp)?;
+        // The replaced tarball is removed
+        assert_eq!(fs::read_dir(root.join("blobs").join("sha256"))?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_oci_store() -> Result<()> {
+        let temp = TempDir::new("nixpacks-cache-store")?;
+        let root = temp.path().join("cache");
+        let store = IncrementalCacheStore::from_dir(&format!("oci:{}", root.display()));
+        assert_eq!(store, IncrementalCacheStore::Oci(root.clone()));
+
+        test_store(&store, &temp)?;
+        // Two layers, the config and the manifest
+        assert_eq!(fs::read_dir(root.join("blobs").join("sha256"))?.count(), 4);
+
+        // An existing layout is detected without the prefix
+        assert_eq!(
+            IncrementalCacheStore::from_dir(&root.display().to_string()),
+            IncrementalCacheStore::Oci(root)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_one_store() {
+        let image = "registry.example.com/cache".to_string();
+        let dir = "/tmp/cache".to_string();
+        assert!(IncrementalCacheStore::new(Some(&image), Some(&dir)).is_err());
+        assert_eq!(
+            IncrementalCacheStore::new(Some(&image), None).unwrap(),
+            Some(IncrementalCacheStore::Image(image))
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{path::Path, process::Command};
+
+/// The container engine that builds the generated Dockerfile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerEngine {
+    Docker,
+    Podman,
+    Buildah,
+}
+
+impl Default for ContainerEngine {
+    fn default() -> Self {
+        ContainerEngine::Docker
+    }
+}
+
+impl ContainerEngine {
+    /// The binary of the engine.
+    pub fn program(&self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman => "podman",
+            ContainerEngine::Buildah => "buildah",
+        }
+    }
+
+    /// The hostname build containers reach the host on, e.g. to upload the incremental cache.
+    pub fn host_gateway(&self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "host.docker.internal",
+            ContainerEngine::Podman | ContainerEngine::Buildah => "host.containers.internal",
+        }
+    }
+
+    /// The command to run the built image with. Buildah can't run images, so Podman is suggested instead.
+    pub fn run_program(&self) -> &'static str {
+        match self {
+            ContainerEngine::Docker => "docker",
+            ContainerEngine::Podman | ContainerEngine::Buildah => "podman",
+        }
+    }
+
+    /// The command that runs a container of a built image, without any arguments.
+    pub fn run_command(&self) -> Command {
+        let mut cmd = Command::new(self.run_program());
+        cmd.arg("run");
+        cmd
+    }
+
+    /// Whether the engine builds with BuildKit, which has a few options of its own.
+    pub fn is_buildkit(&self) -> bool {
+        *self == ContainerEngine::Docker
+    }
+
+    pub fn ensure_exists(&self) -> Result<()> {
+        if Command::new(self.program())
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            match self {
+                ContainerEngine::Docker => bail!(
+                    "Please install Docker to build the app https://docs.docker.com/engine/install/"
+                ),
+                ContainerEngine::Podman => {
+                    bail!("Please install Podman to build the app https://podman.io/getting-started/installation")
+                }
+                ContainerEngine::Buildah => {
+                    bail!("Please install Buildah to build the app https://github.com/containers/buildah/blob/main/install.md")
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The command that builds a Dockerfile, without any arguments.
+    pub fn build_command(&self) -> Command {
+        let mut cmd = Command::new(self.program());
+        match self {
+            ContainerEngine::Docker => {
+                // Enable BuildKit for all builds
+                cmd.env("DOCKER_BUILDKIT", "1");
+                cmd.arg("build");
+            }
+            ContainerEngine::Podman => {
+                cmd.arg("build");
+            }
+            ContainerEngine::Buildah => {
+                cmd.arg("bud");
+            }
+        }
+
+        cmd
+    }
+
+    /// The command that checks whether an image exists in its registry.
+    pub fn manifest_inspect_command(&self, image: &str) -> Command {
+        let mut cmd = Command::new(self.program());
+        cmd.arg("manifest").arg("inspect").arg(image);
+        cmd
+    }
+
+    /// The command that removes a local image.
+    pub fn remove_image_command(&self, image: &str) -> Command {
+        let mut cmd = Command::new(self.program());
+        match self {
+            ContainerEngine::Docker | ContainerEngine::Podman => cmd.arg("image").arg("rm"),
+            ContainerEngine::Buildah => cmd.arg("rmi"),
+        };
+        cmd.arg(image);
+        cmd
+    }
+
+    /// Create an image, or add a layer to it, from the contents of a tarball.
+    pub fn import(&self, file: &Path, tag: &str) -> Result<()> {
+        match self {
+            ContainerEngine::Docker | ContainerEngine::Podman => {
+                let mut import_cmd = Command::new(self.program());
+                import_cmd.arg("import").arg(file).arg(tag);
+                run(&mut import_cmd)
+            }
+            // Buildah has no import command, so the tarball is added to an empty container instead
+            ContainerEngine::Buildah => {
+                let output = Command::new("buildah")
+                    .arg("from")
+                    .arg("scratch")
+                    .output()
+                    .context("Creating container from scratch")?;
+                if !output.status.success() {
+                    bail!("Creating container from scratch failed")
+                }
+                let container = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+                run(Command::new("buildah")
+                    .arg("add")
+                    .arg(&container)
+                    .arg(file)
+                    .arg("/"))?;
+                run(Command::new("buildah")
+                    .arg("commit")
+                    .arg("--rm")
+                    .arg(&container)
+                    .arg(tag))
+            }
+        }
+    }
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let result = cmd.spawn()?.wait().context("Running container engine")?;
+    if !result.success() {
+        bail!("Running {} failed", cmd.get_program().to_string_lossy())
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_command() {
+        let args = |engine: ContainerEngine| {
+            let cmd = engine.build_command();
+            (
+                cmd.get_program().to_string_lossy().to_string(),
+                cmd.get_args()
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(
+            args(ContainerEngine::Docker),
+            ("docker".to_string(), vec!["build".to_string()])
+        );
+        assert_eq!(
+            args(ContainerEngine::Podman),
+            ("podman".to_string(), vec!["build".to_string()])
+        );
+        assert_eq!(
+            args(ContainerEngine::Buildah),
+            ("buildah".to_string(), vec!["bud".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_host_gateway() {
+        assert_eq!(
+            ContainerEngine::Docker.host_gateway(),
+            "host.docker.internal"
+        );
+        assert_eq!(
+            ContainerEngine::Podman.host_gateway(),
+            "host.containers.internal"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use super::{dockerfile_generation::DockerfileGenerator, DockerBuilderOptions, ImageBuilder};
 use crate::nixpacks::{
     builder::docker::{
+        cache_metadata::{self, CacheMetadata},
+        cache_store::{IncrementalCacheStore, INCREMENTAL_CACHE_BUILD_CONTEXT},
         dockerfile_generation::OutputDir,
         file_server::FileServer,
         incremental_cache::{IncrementalCache, IncrementalCacheDirs},
@@ -8,6 +10,7 @@ use crate::nixpacks::{
     environment::Environment,
     files,
     logger::Logger,
+    nix::cache::{local_store_dir, push_store_url, PUSH_SECRET_KEY_PATH},
     plan::BuildPlan,
 };
 use anyhow::{bail, Context, Ok, Result};
@@ -60,21 +63,30 @@ impl ImageBuilder for DockerImageBuilder {
         let name = self.options.name.clone().unwrap_or_else(|| id.to_string());
         output.ensure_output_exists()?;
 
-        let incremental_cache = IncrementalCache::default();
+        let incremental_cache = IncrementalCacheStore::new(
+            self.options.incremental_cache_image.as_ref(),
+            self.options.incremental_cache_dir.as_ref(),
+        )?
+        .map(IncrementalCache::new);
         let incremental_cache_dirs = IncrementalCacheDirs::new(&output);
 
-        let file_server_config = if self.options.incremental_cache_image.is_some() {
+        let file_server_config = if let Some(incremental_cache) = &incremental_cache {
             incremental_cache_dirs.create()?;
+            incremental_cache.restore(&incremental_cache_dirs)?;
 
             let file_server = FileServer {};
-            let config = file_server.start(&incremental_cache_dirs);
+            let config = file_server.start(
+                &incremental_cache_dirs,
+                self.options.engine.host_gateway(),
+                self.options.incremental_cache_max_size,
+            );
             Some(config)
         } else {
             None
         };
 
         let dockerfile = plan
-            .generate_dockerfile(&self.options, env, &output, file_server_config)
+            .generate_dockerfile(&self.options, env, &output, file_server_config.clone())
             .context("Generating Dockerfile for plan")?;
 
         // If printing the Dockerfile, don't write anything to disk
@@ -89,7 +101,7 @@ impl ImageBuilder for DockerImageBuilder {
         plan.write_supporting_files(&self.options, env, &output)
             .context("Writing supporting files")?;
 
-        let mut docker_build_cmd = self.get_docker_build_cmd(plan, name.as_str(), &output)?;
+        let mut docker_build_cmd = self.get_docker_build_cmd(plan, env, name.as_str(), &output)?;
 
         if self.options.out_dir.is_some() {
             let command_path = output.get_absolute_path("build.sh");
@@ -103,20 +115,38 @@ impl ImageBuilder for DockerImageBuilder {
             // Execute docker build
             let build_result = docker_build_cmd.spawn()?.wait().context("Building image")?;
             if !build_result.success() {
-                bail!("Docker build failed")
+                bail!("{} build failed", self.options.engine.program())
             }
 
             self.logger.log_section("Successfully Built!");
             println!("\nRun:");
-            println!("  docker run -it {name}");
+            println!("  {} run -it {name}", self.options.engine.run_program());
+
+            if let Some(url) = self
+                .options
+                .push_nix_cache
+                .clone()
+                .or_else(|| plan.nix_cache.clone().and_then(|cache| cache.push))
+            {
+                self.push_nix_closure(plan, &name, &url)?;
+            }
 
-            if self.options.incremental_cache_image.is_some() {
-                incremental_cache.create_image(
-                    &incremental_cache_dirs,
-                    &self.options.incremental_cache_image.clone().unwrap(),
-                )?;
+            if let Some(incremental_cache) = &incremental_cache {
+                if let Some(config) = &file_server_config {
+                    let report = config.get_upload_report();
+                    if !report.is_empty() {
+                        println!("\nIncremental cache uploads:");
+                        for line in report {
+                            println!("  {line}");
+                        }
+                    }
+                }
+
+                incremental_cache.save(&incremental_cache_dirs, self.options.engine)?;
             }
 
+            self.record_caches(app_src, plan, env);
+
             if output.is_temp {
                 remove_dir_all(output.root)?;
             }
@@ -138,23 +168,21 @@ impl DockerImageBuilder {
     fn get_docker_build_cmd(
         &self,
         plan: &BuildPlan,
+        env: &Environment,
         name: &str,
         output: &OutputDir,
     ) -> Result<Command> {
-        let mut docker_build_cmd = Command::new("docker");
-
-        // Enable BuildKit for all builds
-        docker_build_cmd.env("DOCKER_BUILDKIT", "1");
+        let engine = self.options.engine;
+        let mut docker_build_cmd = engine.build_command();
 
         docker_build_cmd
-            .arg("build")
             .arg(&output.root)
             .arg("-f")
             .arg(output.get_absolute_path("Dockerfile"))
             .arg("-t")
             .arg(name);
 
-        if self.options.verbose {
+        if self.options.verbose && engine.is_buildkit() {
             docker_build_cmd.arg("--progress=plain");
         }
 
@@ -185,7 +213,11 @@ impl DockerImageBuilder {
         }
 
         if let Some(value) = &self.options.docker_host {
-            docker_build_cmd.env("DOCKER_HOST", value);
+            if engine.is_buildkit() {
+                docker_build_cmd.env("DOCKER_HOST", value);
+            } else {
+                docker_build_cmd.env("CONTAINER_HOST", value);
+            }
         }
 
         if let Some(value) = &self.options.docker_tls_verify {
@@ -196,7 +228,7 @@ impl DockerImageBuilder {
             docker_build_cmd.env("DOCKER_CERT_PATH", value);
         }
 
-        if self.options.inline_cache {
+        if self.options.inline_cache && engine.is_buildkit() {
             docker_build_cmd
                 .arg("--build-arg")
                 .arg("BUILDKIT_INLINE_CACHE=1");
@@ -209,6 +241,35 @@ impl DockerImageBuilder {
                 .arg(format!("{name}={value}"));
         }
 
+        for (context, dir) in plan.get_local_build_contexts() {
+            docker_build_cmd
+                .arg("--build-context")
+                .arg(format!("{context}={}", dir.display()));
+        }
+
+        // Cached directories stored on disk are restored here before the build
+        let restore_dir = IncrementalCacheDirs::new(output).restore_dir;
+        if restore_dir.exists() {
+            docker_build_cmd.arg("--build-context").arg(format!(
+                "{INCREMENTAL_CACHE_BUILD_CONTEXT}={}",
+                restore_dir.display()
+            ));
+        }
+
+        // Secrets are read by the engine, from its own environment when they come from variables
+        for (id, secret) in &plan.secrets.clone().unwrap_or_default() {
+            docker_build_cmd
+                .arg("--secret")
+                .arg(secret.get_build_arg(id));
+
+            if let Some(value) = secret
+                .get_env_name(id)
+                .and_then(|name| env.get_variable(&name))
+            {
+                docker_build_cmd.env(secret.get_env_name(id).unwrap(), value);
+            }
+        }
+
         // Add user defined tags and labels to the image
         for t in self.options.tags.clone() {
             docker_build_cmd.arg("-t").arg(t);
@@ -230,6 +291,73 @@ impl DockerImageBuilder {
         Ok(docker_build_cmd)
     }
 
+    /// Pushes the Nix packages installed in the built image to a binary cache, so later builds can substitute them.
+    fn push_nix_closure(&self, plan: &BuildPlan, name: &str, url: &str) -> Result<()> {
+        let start = plan.start_phase.clone().unwrap_or_default();
+        if start.run_image.is_some() {
+            eprintln!(
+                "Warning: Not pushing Nix packages to {url}, the image is based on a run image without them"
+            );
+            return Ok(());
+        }
+
+        self.logger
+            .log_section(&format!("Pushing Nix packages to {url}"));
+
+        let mut push_cmd = self.options.engine.run_command();
+        push_cmd.arg("--rm").arg("--user").arg("root");
+        if let Some(dir) = local_store_dir(url) {
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Creating binary cache {}", dir.display()))?;
+            push_cmd
+                .arg("-v")
+                .arg(format!("{0}:{0}", fs::canonicalize(&dir)?.display()));
+        }
+        if let Some(key) = &self.options.nix_cache_secret_key {
+            push_cmd.arg("-v").arg(format!(
+                "{}:{PUSH_SECRET_KEY_PATH}:ro",
+                fs::canonicalize(key)
+                    .with_context(|| format!("Reading secret key {key}"))?
+                    .display()
+            ));
+        }
+
+        let store_url = push_store_url(
+            url,
+            self.options
+                .nix_cache_secret_key
+                .as_ref()
+                .map(|_| PUSH_SECRET_KEY_PATH),
+        );
+        push_cmd.arg(name).arg(format!(
+            "nix copy --to '{store_url}' $(readlink -f /root/.nix-profile)"
+        ));
+
+        let result = push_cmd.spawn()?.wait().context("Pushing Nix packages")?;
+        if !result.success() {
+            bail!("Pushing Nix packages to {url} failed")
+        }
+
+        Ok(())
+    }
+
+    /// Adds the caches of the build to the metadata `nixpacks cache` lists, which only warns when it fails.
+    fn record_caches(&self, app_src: &str, plan: &BuildPlan, env: &Environment) {
+        let path = match CacheMetadata::default_path() {
+            Some(path) => path,
+            None => return,
+        };
+        let app = fs::canonicalize(app_src)
+            .map_or_else(|_| app_src.to_string(), |path| path.display().to_string());
+
+        let result = CacheMetadata::update(&path, |metadata| {
+            metadata.record_build(&app, plan, &self.options, env, cache_metadata::now())
+        });
+        if let Err(e) = result {
+            eprintln!("Warning: Recording the caches of the build failed: {e}");
+        }
+    }
+
     /// Copies project files to temporary output dir, if that option was used.
     fn write_app(&self, app_src: &str, output: &OutputDir) -> Result<()> {
         if output.is_temp {
@@ -1,17 +1,27 @@
 use super::{
-    file_server::FileServerConfig, incremental_cache::IncrementalCache, utils, DockerBuilderOptions,
+    cache_store::IncrementalCacheStore,
+    file_server::FileServerConfig,
+    incremental_cache::{IncrementalCache, INCREMENTAL_CACHE_UPLOAD_DIR},
+    utils, DockerBuilderOptions,
 };
 use crate::nixpacks::{
     app,
+    builder::{
+        closure::{self, CLOSURE_DIR, CLOSURE_ENV_FILE, CLOSURE_ROOT},
+        supervisor::{self, SUPERVISOR_DIR},
+    },
     environment::Environment,
     images::DEFAULT_BASE_IMAGE,
-    nix::{create_nix_expressions_for_phases, nix_file_names_for_phases, setup_files_for_phases},
+    nix::{
+        create_nix_expressions_for_phases, mirror::NixMirror, nix_file_names_for_phases,
+        setup_files_for_phases,
+    },
     plan::{
         phase::{Phase, StartPhase},
         BuildPlan,
     },
 };
-use anyhow::{Context, Ok, Result};
+use anyhow::{bail, Context, Ok, Result};
 use indoc::formatdoc;
 use path_slash::PathBufExt;
 use std::{
@@ -23,6 +33,9 @@ use std::{
 const NIXPACKS_OUTPUT_DIR: &str = ".nixpacks";
 pub const APP_DIR: &str = "/app/";
 
+/// The build context a local nixpkgs mirror is passed to the build in.
+const NIX_MIRROR_BUILD_CONTEXT: &str = "nixpacks-mirror";
+
 /// Represents a directory into which project files and generated assets like Dockerfiles are written.
 #[derive(Debug, Clone)]
 pub struct OutputDir {
@@ -115,7 +128,26 @@ impl DockerfileGenerator for BuildPlan {
 
         let nix_file_names = nix_file_names_for_phases(&plan.phases.clone().unwrap_or_default());
 
+        // Local mirrors and binary caches are mounted from the build contexts the builder adds for them
+        let local_mounts = plan
+            .get_local_build_contexts()
+            .iter()
+            .map(|(name, dir)| format!("--mount=type=bind,from={name},target={} ", dir.display()))
+            .collect::<Vec<_>>()
+            .concat();
+
         let mut nix_install_cmds: Vec<String> = Vec::new();
+        let nix_conf_lines = plan.nix_cache.clone().unwrap_or_default().nix_conf_lines();
+        if !nix_conf_lines.is_empty() {
+            nix_install_cmds.push(format!(
+                "RUN printf '%s\\n' {} >> /etc/nix/nix.conf",
+                nix_conf_lines
+                    .iter()
+                    .map(|line| format!("'{line}'"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
         for name in nix_file_names {
             let nix_file = output.get_relative_path(name);
 
@@ -124,7 +156,7 @@ impl DockerfileGenerator for BuildPlan {
                 .context("Failed to convert nix file path to slash path.")?;
 
             nix_install_cmds.push(format!(
-                "COPY {nix_file_path} {nix_file_path}\nRUN nix-env -if {nix_file_path} && nix-collect-garbage -d"
+                "COPY {nix_file_path} {nix_file_path}\nRUN {local_mounts}nix-env -if {nix_file_path} && nix-collect-garbage -d"
             ));
         }
         let nix_install_cmds = nix_install_cmds.join("\n");
@@ -171,11 +203,27 @@ impl DockerfileGenerator for BuildPlan {
             format!("COPY {rel_assets_slash_path} {}", app::ASSETS_DIR)
         };
 
+        let upload_copy_cmd = if IncrementalCache::get_upload_files(options).is_empty() {
+            String::new()
+        } else {
+            let rel_upload_path = output.get_relative_path("incremental-cache-upload");
+            let rel_upload_path = rel_upload_path
+                .to_slash()
+                .context("Failed to convert upload script path to slash path.")?;
+            format!("COPY {rel_upload_path} {INCREMENTAL_CACHE_UPLOAD_DIR}")
+        };
+        let assets_copy_cmd = [assets_copy_cmd, upload_copy_cmd]
+            .into_iter()
+            .filter(|cmd| !cmd.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+
         let phases = plan.get_sorted_phases()?;
 
         let mut dockerfile_phases = phases
             .into_iter()
-            .map(|phase| {
+            .map(|mut phase| {
+                phase.secrets = Some(plan.get_phase_secrets(&phase)?);
                 let phase_dockerfile = phase
                     .generate_dockerfile(options, env, output, file_server_config.clone())
                     .context(format!(
@@ -205,6 +253,14 @@ impl DockerfileGenerator for BuildPlan {
 
         dockerfile_phases.extend(profile_dockerfile.iter().cloned());
 
+        // Install phases may only copy the dependency manifests, and run images copy the app from this stage
+        let start_phase = plan.start_phase.clone().unwrap_or_default();
+        if (start_phase.run_image.is_some() || start_phase.is_closure_only())
+            && plan.copies_some_files()?
+        {
+            dockerfile_phases.push(format!("COPY . {APP_DIR}"));
+        }
+
         let dockerfile_phases_str = dockerfile_phases.join("\n");
 
         let start_phase_str = plan
@@ -256,8 +312,11 @@ impl DockerfileGenerator for BuildPlan {
     ) -> Result<()> {
         self.write_assets(self, output).context("Writing assets")?;
 
-        let nix_expressions =
-            create_nix_expressions_for_phases(&self.phases.clone().unwrap_or_default());
+        let nix_expressions = create_nix_expressions_for_phases(
+            &self.phases.clone().unwrap_or_default(),
+            self.nix_sources(),
+            APP_DIR,
+        );
 
         for (name, nix_expression) in nix_expressions {
             let nix_path = output.get_absolute_path(name);
@@ -267,17 +326,52 @@ impl DockerfileGenerator for BuildPlan {
                 .context("Unable to write Nix expression")?;
         }
 
+        let upload_files = IncrementalCache::get_upload_files(options);
+        if !upload_files.is_empty() {
+            let upload_path = output.get_absolute_path("incremental-cache-upload");
+            fs::create_dir_all(&upload_path).context("Creating incremental cache upload folder")?;
+            for (name, contents) in upload_files {
+                fs::write(upload_path.join(&name), contents)
+                    .context(format!("Writing incremental cache upload file {name}"))?;
+            }
+        }
+
         for phase in self.get_sorted_phases()? {
             phase
                 .write_supporting_files(options, env, output)
                 .context(format!("Writing files for phase {}", phase.get_name()))?;
         }
 
+        if let Some(start) = &self.start_phase {
+            start
+                .write_supporting_files(options, env, output)
+                .context("Writing files for start phase")?;
+        }
+
         Ok(())
     }
 }
 
 impl BuildPlan {
+    /// The local directories the Nix install reads from, by the name of the build context they are passed in.
+    pub fn get_local_build_contexts(&self) -> Vec<(String, PathBuf)> {
+        let mut contexts = Vec::new();
+        if let Some(dir) = self.nix_mirror.as_ref().and_then(NixMirror::local_dir) {
+            contexts.push((NIX_MIRROR_BUILD_CONTEXT.to_string(), dir));
+        }
+
+        let substituters = self
+            .nix_cache
+            .clone()
+            .unwrap_or_default()
+            .local_substituters();
+        for (i, dir) in substituters.into_iter().enumerate() {
+            contexts.push((format!("nixpacks-substituter-{i}"), dir));
+        }
+
+        contexts
+    }
+
     /// Copies the plan's static assets to the output directory.
     fn write_assets(&self, plan: &BuildPlan, output: &OutputDir) -> Result<()> {
         if let Some(assets) = &plan.static_assets {
@@ -312,20 +406,69 @@ impl BuildPlan {
     }
 }
 
+/// The `EXPOSE`, `HEALTHCHECK` and `STOPSIGNAL` instructions of the start phase.
+fn get_runtime_instructions(start: &StartPhase) -> Result<String> {
+    let mut instructions = Vec::new();
+
+    if let Some(ports) = &start.ports {
+        if !ports.is_empty() {
+            let ports = ports.iter().map(ToString::to_string).collect::<Vec<_>>();
+            instructions.push(format!("EXPOSE {}", ports.join(" ")));
+        }
+    }
+
+    if let Some(healthcheck) = &start.healthcheck {
+        let cmd = match &healthcheck.cmd {
+            Some(cmd) => cmd.replace('\n', " "),
+            None => bail!("The healthcheck of the start phase is missing a command"),
+        };
+
+        let mut options = Vec::new();
+        if let Some(interval) = &healthcheck.interval {
+            options.push(format!("--interval={interval}"));
+        }
+        if let Some(timeout) = &healthcheck.timeout {
+            options.push(format!("--timeout={timeout}"));
+        }
+        if let Some(retries) = healthcheck.retries {
+            options.push(format!("--retries={retries}"));
+        }
+        options.push(format!("CMD {cmd}"));
+
+        instructions.push(format!("HEALTHCHECK {}", options.join(" ")));
+    }
+
+    if let Some(stop_signal) = &start.stop_signal {
+        instructions.push(format!("STOPSIGNAL {stop_signal}"));
+    }
+
+    Ok(instructions.join("\n"))
+}
+
 impl DockerfileGenerator for StartPhase {
     /// Write the StartPhase data to the Dockerfile.
     fn generate_dockerfile(
         &self,
         _options: &DockerBuilderOptions,
         _env: &Environment,
-        _output: &OutputDir,
+        output: &OutputDir,
         _file_server_config: Option<FileServerConfig>,
     ) -> Result<String> {
-        let start_cmd = match &self.cmd {
-            Some(cmd) => utils::get_exec_command(cmd),
+        let start_cmd = match supervisor::get_start_cmd(self) {
+            Some(cmd) => utils::get_exec_command(&cmd),
             None => String::new(),
         };
 
+        let supervisor_copy_cmd = if supervisor::get_supervisor_files(self).is_empty() {
+            String::new()
+        } else {
+            let rel_supervisor_path = output.get_relative_path("supervisor");
+            let rel_supervisor_path = rel_supervisor_path
+                .to_slash()
+                .context("Failed to convert supervisor path to slash path.")?;
+            format!("COPY {rel_supervisor_path} {SUPERVISOR_DIR}")
+        };
+
         let user_str = match &self.user {
             Some(user) => formatdoc! {"
                 RUN useradd -m -s /bin/bash {user}
@@ -334,6 +477,48 @@ impl DockerfileGenerator for StartPhase {
             None => String::new(),
         };
 
+        let runtime_str = get_runtime_instructions(self)?;
+
+        if self.is_closure_only() {
+            if self.user.is_some() {
+                bail!("The start phase can't set a user in a closure-only image");
+            }
+
+            let rel_closure_path = output.get_relative_path("closure");
+            let rel_closure_path = rel_closure_path
+                .to_slash()
+                .context("Failed to convert closure path to slash path.")?;
+            let closure_cmd = closure::get_closure_cmd(self, APP_DIR).unwrap_or_default();
+            let copy_cmds = utils::get_copy_from_commands(
+                "0",
+                &self.only_include_files.clone().unwrap_or_default(),
+                APP_DIR,
+            );
+            let run_image = self
+                .run_image
+                .clone()
+                .unwrap_or_else(|| "scratch".to_string());
+
+            // The final image has no login profile, so bash reads the environment from BASH_ENV instead
+            return Ok(formatdoc! {"
+                # closure
+                COPY {rel_closure_path} {CLOSURE_DIR}
+                RUN {closure_cmd}
+
+                # start
+                FROM {run_image}
+                COPY --from=0 {CLOSURE_ROOT} /
+                ENV BASH_ENV={CLOSURE_ENV_FILE} SSL_CERT_FILE=/etc/ssl/certs/ca-certificates.crt
+                ENTRYPOINT [\"/bin/bash\", \"-c\"]
+                WORKDIR {APP_DIR}
+                {copy_cmds}
+                {supervisor_copy_cmd}
+                {runtime_str}
+                {start_cmd}
+            ",
+            copy_cmds=copy_cmds.join("\n")});
+        }
+
         let dockerfile: String = match &self.run_image {
             Some(run_image) => {
                 let copy_cmds = utils::get_copy_from_commands(
@@ -351,29 +536,64 @@ impl DockerfileGenerator for StartPhase {
                   COPY --from=0 /etc/ssl/certs /etc/ssl/certs
                   RUN true
                   {copy_cmds}
+                  {supervisor_copy_cmd}
                   {user_str}
+                  {runtime_str}
                   {start_cmd}
                 ",
                 run_image=run_image,
                 APP_DIR=APP_DIR,
                 copy_cmds=copy_cmds.join("\n"),
+                supervisor_copy_cmd=supervisor_copy_cmd,
                 user_str=user_str,
+                runtime_str=runtime_str,
                 start_cmd=start_cmd,}
             }
             None => {
                 formatdoc! {"
                   # start
                   COPY . /app
+                  {supervisor_copy_cmd}
                   {user_str}
+                  {runtime_str}
                   {start_cmd}
                 ",
+                supervisor_copy_cmd=supervisor_copy_cmd,
                 start_cmd=start_cmd,
-                user_str=user_str}
+                user_str=user_str,
+                runtime_str=runtime_str}
             }
         };
 
         Ok(dockerfile)
     }
+
+    /// Writes the supervisor that runs the processes of the start phase, and the script that collects its closure.
+    fn write_supporting_files(
+        &self,
+        _options: &DockerBuilderOptions,
+        _env: &Environment,
+        output: &OutputDir,
+    ) -> Result<()> {
+        let dirs = [
+            ("supervisor", supervisor::get_supervisor_files(self)),
+            ("closure", closure::get_closure_files(self)),
+        ];
+        for (dir, files) in dirs {
+            if files.is_empty() {
+                continue;
+            }
+
+            let dir_path = output.get_absolute_path(dir);
+            fs::create_dir_all(&dir_path).context(format!("Creating {dir} folder"))?;
+            for (name, contents) in files {
+                fs::write(dir_path.join(&name), contents)
+                    .context(format!("Writing {dir} file {name}"))?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl DockerfileGenerator for Phase {
@@ -392,7 +612,11 @@ impl DockerfileGenerator for Phase {
         let phase = self;
 
         let cache_key = if !options.no_cache && !env.is_config_variable_truthy("NO_CACHE") {
-            options.cache_key.clone()
+            options
+                .cache_keys
+                .get(&phase.get_name())
+                .cloned()
+                .or_else(|| options.cache_key.clone())
         } else {
             None
         };
@@ -413,13 +637,30 @@ impl DockerfileGenerator for Phase {
         let phase_copy_cmds = utils::get_copy_commands(&phase_files, APP_DIR);
 
         let cache_mount = utils::get_cache_mount(&cache_key, &phase.cache_directories);
-        let cmds_str = if options.incremental_cache_image.is_some() {
-            let image = &options.incremental_cache_image.clone().unwrap();
-            let cache_copy_in_command = if IncrementalCache::is_image_exists(image)? {
-                IncrementalCache::get_copy_to_image_command(&phase.cache_directories, image)
-                    .join("\n")
-            } else {
-                String::new()
+
+        // Secrets are only mounted while the commands run, so they never end up in a layer
+        let secrets = phase.secrets.clone().unwrap_or_default();
+        let secret_mount = utils::get_secret_mount(&secrets);
+        let secret_exports = utils::get_secret_exports(&secrets);
+        let phase_cmds = phase
+            .cmds
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|cmd| format!("{secret_exports}{cmd}"))
+            .collect::<Vec<_>>();
+
+        let incremental_cache_store = IncrementalCacheStore::new(
+            options.incremental_cache_image.as_ref(),
+            options.incremental_cache_dir.as_ref(),
+        )?;
+        let cmds_str = if let Some(store) = incremental_cache_store {
+            let cache_copy_in_command = match store.get_copy_source(options.engine)? {
+                Some(source) => {
+                    IncrementalCache::get_copy_to_image_command(&phase.cache_directories, &source)
+                        .join("\n")
+                }
+                None => String::new(),
             };
 
             let cache_copy_out_command = IncrementalCache::get_copy_from_image_command(
@@ -427,24 +668,31 @@ impl DockerfileGenerator for Phase {
                 file_server_config,
             );
 
-            let run_commands = [
-                phase.cmds.clone().unwrap_or_default(),
-                cache_copy_out_command,
-            ]
-            .concat()
-            .iter()
-            .map(|s| format!("RUN {s}"))
-            .collect::<Vec<_>>()
-            .join("\n");
+            let phase_cmds = if secrets.is_empty() {
+                phase_cmds
+            } else {
+                phase_cmds
+                    .iter()
+                    .map(|cmd| format!("{secret_mount} {cmd}"))
+                    .collect()
+            };
+            let run_commands = [phase_cmds, cache_copy_out_command]
+                .concat()
+                .iter()
+                .map(|s| format!("RUN {s}"))
+                .collect::<Vec<_>>()
+                .join("\n");
 
             format!("{cache_copy_in_command}\n{run_commands}")
         } else {
-            phase
-                .cmds
-                .clone()
-                .unwrap_or_default()
+            let mounts = [cache_mount, secret_mount]
+                .into_iter()
+                .filter(|mount| !mount.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            phase_cmds
                 .iter()
-                .map(|s| format!("RUN {cache_mount} {s}"))
+                .map(|s| format!("RUN {mounts} {s}"))
                 .collect::<Vec<_>>()
                 .join("\n")
         };
@@ -480,6 +728,8 @@ impl DockerfileGenerator for Phase {
 mod tests {
     use std::collections::BTreeMap;
 
+    use crate::nixpacks::plan::phase::Healthcheck;
+
     use super::*;
 
     #[test]
@@ -534,4 +784,195 @@ mod tests {
         assert!(dockerfile.contains("wget"));
         assert!(dockerfile.contains("ENV VAR1=$VAR1"));
     }
+
+    #[test]
+    fn test_plan_generation_with_secrets() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [secrets]
+            NPM_TOKEN = {}
+
+            [phases.install]
+            cmds = ["npm ci"]
+            secrets = ["NPM_TOKEN"]
+
+            [phases.build]
+            cmds = ["npm run build"]
+            "#,
+        )
+        .unwrap();
+        plan.add_variables(BTreeMap::from([
+            ("NPM_TOKEN".to_string(), "secret".to_string()),
+            ("NODE_ENV".to_string(), "production".to_string()),
+        ]));
+        plan.remove_secret_variables();
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains(
+            "RUN --mount=type=secret,id=NPM_TOKEN export NPM_TOKEN=\"$(cat /run/secrets/NPM_TOKEN)\" && npm ci"
+        ));
+        assert!(dockerfile.contains("RUN  npm run build"));
+        assert!(dockerfile.contains("ENV NODE_ENV=$NODE_ENV"));
+        assert!(!dockerfile.contains("ENV NPM_TOKEN"));
+        assert!(!dockerfile.contains("ARG NPM_TOKEN"));
+    }
+
+    #[test]
+    fn test_plan_generation_with_local_mirror() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+            "#,
+        )
+        .unwrap();
+        plan.nix_mirror = Some(NixMirror::new("file:///srv/nixpacks-mirror").unwrap());
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains(
+            "RUN --mount=type=bind,from=nixpacks-mirror,target=/srv/nixpacks-mirror nix-env -if .nixpacks/nixpkgs.nix"
+        ));
+    }
+
+    #[test]
+    fn test_plan_generation_with_substituters() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+
+            [nixCache]
+            substituters = ["file:///var/cache/nix"]
+            trustedPublicKeys = ["builder-1:abc="]
+            "#,
+        )
+        .unwrap();
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains(
+            "RUN printf '%s\\n' 'extra-substituters = file:///var/cache/nix' 'extra-trusted-public-keys = builder-1:abc=' >> /etc/nix/nix.conf"
+        ));
+        assert!(dockerfile.contains(
+            "RUN --mount=type=bind,from=nixpacks-substituter-0,target=/var/cache/nix nix-env -if .nixpacks/nixpkgs.nix"
+        ));
+    }
+
+    #[test]
+    fn test_closure_only_start_phase() {
+        let mut start = StartPhase::new("./bin/server");
+        start.run_closure_only();
+
+        let dockerfile = start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains(
+            "RUN bash -l /nixpacks/closure/closure.sh /nixpacks-root /app/ './bin/server'"
+        ));
+        assert!(dockerfile.contains("FROM scratch\nCOPY --from=0 /nixpacks-root /\n"));
+        assert!(dockerfile.contains("COPY --from=0 /app/ /app/"));
+        assert!(dockerfile.contains("ENTRYPOINT [\"/bin/bash\", \"-c\"]"));
+
+        start.user = Some("app".to_string());
+        assert!(start
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_run_image_copies_app_after_install() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.install]
+            cmds = ["go mod download"]
+            onlyIncludeFiles = ["go.mod", "go.sum"]
+
+            [phases.build]
+            cmds = ["go build -o out"]
+            dependsOn = ["install"]
+            onlyIncludeFiles = ["main.go"]
+
+            [start]
+            cmd = "./out"
+            runImage = "ubuntu:jammy"
+            "#,
+        )
+        .unwrap();
+
+        let dockerfile = plan
+            .generate_dockerfile(
+                &DockerBuilderOptions::default(),
+                &Environment::default(),
+                &OutputDir::default(),
+                None,
+            )
+            .unwrap();
+
+        assert!(dockerfile.contains("COPY go.mod /app/go.mod\nCOPY go.sum /app/go.sum\n"));
+        assert!(dockerfile.contains("COPY . /app/\n"));
+    }
+
+    #[test]
+    fn test_start_phase_runtime_instructions() {
+        let start = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "nginx"
+            ports = [80, 443]
+            stopSignal = "SIGQUIT"
+            healthcheck = { cmd = "curl -f http://localhost/", interval = "30s", retries = 3 }
+            "#,
+        )
+        .unwrap()
+        .start_phase
+        .unwrap();
+
+        assert_eq!(
+            get_runtime_instructions(&start).unwrap(),
+            "EXPOSE 80 443\nHEALTHCHECK --interval=30s --retries=3 CMD curl -f http://localhost/\nSTOPSIGNAL SIGQUIT"
+        );
+        assert_eq!(
+            get_runtime_instructions(&StartPhase::new("nginx")).unwrap(),
+            ""
+        );
+        assert!(get_runtime_instructions(&StartPhase {
+            healthcheck: Some(Healthcheck::default()),
+            ..Default::default()
+        })
+        .is_err());
+    }
 }
@@ -1,5 +1,5 @@
 // use actix_multipart::Multipart;
-use actix_web::http::header::HeaderValue;
+use actix_web::http::header::{self, HeaderValue};
 use actix_web::{
     middleware, rt, web, App as ActixApp, Error as ActixError, HttpRequest, HttpResponse,
     HttpServer,
@@ -7,17 +7,30 @@ use actix_web::{
 use anyhow::Result;
 use futures_util::stream::StreamExt;
 use portpicker::pick_unused_port;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use super::incremental_cache::IncrementalCacheDirs;
 use uuid::Uuid;
 
-const NIXPACKS_SERVER_HOST: &str = "host.docker.internal";
 const NIXPACKS_SERVER_LISTEN_TO_IP: &str = "0.0.0.0";
 
+/// The largest directory the incremental cache keeps by default, in megabytes.
+pub const DEFAULT_MAX_UPLOAD_SIZE_MB: u64 = 4096;
+
+/// The header with the number of bytes of an upload the server has, so a failed upload can continue from there.
+const UPLOAD_OFFSET_HEADER: &str = "Upload-Offset";
+
+/// Uploads end with `sha256:` and the hex digest of everything uploaded before it.
+const TRAILER_PREFIX: &str = "sha256:";
+const TRAILER_LEN: usize = TRAILER_PREFIX.len() + 64;
+
 #[derive(Debug, Clone)]
 pub struct FileServer {}
 
@@ -29,11 +42,83 @@ pub struct FileServerConfig {
     pub access_token: String,
     pub upload_url: String,
     pub files_dir: PathBuf,
+    /// The largest upload the server accepts, in bytes.
+    pub max_upload_size: u64,
+    /// The uploads the server received, by file name.
+    pub uploads: Arc<Mutex<BTreeMap<String, UploadStats>>>,
+}
+
+/// How a directory was uploaded to the incremental cache.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadStats {
+    /// The size of the tarball of the directory.
+    pub size: u64,
+    /// The bytes uploaded, which is less than the size when the build image has zstd.
+    pub uploaded_size: u64,
+    /// The time spent receiving the upload, across all attempts.
+    pub duration: Duration,
+    pub complete: bool,
+}
+
+/// The encodings an upload can be compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UploadEncoding {
+    Identity,
+    Zstd,
+}
+
+/// How a finished upload ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UploadOutcome {
+    /// The upload was stored, with the size of its tarball.
+    Complete(u64),
+    /// The upload didn't match its trailer and was discarded.
+    Corrupted,
+    /// The decompressed upload is larger than the server accepts and was discarded.
+    TooLarge,
+}
+
+impl FileServerConfig {
+    /// A line for each directory uploaded to the incremental cache, with its size and how long the upload took.
+    pub fn get_upload_report(&self) -> Vec<String> {
+        let uploads = self.uploads.lock().unwrap();
+        uploads
+            .iter()
+            .filter(|(_, stats)| stats.complete)
+            .map(|(name, stats)| {
+                format!(
+                    "{}: {} ({} uploaded) in {:.1}s",
+                    get_upload_dir(name),
+                    format_size(stats.size),
+                    format_size(stats.uploaded_size),
+                    stats.duration.as_secs_f64()
+                )
+            })
+            .collect()
+    }
+
+    fn record_upload(&self, name: &str, duration: Duration, completed: Option<(u64, u64)>) {
+        let mut uploads = self.uploads.lock().unwrap();
+        let stats = uploads.entry(name.to_string()).or_default();
+        stats.duration += duration;
+        if let Some((size, uploaded_size)) = completed {
+            stats.size = size;
+            stats.uploaded_size = uploaded_size;
+            stats.complete = true;
+        }
+    }
 }
 
 impl FileServer {
     /// Launch the file server using the default settings and the incremental cache storage directories.
-    pub fn start(self, incremental_cache_dirs: &IncrementalCacheDirs) -> FileServerConfig {
+    ///
+    /// Build containers upload to the server through `host`, the address they reach the host on.
+    pub fn start(
+        self,
+        incremental_cache_dirs: &IncrementalCacheDirs,
+        host: &str,
+        max_upload_size_mb: Option<u64>,
+    ) -> FileServerConfig {
         let port = self.get_free_port();
 
         let config = FileServerConfig {
@@ -41,7 +126,11 @@ impl FileServer {
             access_token: Uuid::new_v4().to_string(),
             listen_to_ip: NIXPACKS_SERVER_LISTEN_TO_IP.to_string(),
             port,
-            upload_url: format!("http://{NIXPACKS_SERVER_HOST}:{port}/upload/"),
+            upload_url: format!("http://{host}:{port}/upload/"),
+            max_upload_size: max_upload_size_mb
+                .unwrap_or(DEFAULT_MAX_UPLOAD_SIZE_MB)
+                .saturating_mul(1024 * 1024),
+            uploads: Arc::default(),
         };
 
         let server_config = config.clone();
@@ -80,7 +169,9 @@ impl FileServer {
                         .route(web::get().to(|| async { "Nixpacks HTTP server is up & running!" })),
                 )
                 .service(
-                    web::resource("/upload/{filename}").route(web::put().to(FileServer::upload)),
+                    web::resource("/upload/{filename}")
+                        .route(web::head().to(FileServer::upload_offset))
+                        .route(web::put().to(FileServer::upload)),
                 )
         })
         .bind((data.listen_to_ip, data.port))?
@@ -89,11 +180,11 @@ impl FileServer {
         server.await
     }
 
-    /// Check if the provided access_token matches the one in the server response header.
+    /// Check if the provided access_token matches the bearer token of the request.
     fn has_valid_access_token(token: Option<&HeaderValue>, access_token: &str) -> bool {
         if let Some(header) = token {
             match header.to_str() {
-                Ok(value) => value == access_token,
+                Ok(value) => value.strip_prefix("Bearer ") == Some(access_token),
                 _ => false,
             }
         } else {
@@ -101,6 +192,29 @@ impl FileServer {
         }
     }
 
+    /// Respond with how much of an upload the server has, to continue a failed upload from.
+    #[allow(clippy::unused_async)]
+    async fn upload_offset(
+        path: web::Path<String>,
+        req: HttpRequest,
+        data: web::Data<FileServerConfig>,
+    ) -> HttpResponse {
+        if !FileServer::has_valid_access_token(
+            req.headers().get(header::AUTHORIZATION),
+            &data.access_token,
+        ) {
+            return HttpResponse::Unauthorized().into();
+        }
+
+        let partial_path = get_partial_path(&data.files_dir, &path.into_inner());
+        HttpResponse::Ok()
+            .insert_header((UPLOAD_OFFSET_HEADER, get_file_size(&partial_path)))
+            .finish()
+    }
+
+    /// Receive an upload, or the rest of one, from the `Upload-Offset` header on.
+    ///
+    /// The upload is kept once the sha256 trailer matches everything received, decompressed when it is zstd.
     #[allow(dead_code)]
     async fn upload(
         mut payload: web::Payload,
@@ -108,22 +222,264 @@ impl FileServer {
         req: HttpRequest,
         data: web::Data<FileServerConfig>,
     ) -> Result<HttpResponse, ActixError> {
-        if !FileServer::has_valid_access_token(req.headers().get("t"), &data.access_token) {
+        if !FileServer::has_valid_access_token(
+            req.headers().get(header::AUTHORIZATION),
+            &data.access_token,
+        ) {
             return Ok(HttpResponse::Unauthorized().into());
         }
 
-        let filename = path.into_inner();
-        let filepath = data.files_dir.join(sanitize_filename::sanitize(&filename));
+        let encoding = match req
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(HeaderValue::to_str)
+        {
+            None | Some(Ok("identity")) => UploadEncoding::Identity,
+            Some(Ok("zstd")) => UploadEncoding::Zstd,
+            _ => return Ok(HttpResponse::UnsupportedMediaType().into()),
+        };
+
+        let filename = sanitize_filename::sanitize(path.into_inner());
+        let partial_path = get_partial_path(&data.files_dir, &filename);
+        let offset = req
+            .headers()
+            .get(UPLOAD_OFFSET_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or_default();
+
+        let received = get_file_size(&partial_path);
+        if offset != received {
+            return Ok(HttpResponse::Conflict()
+                .insert_header((UPLOAD_OFFSET_HEADER, received))
+                .finish());
+        }
 
-        let in_path = PathBuf::from(&filepath);
-        let mut f: File = web::block(|| File::create(in_path)).await??;
+        let started = Instant::now();
+        let in_path = partial_path.clone();
+        let mut f: File = web::block(move || {
+            OpenOptions::new()
+                .create(true)
+                .append(offset > 0)
+                .write(true)
+                .truncate(offset == 0)
+                .open(in_path)
+        })
+        .await??;
 
+        // The trailer is held back until the upload ends, as it isn't part of the upload
+        let mut tail: Vec<u8> = Vec::new();
+        let mut uploaded_size = offset;
         while let Some(chunk) = payload.next().await {
-            let data = chunk?;
-            f = web::block(move || f.write_all(&data).map(|()| f)).await??;
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    // Keep what arrived, for the next attempt to continue from
+                    web::block(move || f.write_all(&tail)).await??;
+                    data.record_upload(&filename, started.elapsed(), None);
+                    return Err(e.into());
+                }
+            };
+
+            tail.extend_from_slice(&chunk);
+            if tail.len() > TRAILER_LEN {
+                let body = tail.drain(..tail.len() - TRAILER_LEN).collect::<Vec<_>>();
+                uploaded_size += body.len() as u64;
+                if uploaded_size > data.max_upload_size {
+                    drop(f);
+                    web::block(move || fs::remove_file(partial_path)).await??;
+                    return Ok(HttpResponse::PayloadTooLarge().into());
+                }
+
+                f = web::block(move || f.write_all(&body).map(|()| f)).await??;
+            }
         }
         web::block(move || f.flush()).await??;
 
-        Ok(HttpResponse::Ok().into())
+        let trailer = String::from_utf8_lossy(&tail).to_string();
+        let final_path = data.files_dir.join(&filename);
+        let max_size = data.max_upload_size;
+        let outcome = web::block(move || {
+            complete_upload(&partial_path, &final_path, &trailer, encoding, max_size)
+        })
+        .await??;
+
+        match outcome {
+            UploadOutcome::Complete(size) => {
+                data.record_upload(&filename, started.elapsed(), Some((size, uploaded_size)));
+                Ok(HttpResponse::Created().into())
+            }
+            UploadOutcome::Corrupted => {
+                data.record_upload(&filename, started.elapsed(), None);
+                Ok(HttpResponse::UnprocessableEntity().into())
+            }
+            UploadOutcome::TooLarge => {
+                data.record_upload(&filename, started.elapsed(), None);
+                Ok(HttpResponse::PayloadTooLarge().into())
+            }
+        }
+    }
+}
+
+/// Check the trailer of a finished upload, and move the upload to where the cache store reads it from.
+///
+/// Compressed uploads are discarded when they decompress to more than `max_size` bytes.
+fn complete_upload(
+    partial_path: &Path,
+    final_path: &Path,
+    trailer: &str,
+    encoding: UploadEncoding,
+    max_size: u64,
+) -> io::Result<UploadOutcome> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(partial_path)?, &mut hasher)?;
+    if format!("{TRAILER_PREFIX}{:x}", hasher.finalize()) != trailer {
+        fs::remove_file(partial_path)?;
+        return Ok(UploadOutcome::Corrupted);
+    }
+
+    match encoding {
+        UploadEncoding::Identity => fs::rename(partial_path, final_path)?,
+        UploadEncoding::Zstd => {
+            let decoder = zstd::stream::read::Decoder::new(File::open(partial_path)?)?;
+            let size = io::copy(
+                &mut decoder.take(max_size.saturating_add(1)),
+                &mut File::create(final_path)?,
+            )?;
+            fs::remove_file(partial_path)?;
+            if size > max_size {
+                fs::remove_file(final_path)?;
+                return Ok(UploadOutcome::TooLarge);
+            }
+        }
+    }
+
+    Ok(UploadOutcome::Complete(get_file_size(final_path)))
+}
+
+/// Where an upload is kept until it is complete.
+fn get_partial_path(files_dir: &Path, filename: &str) -> PathBuf {
+    files_dir.join(format!("{}.partial", sanitize_filename::sanitize(filename)))
+}
+
+fn get_file_size(path: &Path) -> u64 {
+    fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or_default()
+}
+
+/// The directory of the build image an upload holds, from its file name.
+fn get_upload_dir(filename: &str) -> String {
+    filename.trim_end_matches(".tar").replace("%2f", "/")
+}
+
+/// Format a number of bytes with the largest binary unit it has at least one of.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn trailer(contents: &[u8]) -> String {
+        format!("{TRAILER_PREFIX}{:x}", Sha256::digest(contents))
+    }
+
+    #[test]
+    fn test_complete_upload() -> Result<()> {
+        let dir = TempDir::new("nixpacks-file-server")?;
+        let partial_path = get_partial_path(dir.path(), "%2froot%2f.npm.tar");
+        let final_path = dir.path().join("%2froot%2f.npm.tar");
+
+        let compressed = zstd::encode_all("cached".as_bytes(), 0)?;
+        fs::write(&partial_path, &compressed)?;
+        assert_eq!(
+            complete_upload(
+                &partial_path,
+                &final_path,
+                &trailer(&compressed),
+                UploadEncoding::Zstd,
+                1024
+            )?,
+            UploadOutcome::Complete(6)
+        );
+        assert_eq!(fs::read_to_string(&final_path)?, "cached");
+        assert!(!partial_path.exists());
+
+        // Uploads that don't match their trailer are discarded
+        fs::write(&partial_path, "corrupted")?;
+        assert_eq!(
+            complete_upload(
+                &partial_path,
+                &final_path,
+                &trailer(b"cached"),
+                UploadEncoding::Identity,
+                1024
+            )?,
+            UploadOutcome::Corrupted
+        );
+        assert!(!partial_path.exists());
+
+        // Compressed uploads can't decompress to more than the largest upload
+        let compressed = zstd::encode_all(&[0u8; 4096][..], 0)?;
+        fs::write(&partial_path, &compressed)?;
+        assert_eq!(
+            complete_upload(
+                &partial_path,
+                &final_path,
+                &trailer(&compressed),
+                UploadEncoding::Zstd,
+                1024
+            )?,
+            UploadOutcome::TooLarge
+        );
+        assert!(!partial_path.exists());
+        assert!(!final_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_access_token() {
+        let token = HeaderValue::from_static("Bearer secret");
+        assert!(FileServer::has_valid_access_token(Some(&token), "secret"));
+        assert!(!FileServer::has_valid_access_token(Some(&token), "other"));
+        assert!(!FileServer::has_valid_access_token(
+            Some(&HeaderValue::from_static("secret")),
+            "secret"
+        ));
+        assert!(!FileServer::has_valid_access_token(None, "secret"));
+    }
+
+    #[test]
+    fn test_upload_report() {
+        let config = FileServerConfig::default();
+        config.record_upload("%2froot%2f.npm.tar", Duration::from_millis(1500), None);
+        config.record_upload(
+            "%2froot%2f.npm.tar",
+            Duration::from_millis(500),
+            Some((3 * 1024 * 1024, 1024 * 1024)),
+        );
+        config.record_upload("%2froot%2f.cache.tar", Duration::from_secs(1), None);
+
+        assert_eq!(
+            config.get_upload_report(),
+            vec!["/root/.npm: 3.0 MB (1.0 MB uploaded) in 2.0s"]
+        );
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
     }
 }
@@ -1,19 +1,29 @@
 use std::{
     fs::{self},
     path::PathBuf,
-    process::Command,
 };
 
-use super::{dockerfile_generation::OutputDir, file_server::FileServerConfig};
-use anyhow::{bail, Context, Result};
+use super::{
+    cache_store::IncrementalCacheStore, container_engine::ContainerEngine,
+    dockerfile_generation::OutputDir, file_server::FileServerConfig, DockerBuilderOptions,
+};
+use anyhow::{Context, Result};
 use std::process::Stdio;
 
 const INCREMENTAL_CACHE_DIR: &str = "incremental-cache";
 const INCREMENTAL_CACHE_UPLOADS_DIR: &str = "uploads";
 const INCREMENTAL_CACHE_IMAGE_DIR: &str = "image";
+const INCREMENTAL_CACHE_RESTORE_DIR: &str = "restore";
 
-#[derive(Default)]
-pub struct IncrementalCache {}
+/// Where the script that uploads cached directories is stored in the build image.
+pub const INCREMENTAL_CACHE_UPLOAD_DIR: &str = "/nixpacks/incremental-cache/";
+
+const UPLOAD_SCRIPT: &str = include_str!("upload.sh");
+
+/// Caches directories of the build image between builds, in a registry image or on disk.
+pub struct IncrementalCache {
+    store: IncrementalCacheStore,
+}
 
 /// Directories in which to cache Docker image layers.
 #[derive(Default)]
@@ -21,6 +31,7 @@ pub struct IncrementalCacheDirs {
     out_dir: OutputDir,
     pub uploads_dir: PathBuf,
     pub image_dir: PathBuf,
+    pub restore_dir: PathBuf,
 }
 
 impl IncrementalCacheDirs {
@@ -28,11 +39,13 @@ impl IncrementalCacheDirs {
         let incremental_cache_root = out_dir.get_absolute_path(INCREMENTAL_CACHE_DIR);
         let image_dir = incremental_cache_root.join(PathBuf::from(INCREMENTAL_CACHE_IMAGE_DIR));
         let uploads_dir = incremental_cache_root.join(PathBuf::from(INCREMENTAL_CACHE_UPLOADS_DIR));
+        let restore_dir = incremental_cache_root.join(PathBuf::from(INCREMENTAL_CACHE_RESTORE_DIR));
 
         IncrementalCacheDirs {
             out_dir: out_dir.clone(),
             uploads_dir,
             image_dir,
+            restore_dir,
         }
     }
 
@@ -70,43 +83,28 @@ impl IncrementalCacheDirs {
 }
 
 impl IncrementalCache {
-    /// Create a filesystem image for each of the files in the incremental cache uploads directory, then upload these to the Docker cache.
-    pub fn create_image(
+    pub fn new(store: IncrementalCacheStore) -> Self {
+        IncrementalCache { store }
+    }
+
+    /// Restore the cached directories stored on disk, for the build to copy from the restore dir.
+    pub fn restore(&self, incremental_cache_dirs: &IncrementalCacheDirs) -> Result<bool> {
+        self.store.restore(&incremental_cache_dirs.restore_dir)
+    }
+
+    /// Save the directories the build uploaded to the incremental cache store.
+    pub fn save(
         &self,
         incremental_cache_dirs: &IncrementalCacheDirs,
-        tag: &str,
+        engine: ContainerEngine,
     ) -> Result<()> {
-        let files = fs::read_dir(&incremental_cache_dirs.uploads_dir)?;
-
-        // There are three options to create a filesystem image that contains only tar files
-        // #1 Use a Rust crate to create the image: 30+ seconds in a sample test, Also no clear winner Crate for creating OCI image
-        // #2 Create minimal Dockerfile: 6 seconds in a sample test
-        // #3 Use Docker import: Provide 3 seconds in a sample test
-        for f in files {
-            let mut docker_import_cmd = Command::new("docker");
-            docker_import_cmd.arg("import").arg(f?.path()).arg(tag);
-
-            let result = docker_import_cmd
-                .spawn()?
-                .wait()
-                .context("Create incremental cache image")?;
-
-            if !result.success() {
-                bail!("Creating incremental cache image failed")
-            }
-        }
-
-        println!("Incremental cache image created: {}", &tag);
-        Ok(())
+        self.store.save(&incremental_cache_dirs.uploads_dir, engine)
     }
 
     /// Check if the provided image_tag matches a tag in the incremental Docker image cache.
-    pub fn is_image_exists(image_tag: &str) -> Result<bool> {
-        let mut docker_inspect_cmd = Command::new("docker");
+    pub fn is_image_exists(image_tag: &str, engine: ContainerEngine) -> Result<bool> {
+        let mut docker_inspect_cmd = engine.manifest_inspect_command(image_tag);
         docker_inspect_cmd
-            .arg("manifest")
-            .arg("inspect")
-            .arg(image_tag)
             .stdout(Stdio::null())
             .stderr(Stdio::null());
 
@@ -119,9 +117,11 @@ impl IncrementalCache {
     }
 
     /// Produce Dockerfile line(s) copying cached files from the incremental cache to the final build image.
+    ///
+    /// The source is the incremental cache image, or the named build context it was restored to.
     pub fn get_copy_to_image_command(
         cache_directories: &Option<Vec<String>>,
-        incremental_cache_image: &str,
+        source: &str,
     ) -> Vec<String> {
         let dirs = &cache_directories.clone().unwrap_or_default();
         if dirs.is_empty() {
@@ -139,13 +139,15 @@ impl IncrementalCache {
                     .join("/");
 
                 vec![format!(
-                    "COPY --from={incremental_cache_image} {target_cache_dir_optional} {target_cache_dir}"
+                    "COPY --from={source} {target_cache_dir_optional} {target_cache_dir}"
                 )]
             })
             .collect::<Vec<String>>()
     }
 
     /// Produce Dockerfile line(s) copying files from the build image into the incremental cache.
+    ///
+    /// Each directory is uploaded to the file server by the upload script, which removes it from the image afterwards.
     pub fn get_copy_from_image_command(
         cache_directories: &Option<Vec<String>>,
         file_server_config: Option<FileServerConfig>,
@@ -158,20 +160,26 @@ impl IncrementalCache {
         let server_config = file_server_config.unwrap();
         container_dirs
             .iter()
-            .flat_map(|dir| {
+            .map(|dir| {
                 let sanitized_dir = dir.replace('~', "/root");
-                let compressed_file_name = format!("{}.tar", sanitized_dir.replace('/', "%2f"));
-                vec![
-                    format!("if [ -d \"{sanitized_dir}\" ]; then tar -cf {compressed_file_name} {sanitized_dir}; fi;"),
-                    format!(
-                        "if [ -d \"{sanitized_dir}\" ]; then curl -v -T {} {} --header \"t:{}\" --retry 3 --retry-all-errors; fi;",
-                        compressed_file_name, server_config.upload_url, server_config.access_token,
-                    ),
-                    format!("if [ -d \"{sanitized_dir}\" ]; then rm -rf {sanitized_dir}; fi"),
-                ]
+                format!(
+                    "bash {INCREMENTAL_CACHE_UPLOAD_DIR}upload.sh {sanitized_dir} {} {} {}",
+                    server_config.upload_url,
+                    server_config.access_token,
+                    server_config.max_upload_size,
+                )
             })
             .collect::<Vec<String>>()
     }
+
+    /// The files to write to the upload directory, if the build uploads to an incremental cache.
+    pub fn get_upload_files(options: &DockerBuilderOptions) -> Vec<(String, String)> {
+        if options.uses_incremental_cache() {
+            vec![("upload.sh".to_string(), UPLOAD_SCRIPT.to_string())]
+        } else {
+            Vec::new()
+        }
+    }
 }
 
 #[test]
@@ -182,17 +190,16 @@ fn test_get_copy_from_image_command() {
             listen_to_ip: "0.0.0.0".to_string(),
             port: 1234,
             access_token: "test_access_token".to_string(),
-            upload_url: "http://test.com/upload".to_string(),
+            upload_url: "http://test.com/upload/".to_string(),
             files_dir: PathBuf::from("./source_dir".to_string()),
+            max_upload_size: 1024,
+            ..Default::default()
         }),
     );
 
-    assert_eq!(cmds.len(), 3);
-    assert_eq!(cmds[0], "if [ -d \"./parent_dir/child_dir\" ]; then tar -cf .%2fparent_dir%2fchild_dir.tar ./parent_dir/child_dir; fi;".to_string());
-    assert_eq!(cmds[1], "if [ -d \"./parent_dir/child_dir\" ]; then curl -v -T .%2fparent_dir%2fchild_dir.tar http://test.com/upload --header \"t:test_access_token\" --retry 3 --retry-all-errors; fi;".to_string());
     assert_eq!(
-        cmds[2],
-        "if [ -d \"./parent_dir/child_dir\" ]; then rm -rf ./parent_dir/child_dir; fi".to_string()
+        cmds,
+        vec!["bash /nixpacks/incremental-cache/upload.sh ./parent_dir/child_dir http://test.com/upload/ test_access_token 1024"]
     );
 }
 
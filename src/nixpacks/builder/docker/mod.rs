@@ -1,4 +1,6 @@
-use super::ImageBuilder;
+use super::{ImageBuilder, ImageBuilderKind};
+use container_engine::ContainerEngine;
+use std::collections::BTreeMap;
 
 /// Holds options for generating a Docker image.
 #[derive(Clone, Default, Debug)]
@@ -11,6 +13,9 @@ pub struct DockerBuilderOptions {
     pub labels: Vec<String>,
     pub quiet: bool,
     pub cache_key: Option<String>,
+    /// The cache keys of the phases with cache directories, by phase name. Phases without one use `cache_key`.
+    pub cache_keys: BTreeMap<String, String>,
+    pub explain_cache: bool,
     pub no_cache: bool,
     pub inline_cache: bool,
     pub cache_from: Option<String>,
@@ -18,6 +23,9 @@ pub struct DockerBuilderOptions {
     pub current_dir: bool,
     pub no_error_without_start: bool,
     pub incremental_cache_image: Option<String>,
+    pub incremental_cache_dir: Option<String>,
+    /// The largest cache directory the incremental cache keeps, in megabytes.
+    pub incremental_cache_max_size: Option<u64>,
     pub cpu_quota: Option<String>,
     pub memory: Option<String>,
     pub verbose: bool,
@@ -26,9 +34,23 @@ pub struct DockerBuilderOptions {
     pub docker_output: Vec<String>,
     pub add_host: Vec<String>,
     pub docker_cert_path: Option<String>,
+    pub builder: ImageBuilderKind,
+    pub engine: ContainerEngine,
+    pub push_nix_cache: Option<String>,
+    pub nix_cache_secret_key: Option<String>,
 }
 
-mod cache;
+impl DockerBuilderOptions {
+    /// Whether cache directories are saved to an incremental cache image or directory between builds.
+    pub fn uses_incremental_cache(&self) -> bool {
+        self.incremental_cache_image.is_some() || self.incremental_cache_dir.is_some()
+    }
+}
+
+pub mod cache;
+pub mod cache_metadata;
+pub mod cache_store;
+pub mod container_engine;
 pub mod docker_helper;
 pub mod docker_image_builder;
 mod dockerfile_generation;
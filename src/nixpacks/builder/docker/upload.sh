@@ -0,0 +1,120 @@
+#!/usr/bin/env bash
+# Uploads a directory of the build image to the incremental cache, then removes it from the image.
+#
+# Usage: upload.sh <dir> <upload url> <access token> <max size in bytes>
+#
+# The directory is sent as a tar stream, compressed with zstd when it is installed, followed by a
+# trailer with the sha256 of the stream, which the server checks before keeping it. When an attempt
+# fails, the next one continues from what the server already has. Requests are sent over bash's
+# /dev/tcp, so the build image needs no HTTP client.
+
+set -euo pipefail
+
+dir="$1"
+url="$2"
+token="$3"
+max_size="$4"
+
+if [ ! -d "$dir" ]; then
+  exit 0
+fi
+
+# http://<host>:<port>/<path>/
+address="${url#http://}"
+authority="${address%%/*}"
+host="${authority%:*}"
+port="${authority##*:}"
+# The file name is the directory with its slashes as %2f, which is escaped again in the URL
+path="/${address#*/}$(printf '%s' "$dir" | sed 's|/|%252f|g').tar"
+
+archive="$(mktemp)"
+trap 'rm -f "$archive"' EXIT
+
+# Absolute paths are archived relative to / without tar warning about it
+if [ "${dir:0:1}" = / ]; then
+  tar_args=(-C / "${dir#/}")
+else
+  tar_args=("$dir")
+fi
+
+if command -v zstd >/dev/null 2>&1; then
+  encoding=zstd
+  tar -cf - "${tar_args[@]}" | zstd -q -c >"$archive"
+else
+  encoding=identity
+  tar -cf - "${tar_args[@]}" >"$archive"
+fi
+
+size="$(wc -c <"$archive")"
+if [ "$size" -gt "$max_size" ]; then
+  echo "Not caching $dir, it is $size bytes compressed, more than the limit of $max_size bytes" >&2
+  rm -rf "$dir"
+  exit 0
+fi
+checksum="$(sha256sum "$archive" | cut -d' ' -f1)"
+trailer="sha256:$checksum"
+
+# Send a request with the body read from stdin, printing the status code and the offset the server has
+request() {
+  local method="$1" length="$2" offset="$3"
+  if ! exec 3<>"/dev/tcp/$host/$port"; then
+    echo "000 0"
+    return 0
+  fi
+
+  {
+    printf '%s %s HTTP/1.1\r\nHost: %s\r\nConnection: close\r\n' "$method" "$path" "$authority"
+    printf 'Authorization: Bearer %s\r\nContent-Encoding: %s\r\n' "$token" "$encoding"
+    printf 'Content-Length: %s\r\nUpload-Offset: %s\r\n\r\n' "$length" "$offset"
+    cat
+  } >&3
+
+  local status="000" server_offset=0 line
+  read -r _ status _ <&3 || true
+  while IFS= read -r line <&3; do
+    line="${line%$'\r'}"
+    if [ -z "$line" ]; then
+      break
+    fi
+    case "${line,,}" in
+    upload-offset:*) server_offset="${line#*: }" ;;
+    esac
+  done
+  exec 3>&-
+
+  echo "$status $server_offset"
+}
+
+uploaded=false
+for attempt in 1 2 3; do
+  read -r status offset < <(request HEAD 0 0 </dev/null)
+  if [ "$status" = 200 ]; then
+    # Anything past the end of the archive is from an upload of different contents
+    if [ "$offset" -gt "$size" ]; then
+      offset=0
+    fi
+
+    read -r status offset < <({
+      tail -c +"$((offset + 1))" "$archive"
+      printf '%s' "$trailer"
+    } | request PUT "$((size - offset + ${#trailer}))" "$offset")
+  fi
+
+  case "$status" in
+  201)
+    uploaded=true
+    break
+    ;;
+  413)
+    echo "Not caching $dir, it is $size bytes compressed, more than the limit of $max_size bytes" >&2
+    break
+    ;;
+  esac
+  sleep "$attempt"
+done
+
+if [ "$uploaded" = false ] && [ "$status" != 413 ]; then
+  echo "Uploading $dir to the incremental cache failed with status $status" >&2
+fi
+
+rm -rf "$dir"
@@ -11,12 +11,8 @@ pub fn get_cache_mount(
         (Some(cache_key), Some(cache_directories)) => cache_directories
             .iter()
             .map(|dir| {
-                let mut sanitized_dir = dir.replace('~', "/root");
-                let sanitized_key = sanitize_cache_key(&format!("{cache_key}-{sanitized_dir}"));
-                if !sanitized_dir.starts_with('/') {
-                    sanitized_dir = format!("/app/{sanitized_dir}");
-                }
-                format!("--mount=type=cache,id={sanitized_key},target={sanitized_dir}")
+                let (id, target) = get_cache_mount_id(cache_key, dir);
+                format!("--mount=type=cache,id={id},target={target}")
             })
             .collect::<Vec<String>>()
             .join(" "),
@@ -24,6 +20,35 @@ pub fn get_cache_mount(
     }
 }
 
+/// The id of the BuildKit cache mount of a cache directory, and the directory it is mounted at.
+pub fn get_cache_mount_id(cache_key: &str, dir: &str) -> (String, String) {
+    let mut sanitized_dir = dir.replace('~', "/root");
+    let sanitized_key = sanitize_cache_key(&format!("{cache_key}-{sanitized_dir}"));
+    if !sanitized_dir.starts_with('/') {
+        sanitized_dir = format!("/app/{sanitized_dir}");
+    }
+
+    (sanitized_key, sanitized_dir)
+}
+
+/// Produce a string of Docker command flags mounting the given build secrets.
+pub fn get_secret_mount(secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .map(|id| format!("--mount=type=secret,id={id}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Produce the shell prefix that exposes mounted build secrets to a command as environment variables.
+pub fn get_secret_exports(secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .map(|id| format!("export {id}=\"$(cat /run/secrets/{id})\" && "))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
 /// Produce Dockerfile line(s) copying files into the build image.
 pub fn get_copy_commands(files: &[String], app_dir: &str) -> Vec<String> {
     if files.is_empty() {
@@ -2,7 +2,25 @@ use super::{environment::Environment, plan::BuildPlan};
 use anyhow::Result;
 use async_trait::async_trait;
 
+pub mod closure;
 pub mod docker;
+pub mod oci;
+pub mod supervisor;
+
+/// Which image builder creates the image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageBuilderKind {
+    /// Generate a Dockerfile and build it with `docker build`.
+    Docker,
+    /// Assemble an OCI image directly, without a Docker daemon.
+    Oci,
+}
+
+impl Default for ImageBuilderKind {
+    fn default() -> Self {
+        ImageBuilderKind::Docker
+    }
+}
 
 /// Types that impl this trait can produce Docker images.
 #[async_trait]
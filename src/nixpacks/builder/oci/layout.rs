@@ -0,0 +1,530 @@
+use anyhow::{Context, Result};
+use flate2::{write::GzEncoder, Compression};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tar::{EntryType, Header, HeaderMode};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+const TAR_LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// A blob written to the layout, as referenced from manifests and indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+}
+
+/// A compressed layer, along with the digest of its uncompressed contents the image config needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub descriptor: Descriptor,
+    pub diff_id: String,
+    pub description: String,
+}
+
+/// What goes into a layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerEntry {
+    /// A file, directory, or symlink on the host, added recursively at the given path in the image.
+    Path { source: PathBuf, target: String },
+    /// A file generated by Nixpacks.
+    File { target: String, contents: Vec<u8> },
+}
+
+/// The runtime configuration of the image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageConfig {
+    pub env: Vec<String>,
+    pub entrypoint: Vec<String>,
+    pub cmd: Vec<String>,
+    pub working_dir: String,
+    pub labels: BTreeMap<String, String>,
+    pub exposed_ports: Vec<u16>,
+    pub healthcheck: Option<HealthcheckConfig>,
+    pub stop_signal: Option<String>,
+}
+
+/// The healthcheck of the image, run with the shell of the image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HealthcheckConfig {
+    pub cmd: String,
+    pub interval: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+}
+
+/// Writes an OCI image layout directory.
+pub struct OciLayout {
+    root: PathBuf,
+}
+
+impl OciLayout {
+    /// Create the layout directory structure.
+    pub fn create<P: Into<PathBuf>>(root: P) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(root.join("blobs").join("sha256"))
+            .context("Creating OCI layout directory")?;
+        fs::write(
+            root.join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )?;
+
+        Ok(OciLayout { root })
+    }
+
+    /// Open an existing layout directory, if there is one.
+    pub fn open<P: Into<PathBuf>>(root: P) -> Option<Self> {
+        let root = root.into();
+        if root.join("oci-layout").is_file() {
+            Some(OciLayout { root })
+        } else {
+            None
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Write a blob and return its descriptor.
+    pub fn write_blob(&self, media_type: &str, contents: &[u8]) -> Result<Descriptor> {
+        let digest = format!("sha256:{:x}", Sha256::digest(contents));
+        fs::write(self.blob_path(&digest), contents).context("Writing OCI blob")?;
+
+        Ok(Descriptor {
+            media_type: media_type.to_string(),
+            digest,
+            size: contents.len() as u64,
+        })
+    }
+
+    /// Write a gzip-compressed layer containing the given entries.
+    pub fn write_layer(&self, description: &str, entries: &[LayerEntry]) -> Result<Layer> {
+        let temp_path = self.root.join("blobs").join("layer.tmp");
+        let file = HashWriter::new(File::create(&temp_path)?);
+        let encoder = HashWriter::new(GzEncoder::new(file, Compression::default()));
+
+        let mut builder = tar::Builder::new(encoder);
+        builder.mode(HeaderMode::Deterministic);
+        builder.follow_symlinks(false);
+
+        let mut dirs = BTreeSet::new();
+        for entry in entries {
+            match entry {
+                LayerEntry::Path { source, target } => {
+                    append_parent_dirs(&mut builder, &mut dirs, target)?;
+                    if source.is_dir() && !source.is_symlink() {
+                        builder
+                            .append_dir_all(target, source)
+                            .with_context(|| format!("Adding {} to layer", source.display()))?;
+                    } else {
+                        builder
+                            .append_path_with_name(source, target)
+                            .with_context(|| format!("Adding {} to layer", source.display()))?;
+                    }
+                }
+                LayerEntry::File { target, contents } => {
+                    append_parent_dirs(&mut builder, &mut dirs, target)?;
+                    let mut header = Header::new_gnu();
+                    header.set_size(contents.len() as u64);
+                    header.set_mode(0o644);
+                    header.set_cksum();
+                    builder.append_data(&mut header, target, contents.as_slice())?;
+                }
+            }
+        }
+
+        let encoder = builder.into_inner()?;
+        let (encoder, diff_id) = encoder.finish();
+        let (mut file, digest, size) = encoder.finish()?.finish_with_size();
+        file.flush()?;
+
+        let digest = format!("sha256:{digest:x}");
+        fs::rename(&temp_path, self.blob_path(&digest))?;
+
+        Ok(Layer {
+            descriptor: Descriptor {
+                media_type: LAYER_MEDIA_TYPE.to_string(),
+                digest,
+                size,
+            },
+            diff_id: format!("sha256:{diff_id:x}"),
+            description: description.to_string(),
+        })
+    }
+
+    /// Write an uncompressed tarball as a layer, as it is.
+    pub fn write_tar_layer(&self, description: &str, path: &Path) -> Result<Layer> {
+        let temp_path = self.root.join("blobs").join("layer.tmp");
+        let mut file = HashWriter::new(File::create(&temp_path)?);
+        io::copy(
+            &mut File::open(path).with_context(|| format!("Reading {}", path.display()))?,
+            &mut file,
+        )?;
+        let (mut file, digest, size) = file.finish_with_size();
+        file.flush()?;
+
+        let digest = format!("sha256:{digest:x}");
+        fs::rename(&temp_path, self.blob_path(&digest))?;
+
+        Ok(Layer {
+            descriptor: Descriptor {
+                media_type: TAR_LAYER_MEDIA_TYPE.to_string(),
+                digest: digest.clone(),
+                size,
+            },
+            diff_id: digest,
+            description: description.to_string(),
+        })
+    }
+
+    /// The layers of the first image in the index, with the descriptions they were written with.
+    pub fn read_layers(&self) -> Result<Vec<Layer>> {
+        let index: Value = serde_json::from_slice(
+            &fs::read(self.root.join("index.json")).context("Reading OCI index")?,
+        )?;
+        let manifest_digest = match index["manifests"][0]["digest"].as_str() {
+            Some(digest) => digest,
+            None => return Ok(Vec::new()),
+        };
+
+        let manifest: Value = serde_json::from_slice(&fs::read(self.blob_path(manifest_digest))?)?;
+        let config: Value = serde_json::from_slice(&fs::read(
+            self.blob_path(manifest["config"]["digest"].as_str().unwrap_or_default()),
+        )?)?;
+
+        let layers = manifest["layers"].as_array().cloned().unwrap_or_default();
+        Ok(layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| Layer {
+                descriptor: Descriptor {
+                    media_type: layer["mediaType"].as_str().unwrap_or_default().to_string(),
+                    digest: layer["digest"].as_str().unwrap_or_default().to_string(),
+                    size: layer["size"].as_u64().unwrap_or_default(),
+                },
+                diff_id: config["rootfs"]["diff_ids"][i]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+                description: config["history"][i]["created_by"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .trim_start_matches("nixpacks: ")
+                    .to_string(),
+            })
+            .collect())
+    }
+
+    /// Remove the blobs the images of the index don't use, e.g. layers of an image that was written over.
+    pub fn remove_unreferenced_blobs(&self) -> Result<()> {
+        let index: Value = serde_json::from_slice(&fs::read(self.root.join("index.json"))?)?;
+        let mut referenced = BTreeSet::new();
+        for manifest in index["manifests"].as_array().cloned().unwrap_or_default() {
+            let digest = manifest["digest"].as_str().unwrap_or_default().to_string();
+            let manifest: Value = serde_json::from_slice(&fs::read(self.blob_path(&digest))?)?;
+            referenced.insert(digest);
+            referenced.extend(
+                std::iter::once(&manifest["config"])
+                    .chain(manifest["layers"].as_array().into_iter().flatten())
+                    .filter_map(|descriptor| descriptor["digest"].as_str())
+                    .map(ToString::to_string),
+            );
+        }
+
+        for entry in fs::read_dir(self.root.join("blobs").join("sha256"))? {
+            let entry = entry?;
+            let digest = format!("sha256:{}", entry.file_name().to_string_lossy());
+            if !referenced.contains(&digest) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the image config and manifest, and reference the manifest from the index under each of the given names.
+    pub fn write_image(
+        &self,
+        config: &ImageConfig,
+        layers: &[Layer],
+        ref_names: &[String],
+    ) -> Result<Descriptor> {
+        let mut runtime_config = json!({
+            "Env": config.env,
+            "Entrypoint": config.entrypoint,
+            "Cmd": config.cmd,
+            "WorkingDir": config.working_dir,
+            "Labels": config.labels,
+        });
+        if !config.exposed_ports.is_empty() {
+            runtime_config["ExposedPorts"] = config
+                .exposed_ports
+                .iter()
+                .map(|port| (format!("{port}/tcp"), json!({})))
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+        }
+        if let Some(stop_signal) = &config.stop_signal {
+            runtime_config["StopSignal"] = json!(stop_signal);
+        }
+        // Not part of the OCI spec, but understood by Docker and Podman
+        if let Some(healthcheck) = &config.healthcheck {
+            let mut healthcheck_json = json!({ "Test": ["CMD-SHELL", healthcheck.cmd] });
+            if let Some(interval) = healthcheck.interval {
+                healthcheck_json["Interval"] = json!(interval.as_nanos() as u64);
+            }
+            if let Some(timeout) = healthcheck.timeout {
+                healthcheck_json["Timeout"] = json!(timeout.as_nanos() as u64);
+            }
+            if let Some(retries) = healthcheck.retries {
+                healthcheck_json["Retries"] = json!(retries);
+            }
+            runtime_config["Healthcheck"] = healthcheck_json;
+        }
+
+        let config_json = json!({
+            "architecture": oci_architecture(),
+            "os": "linux",
+            "config": runtime_config,
+            "rootfs": {
+                "type": "layers",
+                "diff_ids": layers.iter().map(|layer| layer.diff_id.clone()).collect::<Vec<_>>(),
+            },
+            "history": layers
+                .iter()
+                .map(|layer| json!({ "created_by": format!("nixpacks: {}", layer.description) }))
+                .collect::<Vec<_>>(),
+        });
+        let config_descriptor =
+            self.write_blob(CONFIG_MEDIA_TYPE, config_json.to_string().as_bytes())?;
+
+        let manifest = json!({
+            "schemaVersion": 2,
+            "mediaType": MANIFEST_MEDIA_TYPE,
+            "config": descriptor_json(&config_descriptor, None),
+            "layers": layers
+                .iter()
+                .map(|layer| descriptor_json(&layer.descriptor, None))
+                .collect::<Vec<_>>(),
+        });
+        let manifest_descriptor =
+            self.write_blob(MANIFEST_MEDIA_TYPE, manifest.to_string().as_bytes())?;
+
+        let manifests = if ref_names.is_empty() {
+            vec![descriptor_json(&manifest_descriptor, None)]
+        } else {
+            ref_names
+                .iter()
+                .map(|name| descriptor_json(&manifest_descriptor, Some(name)))
+                .collect()
+        };
+        let index = json!({
+            "schemaVersion": 2,
+            "manifests": manifests,
+        });
+        fs::write(self.root.join("index.json"), index.to_string())?;
+
+        Ok(manifest_descriptor)
+    }
+
+    /// Pack the layout into a single tarball, which tools like `podman load` and `skopeo` read as an `oci-archive`.
+    pub fn write_archive(&self, path: &Path) -> Result<()> {
+        let mut builder = tar::Builder::new(File::create(path)?);
+        builder.mode(HeaderMode::Deterministic);
+        builder.append_path_with_name(self.root.join("oci-layout"), "oci-layout")?;
+        builder.append_path_with_name(self.root.join("index.json"), "index.json")?;
+        builder.append_dir_all("blobs", self.root.join("blobs"))?;
+        builder.into_inner()?.flush()?;
+
+        Ok(())
+    }
+
+    pub fn blob_path(&self, digest: &str) -> PathBuf {
+        let hex = digest.trim_start_matches("sha256:");
+        self.root.join("blobs").join("sha256").join(hex)
+    }
+}
+
+fn descriptor_json(descriptor: &Descriptor, ref_name: Option<&String>) -> Value {
+    let mut value = json!({
+        "mediaType": descriptor.media_type,
+        "digest": descriptor.digest,
+        "size": descriptor.size,
+    });
+    if let Some(name) = ref_name {
+        value["annotations"] = json!({ REF_NAME_ANNOTATION: name });
+    }
+
+    value
+}
+
+/// Tar entries need their parent directories to exist when the layer is extracted.
+fn append_parent_dirs<W: Write>(
+    builder: &mut tar::Builder<W>,
+    dirs: &mut BTreeSet<String>,
+    target: &str,
+) -> Result<()> {
+    let mut path = String::new();
+    let parents = target.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    for segment in &parents[..parents.len() - 1] {
+        if segment.is_empty() {
+            continue;
+        }
+        path = format!("{path}{segment}/");
+        if dirs.insert(path.clone()) {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            header.set_cksum();
+            builder.append_data(&mut header, &path, io::empty())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The architecture of the host in the naming OCI images use.
+fn oci_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => arch,
+    }
+}
+
+/// Hashes everything written through it.
+struct HashWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        HashWriter {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    fn finish(self) -> (W, sha2::digest::Output<Sha256>) {
+        (self.inner, self.hasher.finalize())
+    }
+
+    fn finish_with_size(self) -> (W, sha2::digest::Output<Sha256>, u64) {
+        let size = self.size;
+        let (inner, digest) = self.finish();
+        (inner, digest, size)
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_write_oci_layout() -> Result<()> {
+        let dir = TempDir::new("nixpacks-oci")?;
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("src"))?;
+        fs::write(source.join("src").join("main.py"), "print('hello')")?;
+
+        let layout = OciLayout::create(dir.path().join("layout"))?;
+        let layer = layout.write_layer(
+            "app",
+            &[
+                LayerEntry::Path {
+                    source: source.clone(),
+                    target: "app".to_string(),
+                },
+                LayerEntry::File {
+                    target: "etc/profile".to_string(),
+                    contents: b"export FOO=bar\n".to_vec(),
+                },
+            ],
+        )?;
+
+        let config = ImageConfig {
+            env: vec!["FOO=bar".to_string()],
+            cmd: vec!["python src/main.py".to_string()],
+            working_dir: "/app/".to_string(),
+            ..Default::default()
+        };
+        let manifest = layout.write_image(
+            &config,
+            std::slice::from_ref(&layer),
+            &["app:latest".to_string()],
+        )?;
+
+        // Blobs are stored by the digest of their contents
+        let blob = fs::read(layout.blob_path(&layer.descriptor.digest))?;
+        assert_eq!(
+            format!("sha256:{:x}", Sha256::digest(&blob)),
+            layer.descriptor.digest
+        );
+        assert_eq!(blob.len() as u64, layer.descriptor.size);
+
+        // The diff id is the digest of the uncompressed layer
+        let mut uncompressed = Vec::new();
+        flate2::read::GzDecoder::new(blob.as_slice()).read_to_end(&mut uncompressed)?;
+        assert_eq!(
+            format!("sha256:{:x}", Sha256::digest(&uncompressed)),
+            layer.diff_id
+        );
+
+        let paths = tar::Archive::new(uncompressed.as_slice())
+            .entries()?
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&"app/src/main.py".to_string()));
+        assert!(paths.contains(&"etc/".to_string()));
+        assert!(paths.contains(&"etc/profile".to_string()));
+
+        let index: Value =
+            serde_json::from_str(&fs::read_to_string(layout.root().join("index.json"))?)?;
+        assert_eq!(index["manifests"][0]["digest"], manifest.digest);
+        assert_eq!(
+            index["manifests"][0]["annotations"][REF_NAME_ANNOTATION],
+            "app:latest"
+        );
+
+        let manifest: Value =
+            serde_json::from_slice(&fs::read(layout.blob_path(&manifest.digest))?)?;
+        let config: Value = serde_json::from_slice(&fs::read(
+            layout.blob_path(manifest["config"]["digest"].as_str().unwrap()),
+        )?)?;
+        assert_eq!(config["config"]["WorkingDir"], "/app/");
+        assert_eq!(config["rootfs"]["diff_ids"][0], layer.diff_id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,753 @@
+use self::layout::{HealthcheckConfig, ImageConfig, LayerEntry, OciLayout};
+use super::{
+    docker::DockerBuilderOptions,
+    supervisor::{self, SUPERVISOR_DIR},
+    ImageBuilder,
+};
+use crate::nixpacks::{
+    app,
+    environment::Environment,
+    files,
+    logger::Logger,
+    nix::{
+        cache::push_store_url, create_nix_expressions_for_phases, mirror::NixSources,
+        NIXPKGS_ARCHIVE,
+    },
+    plan::{phase::Phase, BuildPlan},
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use indoc::formatdoc;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+use tempdir::TempDir;
+use uuid::Uuid;
+
+pub mod layout;
+
+const APP_DIR: &str = "/app/";
+
+/// Fallback PATH entries, after the Nix environments.
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// Host files the phase commands can read, for name resolution while installing packages.
+const SANDBOX_HOST_FILES: &[&str] = &["/etc/resolv.conf", "/etc/hosts"];
+
+/// Mount points in the sandbox root, which are not part of the image.
+const SANDBOX_MOUNT_POINTS: &[&str] = &["app", "nix", "proc", "dev", "tmp"];
+
+/// Builds OCI images by running the build phases in a sandbox and assembling the layers itself, without a Docker daemon.
+pub struct OciImageBuilder {
+    logger: Logger,
+    options: DockerBuilderOptions,
+}
+
+#[async_trait]
+impl ImageBuilder for OciImageBuilder {
+    /// Build an OCI image layout (or archive) from a given BuildPlan.
+    async fn create_image(&self, app_src: &str, plan: &BuildPlan, env: &Environment) -> Result<()> {
+        ensure_nix_exists()?;
+        check_plan_is_supported(plan)?;
+
+        let name = self
+            .options
+            .name
+            .clone()
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        let staging = TempDir::new("nixpacks-oci").context("Creating a temp directory")?;
+        let app_dir = staging.path().join("app");
+        files::recursive_copy_dir(app_src, &app_dir).context("Copying app")?;
+
+        self.logger.log_section("Building Nix environments");
+        let envs = build_nix_envs(plan, staging.path())?;
+        let sandbox = Sandbox::create(plan, staging.path(), &app_dir, &envs)?;
+
+        let phases = plan.get_sorted_phases()?;
+        let mut phase_paths = Vec::new();
+        for phase in &phases {
+            phase_paths.extend(phase.paths.clone().unwrap_or_default());
+            self.run_phase(phase, plan, env, &envs, &phase_paths, &sandbox)?;
+        }
+
+        self.logger.log_section("Assembling image");
+        let (layout, archive_path) = match &self.options.out_dir {
+            Some(out_dir) => (OciLayout::create(out_dir)?, None),
+            None => (
+                OciLayout::create(staging.path().join("layout"))?,
+                Some(PathBuf::from(format!("{name}.tar"))),
+            ),
+        };
+
+        self.logger.log_step("Nix closure");
+        let closure = nix_closure(&envs)?;
+        let nix_layer = layout.write_layer(
+            "nix closure",
+            &closure
+                .iter()
+                .map(|path| LayerEntry::Path {
+                    source: path.clone(),
+                    target: path.to_string_lossy().trim_start_matches('/').to_string(),
+                })
+                .collect::<Vec<_>>(),
+        )?;
+
+        // Everything the phases wrote outside of the app, like a virtualenv in /opt
+        let mut layers = vec![nix_layer];
+        let build_entries = sandbox.layer_entries()?;
+        if !build_entries.is_empty() {
+            self.logger.log_step("Build outputs");
+            layers.push(layout.write_layer("build outputs", &build_entries)?);
+        }
+
+        self.logger.log_step("App");
+        let mut app_entries = vec![
+            LayerEntry::Path {
+                source: app_dir,
+                target: APP_DIR.trim_matches('/').to_string(),
+            },
+            LayerEntry::File {
+                target: "etc/profile".to_string(),
+                contents: profile_script(&envs).into_bytes(),
+            },
+        ];
+        let start = plan.start_phase.clone().unwrap_or_default();
+        for (name, contents) in supervisor::get_supervisor_files(&start) {
+            app_entries.push(LayerEntry::File {
+                target: format!("{}{name}", SUPERVISOR_DIR.trim_start_matches('/')),
+                contents: contents.into_bytes(),
+            });
+        }
+        for (asset, contents) in plan.static_assets.clone().unwrap_or_default() {
+            app_entries.push(LayerEntry::File {
+                target: format!("{}{asset}", app::ASSETS_DIR.trim_start_matches('/')),
+                contents: contents.into_bytes(),
+            });
+        }
+        layers.push(layout.write_layer("app", &app_entries)?);
+
+        let config = image_config(plan, &envs, &phase_paths, &self.options.labels)?;
+        let mut ref_names = vec![name.clone()];
+        ref_names.extend(self.options.tags.clone());
+        layout.write_image(&config, &layers, &ref_names)?;
+
+        if let Some(url) = self
+            .options
+            .push_nix_cache
+            .clone()
+            .or_else(|| plan.nix_cache.clone().and_then(|cache| cache.push))
+        {
+            self.logger
+                .log_step(&format!("Pushing Nix packages to {url}"));
+            let secret_key = self
+                .options
+                .nix_cache_secret_key
+                .as_ref()
+                .map(fs::canonicalize)
+                .transpose()
+                .context("Reading the Nix cache secret key")?;
+            push_nix_closure(&envs, &url, secret_key.as_deref())?;
+        }
+
+        self.logger.log_section("Successfully Built!");
+        if let Some(archive_path) = archive_path {
+            layout.write_archive(&archive_path)?;
+            println!("\nSaved OCI archive to:");
+            println!("  {}", archive_path.display());
+            println!("\nRun:");
+            println!("  podman run -it oci-archive:{}", archive_path.display());
+        } else {
+            println!("\nSaved OCI layout to:");
+            println!("  {}", layout.root().display());
+        }
+
+        Ok(())
+    }
+}
+
+impl OciImageBuilder {
+    pub fn new(logger: Logger, options: DockerBuilderOptions) -> OciImageBuilder {
+        OciImageBuilder { logger, options }
+    }
+
+    /// Run the commands of a phase in the sandbox, with the staged copy of the app at `/app`.
+    fn run_phase(
+        &self,
+        phase: &Phase,
+        plan: &BuildPlan,
+        env: &Environment,
+        envs: &[PathBuf],
+        phase_paths: &[String],
+        sandbox: &Sandbox,
+    ) -> Result<()> {
+        let cmds = phase.cmds.clone().unwrap_or_default();
+        if cmds.is_empty() {
+            return Ok(());
+        }
+
+        self.logger.log_section(&phase.get_name());
+
+        // Secrets are only passed to the commands, never to the image config
+        let all_secrets = plan.secrets.clone().unwrap_or_default();
+        let secrets = plan
+            .get_phase_secrets(phase)?
+            .into_iter()
+            .map(|id| {
+                let value = all_secrets[&id].read_value(&id, env)?;
+                Ok((id, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let paths = phase_paths
+            .iter()
+            .cloned()
+            .chain(env_bin_paths(envs))
+            .chain(std::iter::once(DEFAULT_PATH.to_string()))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let cache_dirs = phase.cache_directories.clone().unwrap_or_default();
+        for cmd in cmds {
+            self.logger.log_step(&cmd);
+            let status = sandbox
+                .command(&cache_dirs)?
+                .arg(base_env(envs).join("bin").join("bash"))
+                .arg("-c")
+                .arg(format!("{}\n{cmd}", source_profiles(envs)))
+                .env_clear()
+                .envs(plan.variables.clone().unwrap_or_default())
+                .envs(secrets.clone())
+                .env("PATH", paths.clone())
+                .env("HOME", "/root")
+                .env(
+                    "SSL_CERT_FILE",
+                    base_env(envs).join("etc/ssl/certs/ca-bundle.crt"),
+                )
+                .status()
+                .with_context(|| format!("Running `{cmd}`"))?;
+
+            if !status.success() {
+                bail!("Phase {} failed running `{cmd}`", phase.get_name());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An isolated root the phase commands run in, so that what they write outside of `/app` ends up in the image
+/// instead of on the host. The Nix store is read-only, and cache directories are kept out of the image.
+struct Sandbox {
+    bwrap: PathBuf,
+    root: PathBuf,
+    app_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl Sandbox {
+    /// Build bubblewrap with Nix and set up the root with the shell of the base environment at `/bin/sh`.
+    fn create(
+        plan: &BuildPlan,
+        staging: &Path,
+        app_dir: &Path,
+        envs: &[PathBuf],
+    ) -> Result<Sandbox> {
+        let nix_dir = staging.join("nix");
+        let file = nix_dir.join("sandbox.nix");
+        fs::write(&file, sandbox_nix_expression(plan.nix_sources()))?;
+        let bwrap = nix_build(plan, &file, &nix_dir.join("sandbox.nix-result"))?
+            .join("bin")
+            .join("bwrap");
+
+        let root = staging.join("root");
+        for dir in ["bin", "usr/bin", "root"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        symlink(&base_env(envs).join("bin/bash"), &root.join("bin/sh"))?;
+        symlink(&base_env(envs).join("bin/env"), &root.join("usr/bin/env"))?;
+
+        let cache_dir = staging.join("cache");
+        fs::create_dir_all(&cache_dir)?;
+
+        Ok(Sandbox {
+            bwrap,
+            root,
+            app_dir: app_dir.to_path_buf(),
+            cache_dir,
+        })
+    }
+
+    /// A `bwrap` command to run a program in the sandbox with, from `/app`.
+    fn command(&self, cache_dirs: &[String]) -> Result<Command> {
+        let mut cmd = Command::new(&self.bwrap);
+        cmd.args(["--unshare-user", "--uid", "0", "--gid", "0"])
+            .arg("--bind")
+            .arg(&self.root)
+            .arg("/")
+            .arg("--bind")
+            .arg(&self.app_dir)
+            .arg(APP_DIR)
+            .args(["--ro-bind", "/nix/store", "/nix/store"])
+            .args(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp"]);
+        for file in SANDBOX_HOST_FILES {
+            cmd.args(["--ro-bind-try", file, file]);
+        }
+
+        // Cache directories persist for the whole build, but are not part of the image
+        for dir in cache_dirs {
+            let target = sandbox_path(dir);
+            let source = self
+                .cache_dir
+                .join(target.trim_start_matches('/').replace('/', "%2f"));
+            fs::create_dir_all(&source)?;
+            cmd.arg("--bind").arg(source).arg(target);
+        }
+
+        cmd.args(["--chdir", APP_DIR, "--"]);
+        Ok(cmd)
+    }
+
+    /// The files the phases wrote to the root, without the mount points of the sandbox.
+    fn layer_entries(&self) -> Result<Vec<LayerEntry>> {
+        for file in SANDBOX_HOST_FILES {
+            let placeholder = self.root.join(file.trim_start_matches('/'));
+            if fs::metadata(&placeholder).map_or(false, |meta| meta.len() == 0) {
+                fs::remove_file(placeholder)?;
+            }
+        }
+        // Only removes the directories when empty, e.g. when the phases wrote nothing to /etc
+        for dir in ["etc", "root"] {
+            let _ = fs::remove_dir(self.root.join(dir));
+        }
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if SANDBOX_MOUNT_POINTS.contains(&name.as_str()) {
+                continue;
+            }
+
+            entries.push(LayerEntry::Path {
+                source: entry.path(),
+                target: name,
+            });
+        }
+        entries.sort_by(|a, b| match (a, b) {
+            (LayerEntry::Path { target: a, .. }, LayerEntry::Path { target: b, .. }) => a.cmp(b),
+            _ => std::cmp::Ordering::Equal,
+        });
+
+        Ok(entries)
+    }
+}
+
+/// The absolute path of a cache directory in the sandbox, e.g. `~/.npm` or `node_modules/.cache`.
+fn sandbox_path(dir: &str) -> String {
+    if let Some(rest) = dir.strip_prefix('~') {
+        format!("/root{rest}")
+    } else if dir.starts_with('/') {
+        dir.to_string()
+    } else {
+        format!("{APP_DIR}{}", dir.trim_start_matches("./"))
+    }
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(original, link)
+        .with_context(|| format!("Linking {} to {}", link.display(), original.display()))
+}
+
+#[cfg(not(unix))]
+fn symlink(_original: &Path, _link: &Path) -> Result<()> {
+    bail!("The OCI builder only runs on Unix")
+}
+
+/// The OCI builder can only produce what it can build on the host with Nix.
+fn check_plan_is_supported(plan: &BuildPlan) -> Result<()> {
+    let phases = plan.phases.clone().unwrap_or_default();
+    if let Some(phase) = phases
+        .values()
+        .find(|phase| !phase.apt_pkgs.clone().unwrap_or_default().is_empty())
+    {
+        bail!(
+            "The OCI builder does not support apt packages, but phase {} installs some",
+            phase.get_name()
+        );
+    }
+
+    let start = plan.start_phase.clone().unwrap_or_default();
+    if start.run_image.is_some() {
+        bail!("The OCI builder does not support start.runImage");
+    }
+    if start.is_closure_only() {
+        bail!("The OCI builder does not support start.closureOnly");
+    }
+    if start.user.is_some() {
+        bail!("The OCI builder does not support start.user");
+    }
+
+    Ok(())
+}
+
+fn ensure_nix_exists() -> Result<()> {
+    for program in ["nix-build", "nix-store"] {
+        if Command::new(program).arg("--version").output().is_err() {
+            bail!("Please install Nix to build images with the OCI builder: `{program}` was not found")
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the Nix environment of every phase, plus a base environment with the shell the image runs in.
+///
+/// The base environment is always first.
+fn build_nix_envs(plan: &BuildPlan, staging: &Path) -> Result<Vec<PathBuf>> {
+    let nix_dir = staging.join("nix");
+    fs::create_dir_all(&nix_dir)?;
+
+    let mut expressions = BTreeMap::from([(
+        "base.nix".to_string(),
+        base_nix_expression(plan.nix_sources()),
+    )]);
+    expressions.extend(create_nix_expressions_for_phases(
+        &plan.phases.clone().unwrap_or_default(),
+        plan.nix_sources(),
+        &staging.join("app").display().to_string(),
+    ));
+
+    // The base environment sorts first
+    expressions
+        .iter()
+        .map(|(file_name, expression)| {
+            let file = nix_dir.join(file_name);
+            fs::write(&file, expression)?;
+
+            nix_build(plan, &file, &nix_dir.join(format!("{file_name}-result")))
+        })
+        .collect()
+}
+
+/// Build a Nix file, returning the store path of the result.
+fn nix_build(plan: &BuildPlan, file: &Path, out_link: &Path) -> Result<PathBuf> {
+    let file_name = file
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+
+    let mut build_cmd = Command::new("nix-build");
+    build_cmd.arg(file).arg("--out-link").arg(out_link);
+    for (name, value) in plan.nix_cache.clone().unwrap_or_default().nix_settings() {
+        build_cmd.arg("--option").arg(name).arg(value);
+    }
+
+    let output = build_cmd
+        .output()
+        .with_context(|| format!("Building {file_name}"))?;
+    if !output.status.success() {
+        bail!(
+            "Building {file_name} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    match String::from_utf8_lossy(&output.stdout).lines().last() {
+        Some(path) => Ok(PathBuf::from(path.trim())),
+        None => bail!("nix-build did not output a path for {file_name}"),
+    }
+}
+
+fn base_nix_expression(sources: NixSources) -> String {
+    let source = sources.archive_source(NIXPKGS_ARCHIVE);
+    formatdoc! {"
+        let pkgs = import (fetchTarball {source}) {{ }};
+        in pkgs.buildEnv {{
+          name = \"nixpacks-oci-base\";
+          paths = with pkgs; [ bashInteractive coreutils cacert ];
+        }}
+    "}
+}
+
+/// Bubblewrap, to run the phases in a sandbox. It is only used while building, so it is not in the image.
+fn sandbox_nix_expression(sources: NixSources) -> String {
+    let source = sources.archive_source(NIXPKGS_ARCHIVE);
+    formatdoc! {"
+        let pkgs = import (fetchTarball {source}) {{ }};
+        in pkgs.bubblewrap
+    "}
+}
+
+/// Copies the environments and everything they depend on to a binary cache.
+fn push_nix_closure(envs: &[PathBuf], url: &str, secret_key: Option<&Path>) -> Result<()> {
+    let secret_key = secret_key.map(|path| path.display().to_string());
+    let output = Command::new("nix")
+        .args([
+            "--extra-experimental-features",
+            "nix-command",
+            "copy",
+            "--to",
+        ])
+        .arg(push_store_url(url, secret_key.as_deref()))
+        .args(envs)
+        .output()
+        .context("Running nix copy")?;
+    if !output.status.success() {
+        bail!(
+            "Pushing Nix packages to {url} failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+/// Every store path the environments depend on.
+fn nix_closure(envs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let output = Command::new("nix-store")
+        .arg("--query")
+        .arg("--requisites")
+        .args(envs)
+        .output()
+        .context("Querying the Nix closure")?;
+    if !output.status.success() {
+        bail!(
+            "Querying the Nix closure failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn base_env(envs: &[PathBuf]) -> &Path {
+    envs[0].as_path()
+}
+
+fn env_bin_paths(envs: &[PathBuf]) -> impl Iterator<Item = String> + '_ {
+    envs.iter()
+        .map(|env| env.join("bin").to_string_lossy().to_string())
+}
+
+/// Shell commands that load the `etc/profile.d` scripts of each environment, e.g. to set LD_LIBRARY_PATH.
+fn source_profiles(envs: &[PathBuf]) -> String {
+    envs.iter()
+        .map(|env| {
+            format!(
+                "for script in {}/etc/profile.d/*.sh; do [ -r \"$script\" ] && . \"$script\"; done",
+                env.display()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The `/etc/profile` the login shell of the entrypoint reads.
+fn profile_script(envs: &[PathBuf]) -> String {
+    format!("{}\n", source_profiles(envs))
+}
+
+fn image_config(
+    plan: &BuildPlan,
+    envs: &[PathBuf],
+    phase_paths: &[String],
+    labels: &[String],
+) -> Result<ImageConfig> {
+    let nixpacks_path = phase_paths.join(":");
+    let path = phase_paths
+        .iter()
+        .cloned()
+        .chain(env_bin_paths(envs))
+        .chain(std::iter::once(DEFAULT_PATH.to_string()))
+        .collect::<Vec<_>>()
+        .join(":");
+
+    let mut env = vec![
+        format!("PATH={path}"),
+        format!("NIXPACKS_PATH={nixpacks_path}"),
+        format!(
+            "SSL_CERT_FILE={}",
+            base_env(envs).join("etc/ssl/certs/ca-bundle.crt").display()
+        ),
+    ];
+    env.extend(
+        plan.variables
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|(name, value)| format!("{name}={value}")),
+    );
+
+    let start = plan.start_phase.clone().unwrap_or_default();
+    let healthcheck = match start.healthcheck.clone() {
+        Some(healthcheck) => Some(HealthcheckConfig {
+            cmd: healthcheck
+                .cmd
+                .clone()
+                .context("The healthcheck of the start phase is missing a command")?,
+            interval: healthcheck.get_interval()?,
+            timeout: healthcheck.get_timeout()?,
+            retries: healthcheck.retries,
+        }),
+        None => None,
+    };
+
+    Ok(ImageConfig {
+        env,
+        entrypoint: vec![
+            base_env(envs)
+                .join("bin")
+                .join("bash")
+                .display()
+                .to_string(),
+            "-l".to_string(),
+            "-c".to_string(),
+        ],
+        cmd: supervisor::get_start_cmd(&start).into_iter().collect(),
+        working_dir: APP_DIR.to_string(),
+        labels: labels
+            .iter()
+            .filter_map(|label| label.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        exposed_ports: start.ports.unwrap_or_default(),
+        healthcheck,
+        stop_signal: start.stop_signal,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_config() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+
+            [phases.install]
+            cmds = ["npm ci"]
+            paths = ["/app/node_modules/.bin"]
+
+            [start]
+            cmd = "npm run start"
+
+            [variables]
+            NODE_ENV = "production"
+            "#,
+        )
+        .unwrap();
+        let envs = vec![
+            PathBuf::from("/nix/store/abc-nixpacks-oci-base"),
+            PathBuf::from("/nix/store/def-env"),
+        ];
+
+        let config = image_config(
+            &plan,
+            &envs,
+            &["/app/node_modules/.bin".to_string()],
+            &["org.example.team=web".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.entrypoint,
+            vec!["/nix/store/abc-nixpacks-oci-base/bin/bash", "-l", "-c"]
+        );
+        assert_eq!(config.cmd, vec!["npm run start"]);
+        assert_eq!(config.working_dir, "/app/");
+        assert_eq!(
+            config.env,
+            vec![
+                "PATH=/app/node_modules/.bin:/nix/store/abc-nixpacks-oci-base/bin:/nix/store/def-env/bin:/usr/local/bin:/usr/bin:/bin",
+                "NIXPACKS_PATH=/app/node_modules/.bin",
+                "SSL_CERT_FILE=/nix/store/abc-nixpacks-oci-base/etc/ssl/certs/ca-bundle.crt",
+                "NODE_ENV=production",
+            ]
+        );
+        assert_eq!(
+            config.labels,
+            BTreeMap::from([("org.example.team".to_string(), "web".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_sandbox_path() {
+        assert_eq!(sandbox_path("~/.npm"), "/root/.npm");
+        assert_eq!(
+            sandbox_path("node_modules/.cache"),
+            "/app/node_modules/.cache"
+        );
+        assert_eq!(sandbox_path("./.next/cache"), "/app/.next/cache");
+        assert_eq!(sandbox_path("/var/cache/pip"), "/var/cache/pip");
+    }
+
+    #[test]
+    fn test_sandbox_layer_entries() {
+        let staging = TempDir::new("nixpacks-oci-test").unwrap();
+        let root = staging.path().join("root");
+        for dir in [
+            "app",
+            "nix",
+            "proc",
+            "dev",
+            "tmp",
+            "root",
+            "etc",
+            "opt/venv/bin",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("etc/resolv.conf"), "").unwrap();
+        fs::write(root.join("opt/venv/bin/python"), "").unwrap();
+
+        let sandbox = Sandbox {
+            bwrap: PathBuf::from("bwrap"),
+            root: root.clone(),
+            app_dir: staging.path().join("app"),
+            cache_dir: staging.path().join("cache"),
+        };
+        let targets = sandbox
+            .layer_entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| match entry {
+                LayerEntry::Path { target, .. } | LayerEntry::File { target, .. } => target,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(targets, vec!["opt".to_string()]);
+    }
+
+    #[test]
+    fn test_unsupported_plans() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            aptPkgs = ["curl"]
+            "#,
+        )
+        .unwrap();
+        assert!(check_plan_is_supported(&plan).is_err());
+
+        let plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "./app"
+            runImage = "ubuntu:jammy"
+            "#,
+        )
+        .unwrap();
+        assert!(check_plan_is_supported(&plan).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use crate::nixpacks::plan::phase::StartPhase;
+
+/// Where the supervisor and its list of processes are stored in the image.
+pub const SUPERVISOR_DIR: &str = "/nixpacks/supervisor/";
+
+const SUPERVISOR_SCRIPT: &str = include_str!("supervisor.sh");
+
+/// The command the image starts with: the supervisor when the start phase has processes, otherwise the start command.
+pub fn get_start_cmd(start: &StartPhase) -> Option<String> {
+    if start.processes.clone().unwrap_or_default().is_empty() {
+        start.cmd.clone()
+    } else {
+        Some(format!(
+            "bash {SUPERVISOR_DIR}supervisor.sh {}",
+            start.restart.unwrap_or_default()
+        ))
+    }
+}
+
+/// The files to write to the supervisor directory, if the start phase has processes.
+pub fn get_supervisor_files(start: &StartPhase) -> Vec<(String, String)> {
+    let processes = start.processes.clone().unwrap_or_default();
+    if processes.is_empty() {
+        return Vec::new();
+    }
+
+    let process_list = processes
+        .iter()
+        .map(|(name, cmd)| format!("{name}: {}\n", cmd.replace('\n', " ")))
+        .collect::<Vec<_>>()
+        .concat();
+
+    vec![
+        ("supervisor.sh".to_string(), SUPERVISOR_SCRIPT.to_string()),
+        ("processes".to_string(), process_list),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::plan::phase::RestartPolicy;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_single_process() {
+        let start = StartPhase::new("python main.py");
+        assert_eq!(get_start_cmd(&start), Some("python main.py".to_string()));
+        assert!(get_supervisor_files(&start).is_empty());
+    }
+
+    #[test]
+    fn test_multiple_processes() {
+        let start = StartPhase {
+            processes: Some(BTreeMap::from([
+                ("web".to_string(), "python main.py".to_string()),
+                ("worker".to_string(), "python worker.py".to_string()),
+            ])),
+            restart: Some(RestartPolicy::OnFailure),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_start_cmd(&start),
+            Some("bash /nixpacks/supervisor/supervisor.sh on-failure".to_string())
+        );
+        assert_eq!(
+            get_supervisor_files(&start)[1],
+            (
+                "processes".to_string(),
+                "web: python main.py\nworker: python worker.py\n".to_string()
+            )
+        );
+    }
+}
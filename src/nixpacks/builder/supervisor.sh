@@ -0,0 +1,90 @@
+#!/usr/bin/env bash
+# Runs the processes of the start phase in one container.
+#
+# Usage: supervisor.sh <restart policy>
+#
+# Processes are read from the `processes` file next to this script, one `name: command` per line.
+# Set NIXPACKS_PROCESS to the name of a process to only run that one.
+#
+# The restart policy is one of:
+#   never       a process that exits is not restarted
+#   on-failure  a process that exits with a non-zero code is restarted
+#   always      a process that exits is restarted
+#
+# When a process fails and is not restarted, the others are stopped and the supervisor exits with its code.
+# SIGTERM and SIGINT are forwarded to every process.
+
+restart="${1:-never}"
+processes_file="$(dirname "${BASH_SOURCE[0]}")/processes"
+
+declare -A commands
+names=()
+while IFS= read -r line || [ -n "$line" ]; do
+  [ -z "$line" ] && continue
+  name="${line%%:*}"
+  commands["$name"]="${line#*: }"
+  names+=("$name")
+done <"$processes_file"
+
+if [ -n "$NIXPACKS_PROCESS" ]; then
+  if [ -z "${commands[$NIXPACKS_PROCESS]+set}" ]; then
+    echo "Unknown process $NIXPACKS_PROCESS, expected one of: ${names[*]}" >&2
+    exit 1
+  fi
+
+  # A single process doesn't need supervising
+  if [ "$restart" = "never" ]; then
+    exec bash -c "${commands[$NIXPACKS_PROCESS]}"
+  fi
+
+  names=("$NIXPACKS_PROCESS")
+fi
+
+supervise() {
+  local name="$1" child code
+  trap 'kill -TERM "$child" 2>/dev/null; wait "$child"; exit 143' TERM INT
+
+  while true; do
+    bash -c "${commands[$name]}" &
+    child=$!
+    wait "$child"
+    code=$?
+
+    case "$restart" in
+    always) ;;
+    on-failure) [ "$code" -eq 0 ] && exit 0 ;;
+    *) exit "$code" ;;
+    esac
+
+    echo "Process $name exited with code $code, restarting" >&2
+    sleep 1
+  done
+}
+
+pids=()
+for name in "${names[@]}"; do
+  echo "Starting process $name: ${commands[$name]}"
+  supervise "$name" &
+  pids+=("$!")
+done
+
+stop() {
+  trap - TERM INT
+  kill -TERM "${pids[@]}" 2>/dev/null
+  wait
+  exit "$1"
+}
+trap 'stop 143' TERM
+trap 'stop 130' INT
+
+remaining=${#pids[@]}
+while [ "$remaining" -gt 0 ]; do
+  wait -n
+  code=$?
+  remaining=$((remaining - 1))
+
+  if [ "$code" -ne 0 ]; then
+    echo "A process exited with code $code, stopping the others" >&2
+    stop "$code"
+  fi
+done
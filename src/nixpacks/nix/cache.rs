@@ -0,0 +1,121 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Where the secret key a pushed closure is signed with is mounted in the push container.
+pub const PUSH_SECRET_KEY_PATH: &str = "/run/nixpacks/nix-cache-secret-key";
+
+/// Binary caches the Nix packages of a build are substituted from, and pushed to after the build.
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NixCacheConfig {
+    /// Binary caches to try before building or downloading from cache.nixos.org.
+    pub substituters: Option<Vec<String>>,
+
+    /// Keys the substituted paths may be signed with, e.g. `cache.example.com-1:<key>`.
+    pub trusted_public_keys: Option<Vec<String>>,
+
+    /// Binary cache the Nix packages of the image are pushed to after a successful build.
+    pub push: Option<String>,
+}
+
+impl NixCacheConfig {
+    /// The Nix settings that add the substituters and keys.
+    pub fn nix_settings(&self) -> Vec<(&'static str, String)> {
+        let mut settings = Vec::new();
+        let substituters = self.substituters.clone().unwrap_or_default();
+        if !substituters.is_empty() {
+            settings.push(("extra-substituters", substituters.join(" ")));
+        }
+
+        let keys = self.trusted_public_keys.clone().unwrap_or_default();
+        if !keys.is_empty() {
+            settings.push(("extra-trusted-public-keys", keys.join(" ")));
+        }
+
+        settings
+    }
+
+    /// The lines added to the build image's `nix.conf`.
+    pub fn nix_conf_lines(&self) -> Vec<String> {
+        self.nix_settings()
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}"))
+            .collect()
+    }
+
+    /// The directories of the `file://` substituters, which are mounted into the build.
+    pub fn local_substituters(&self) -> Vec<PathBuf> {
+        self.substituters
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|url| local_store_dir(url))
+            .collect()
+    }
+}
+
+/// The directory of a `file://` binary cache, without its store parameters.
+pub fn local_store_dir(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://")?;
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    Some(PathBuf::from(path))
+}
+
+/// The store URL a closure is pushed with, signing it with the secret key file if there is one.
+pub fn push_store_url(url: &str, secret_key: Option<&str>) -> String {
+    match secret_key {
+        Some(secret_key) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{url}{separator}secret-key={secret_key}")
+        }
+        None => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nix_conf_lines() {
+        let config = NixCacheConfig {
+            substituters: Some(vec![
+                "https://cache.example.com".to_string(),
+                "file:///var/cache/nix?trusted=1".to_string(),
+            ]),
+            trusted_public_keys: Some(vec!["cache.example.com-1:abc=".to_string()]),
+            push: None,
+        };
+
+        assert_eq!(
+            config.nix_conf_lines(),
+            vec![
+                "extra-substituters = https://cache.example.com file:///var/cache/nix?trusted=1",
+                "extra-trusted-public-keys = cache.example.com-1:abc="
+            ]
+        );
+        assert_eq!(
+            config.local_substituters(),
+            vec![PathBuf::from("/var/cache/nix")]
+        );
+        assert!(NixCacheConfig::default().nix_conf_lines().is_empty());
+    }
+
+    #[test]
+    fn test_push_store_url() {
+        assert_eq!(
+            push_store_url("file:///var/cache/nix", Some(PUSH_SECRET_KEY_PATH)),
+            format!("file:///var/cache/nix?secret-key={PUSH_SECRET_KEY_PATH}")
+        );
+        assert_eq!(
+            push_store_url("s3://cache?region=eu-west-1", Some(PUSH_SECRET_KEY_PATH)),
+            format!("s3://cache?region=eu-west-1&secret-key={PUSH_SECRET_KEY_PATH}")
+        );
+        assert_eq!(
+            push_store_url("https://cache.example.com", None),
+            "https://cache.example.com"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+use super::{group_nix_packages_by_archive, nix_exports, NixGroup, NIXPKGS_ARCHIVE};
+use crate::nixpacks::plan::BuildPlan;
+use indoc::formatdoc;
+use std::{collections::BTreeMap, fmt::Write as _};
+
+/// Systems the development shells are available on.
+const SYSTEMS: &[&str] = &[
+    "x86_64-linux",
+    "aarch64-linux",
+    "x86_64-darwin",
+    "aarch64-darwin",
+];
+
+/// Generates a `flake.nix` with a development shell for each nixpkgs archive the plan installs packages from.
+/// The first archive group is the `default` shell, the others are named `nixpkgs-<archive>`.
+pub fn create_flake_for_plan(plan: &BuildPlan) -> String {
+    let groups = group_nix_packages_by_archive(
+        &plan
+            .phases
+            .clone()
+            .unwrap_or_default()
+            .into_values()
+            .collect::<Vec<_>>(),
+    );
+
+    // Overlays are fetched as inputs, as pure evaluation doesn't allow unpinned tarballs
+    let mut overlay_inputs: BTreeMap<String, String> = BTreeMap::new();
+    for url in groups.iter().flat_map(|group| group.overlays.iter()) {
+        if !overlay_inputs.contains_key(url) {
+            let name = format!("overlay-{}", overlay_inputs.len());
+            overlay_inputs.insert(url.clone(), name);
+        }
+    }
+
+    let mut inputs = groups
+        .iter()
+        .map(|group| {
+            format!(
+                "{}.url = \"github:NixOS/nixpkgs/{}\";",
+                input_name(group),
+                group_archive(group)
+            )
+        })
+        .collect::<Vec<_>>();
+    inputs.extend(
+        overlay_inputs
+            .iter()
+            .map(|(url, name)| format!("{name} = {{ url = \"{url}\"; flake = false; }};")),
+    );
+
+    let variables = plan
+        .variables
+        .clone()
+        .unwrap_or_default()
+        .iter()
+        .map(|(name, value)| format!("export {name}={}\n", escape_nix_string(&shell_quote(value))))
+        .collect::<Vec<_>>()
+        .concat();
+
+    let shells = groups
+        .iter()
+        .enumerate()
+        .map(|(i, group)| {
+            let name = if i == 0 {
+                "default".to_string()
+            } else {
+                input_name(group)
+            };
+            dev_shell_for_group(&name, group, &overlay_inputs)
+        })
+        .collect::<Vec<_>>();
+
+    let systems = SYSTEMS
+        .iter()
+        .map(|system| format!("\"{system}\""))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    formatdoc! {"
+        # Generated by Nixpacks. Run `nix develop` to enter the same environment the app is built with.
+        {{
+          description = \"Development environment generated by Nixpacks\";
+
+          inputs = {{
+        {inputs}
+          }};
+
+          outputs = {{ self, ... }}@inputs:
+            let
+              systems = [ {systems} ];
+              forAllSystems = f: builtins.listToAttrs (map (system: {{ name = system; value = f system; }}) systems);
+              variables = ''
+        {variables}
+              '';
+            in
+            {{
+              devShells = forAllSystems (system: {{
+        {shells}
+              }});
+            }};
+        }}
+    ",
+    inputs = indent(&inputs.join("\n"), 4),
+    systems = systems,
+    variables = indent(variables.trim_end(), 8),
+    shells = indent(&shells.join("\n"), 8),
+    }
+}
+
+fn dev_shell_for_group(
+    name: &str,
+    group: &NixGroup,
+    overlay_inputs: &BTreeMap<String, String>,
+) -> String {
+    let mut pkgs = group.pkgs.clone();
+    pkgs.sort();
+    pkgs.dedup();
+    pkgs.extend(group.expressions.iter().map(|expr| format!("({expr})")));
+    pkgs.extend(
+        group
+            .nix_files
+            .iter()
+            .map(|file| format!("(callPackage ./{} {{ }})", file.trim_start_matches("./"))),
+    );
+
+    let mut libs = group.libs.clone();
+    libs.sort();
+    libs.dedup();
+
+    let overlays = group
+        .overlays
+        .iter()
+        .filter_map(|url| overlay_inputs.get(url))
+        .map(|input| format!("(import inputs.{input})"))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let mut library_exports = String::new();
+    if !libs.is_empty() {
+        let _ = writeln!(
+            library_exports,
+            "export LD_LIBRARY_PATH=\"${{lib.makeLibraryPath [ {} ]}}:$LD_LIBRARY_PATH\"",
+            libs.join(" ")
+        );
+    }
+    library_exports.push_str(&nix_exports(&group.exports));
+
+    let shell_hook = if library_exports.is_empty() {
+        "variables".to_string()
+    } else {
+        format!(
+            "''\n{}\n''\n+ variables",
+            indent(library_exports.trim_end(), 2)
+        )
+    };
+
+    formatdoc! {"
+        {name} =
+          let pkgs = import inputs.{input} {{ inherit system; overlays = [ {overlays} ]; }};
+          in with pkgs; mkShell {{
+            packages = [ {pkgs} ];
+            shellHook = {shell_hook};
+          }};
+    ",
+    name = name,
+    input = input_name(group),
+    overlays = overlays,
+    pkgs = pkgs.join(" "),
+    shell_hook = indent(&shell_hook, 4).trim_start(),
+    }
+}
+
+fn group_archive(group: &NixGroup) -> String {
+    group
+        .archive
+        .clone()
+        .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string())
+}
+
+fn input_name(group: &NixGroup) -> String {
+    match &group.archive {
+        Some(archive) => format!("nixpkgs-{archive}"),
+        None => "nixpkgs".to_string(),
+    }
+}
+
+/// Indent every non-empty line by the given number of spaces.
+fn indent(s: &str, spaces: usize) -> String {
+    let prefix = " ".repeat(spaces);
+    s.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Escape a value for use in a Nix indented string.
+fn escape_nix_string(value: &str) -> String {
+    value.replace("''", "'''").replace("${", "''${")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_flake_for_plan() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs", "yarn"]
+            nixLibs = ["openssl"]
+            nixExports = { OPENSSL_DIR = "${openssl.dev}" }
+            nixOverlays = ["https://github.com/railwayapp/nix-npm-overlay/archive/main.tar.gz"]
+
+            [phases.legacy]
+            nixPkgs = ["python27"]
+            nixpkgsArchive = "abc123"
+
+            [variables]
+            NODE_ENV = "production"
+            GREETING = "it's ${HOME}"
+            "#,
+        )
+        .unwrap();
+
+        let flake = create_flake_for_plan(&plan);
+
+        assert!(flake.contains(&format!(
+            "nixpkgs.url = \"github:NixOS/nixpkgs/{NIXPKGS_ARCHIVE}\";"
+        )));
+        assert!(flake.contains("nixpkgs-abc123.url = \"github:NixOS/nixpkgs/abc123\";"));
+        assert!(flake.contains("overlay-0 = { url = \"https://github.com/railwayapp/nix-npm-overlay/archive/main.tar.gz\"; flake = false; };"));
+
+        assert!(flake.contains("default =\n"));
+        assert!(flake.contains(
+            "import inputs.nixpkgs { inherit system; overlays = [ (import inputs.overlay-0) ]; }"
+        ));
+        assert!(flake.contains("packages = [ nodejs yarn ];"));
+        assert!(flake.contains(
+            "export LD_LIBRARY_PATH=\"${lib.makeLibraryPath [ openssl ]}:$LD_LIBRARY_PATH\""
+        ));
+        assert!(flake.contains("export OPENSSL_DIR=\"${openssl.dev}\""));
+
+        assert!(flake.contains("nixpkgs-abc123 =\n"));
+        assert!(flake.contains("packages = [ python27 ];"));
+
+        assert!(flake.contains("export NODE_ENV='production'"));
+        // `'''` and `''${` are how `''` and `${` are written in Nix indented strings
+        assert!(flake.contains("export GREETING='it'\\'''s ''${HOME}'"));
+    }
+}
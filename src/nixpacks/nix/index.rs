@@ -0,0 +1,414 @@
+use super::{mirror::NixSources, NIXPKGS_ARCHIVE};
+use crate::nixpacks::plan::phase::Phases;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::PathBuf,
+    process::Command,
+};
+
+/// The largest edit distance at which an attribute is suggested for an unknown package.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The top-level attributes of a nixpkgs revision, used to check package names before building.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NixpkgsIndex {
+    attributes: BTreeSet<String>,
+}
+
+impl NixpkgsIndex {
+    pub fn from_attributes<I: IntoIterator<Item = String>>(attributes: I) -> Self {
+        NixpkgsIndex {
+            attributes: attributes.into_iter().collect(),
+        }
+    }
+
+    /// Load the index of an archive from the cache, or evaluate it with a local Nix installation.
+    ///
+    /// Returns `None` when neither is available, or with a warning when the evaluation fails, e.g. offline.
+    pub fn load(archive: &str, sources: NixSources) -> Result<Option<Self>> {
+        let cache_file = get_cache_dir().map(|dir| dir.join(get_cache_file_name(archive, sources)));
+        if let Some(cache_file) = &cache_file {
+            if cache_file.exists() {
+                let contents = fs::read_to_string(cache_file)?;
+                let attributes: Vec<String> = serde_json::from_str(&contents)
+                    .with_context(|| format!("Reading {}", cache_file.display()))?;
+                return Ok(Some(NixpkgsIndex::from_attributes(attributes)));
+            }
+        }
+
+        if Command::new("nix-instantiate")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        let expression = format!(
+            "builtins.attrNames (import (fetchTarball {}) {{ }})",
+            sources.archive_source(archive)
+        );
+        let output = Command::new("nix-instantiate")
+            .args(["--eval", "--strict", "--json", "--expr", &expression])
+            .output()
+            .context("Running nix-instantiate")?;
+        // The packages are checked again when they are built, so this doesn't fail the build
+        if !output.status.success() {
+            eprintln!(
+                "Warning: Listing the packages of nixpkgs {archive} failed, so they are not checked: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return Ok(None);
+        }
+
+        let attributes: Vec<String> = serde_json::from_slice(&output.stdout)
+            .context("Reading package names from nix-instantiate")?;
+
+        // The index is only a cache, so failing to write it doesn't fail the build
+        if let (Some(cache_file), Ok(contents)) = (&cache_file, serde_json::to_string(&attributes))
+        {
+            if let Some(dir) = cache_file.parent() {
+                let _ = fs::create_dir_all(dir).and_then(|()| fs::write(cache_file, contents));
+            }
+        }
+
+        Ok(Some(NixpkgsIndex::from_attributes(attributes)))
+    }
+
+    pub fn contains(&self, attribute: &str) -> bool {
+        self.attributes.contains(attribute)
+    }
+
+    /// The attributes that contain the query, ignoring case. Exact matches come first, then those starting with the query.
+    pub fn search(&self, query: &str) -> Vec<String> {
+        let query = query.to_lowercase();
+        let mut matches = self
+            .attributes
+            .iter()
+            .filter_map(|attribute| {
+                let lowercase = attribute.to_lowercase();
+                let rank = if lowercase == query {
+                    0
+                } else if lowercase.starts_with(&query) {
+                    1
+                } else if lowercase.contains(&query) {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, attribute))
+            })
+            .collect::<Vec<_>>();
+        matches.sort();
+
+        matches
+            .into_iter()
+            .map(|(_, attribute)| attribute.clone())
+            .collect()
+    }
+
+    /// The attributes closest to a misspelled one, closest first.
+    pub fn suggest(&self, attribute: &str) -> Vec<String> {
+        let mut candidates = self
+            .attributes
+            .iter()
+            .map(|other| (strsim::levenshtein(attribute, other), other))
+            .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, other)| other.clone())
+            .collect()
+    }
+}
+
+/// Fail when a phase installs a package or library its nixpkgs archive doesn't have.
+///
+/// Archives whose index isn't available are not checked, nor are archives with overlays, which can add any attribute.
+pub fn check_nix_packages(phases: &Phases, sources: NixSources) -> Result<()> {
+    let mut indexes = BTreeMap::new();
+    for archive in get_checked_archives(phases) {
+        if let Some(index) = NixpkgsIndex::load(&archive, sources)? {
+            indexes.insert(archive, index);
+        }
+    }
+
+    let issues = find_unknown_packages(phases, &indexes);
+    match issues.as_slice() {
+        [] => Ok(()),
+        [issue] => bail!("{issue}"),
+        issues => bail!(
+            "{} unknown Nix packages:\n{}",
+            issues.len(),
+            issues
+                .iter()
+                .map(|issue| format!("  {issue}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+/// The archives of the phases that install packages, except those with overlays.
+fn get_checked_archives(phases: &Phases) -> BTreeSet<String> {
+    let mut archives = BTreeSet::new();
+    let mut overlaid = BTreeSet::new();
+    for phase in phases.values().filter(|phase| phase.uses_nix()) {
+        let archive = phase
+            .nixpkgs_archive
+            .clone()
+            .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string());
+        if phase.nix_overlays.clone().unwrap_or_default().is_empty() {
+            archives.insert(archive);
+        } else {
+            overlaid.insert(archive);
+        }
+    }
+
+    archives.difference(&overlaid).cloned().collect()
+}
+
+fn find_unknown_packages(phases: &Phases, indexes: &BTreeMap<String, NixpkgsIndex>) -> Vec<String> {
+    let mut issues = Vec::new();
+    for (name, phase) in phases {
+        let archive = phase
+            .nixpkgs_archive
+            .clone()
+            .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string());
+        let index = match indexes.get(&archive) {
+            Some(index) => index,
+            None => continue,
+        };
+
+        let pkgs = phase.nix_pkgs.clone().unwrap_or_default();
+        let libs = phase.nix_libs.clone().unwrap_or_default();
+        for attribute in pkgs
+            .iter()
+            .chain(libs.iter())
+            .flat_map(|pkg| referenced_attributes(pkg))
+        {
+            if index.contains(&attribute) {
+                continue;
+            }
+
+            let suggestions = index.suggest(&attribute);
+            let hint = if suggestions.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    ", did you mean {}?",
+                    suggestions
+                        .iter()
+                        .map(|s| format!("`{s}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            };
+            issues.push(format!(
+                "phase `{name}` installs `{attribute}`, which is not in nixpkgs {archive}{hint}"
+            ));
+        }
+    }
+
+    issues.dedup();
+    issues
+}
+
+/// The top-level attributes a package expression refers to, e.g. `nodePackages` for `nodePackages.pnpm`,
+/// or `cowsay` and `hello` for `(cowsay.override { hello = hello; })`.
+pub(crate) fn referenced_attributes(pkg: &str) -> Vec<String> {
+    if pkg == "..." {
+        return Vec::new();
+    }
+
+    let mut attributes = Vec::new();
+    let expression = pkg.trim().trim_start_matches('(');
+    if let Some(attribute) = leading_attribute(expression) {
+        attributes.push(attribute);
+    }
+
+    // Override values are usually other packages
+    if let Some((_, overrides)) = expression.split_once(".override {") {
+        for value in overrides
+            .split(';')
+            .filter_map(|assignment| assignment.split_once('=').map(|(_, value)| value.trim()))
+        {
+            // Only plain attribute paths, not other expressions
+            let first_segment = value.split('.').next().unwrap_or_default();
+            if let Some(attribute) = leading_attribute(value) {
+                if attribute == first_segment {
+                    attributes.push(attribute);
+                }
+            }
+        }
+    }
+
+    attributes
+}
+
+/// The first segment of an attribute path, if the expression starts with one.
+fn leading_attribute(expression: &str) -> Option<String> {
+    let attribute = expression
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\''))
+        .collect::<String>();
+
+    let starts_like_identifier = attribute
+        .chars()
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_');
+    if starts_like_identifier {
+        Some(attribute)
+    } else {
+        None
+    }
+}
+
+/// The file an index is cached in. A locked archive is keyed by its hash too, since the same name can be locked to
+/// different contents.
+fn get_cache_file_name(archive: &str, sources: NixSources) -> String {
+    match sources.lock.and_then(|lock| lock.archives.get(archive)) {
+        Some(locked) => format!("nixpkgs-{archive}-{}.json", locked.sha256),
+        None => format!("nixpkgs-{archive}.json"),
+    }
+}
+
+/// Where package indexes are cached between builds.
+fn get_cache_dir() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("nixpacks").join("nixpkgs-index"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::{
+        nix::{
+            lock::{LockedArchive, NixLock},
+            pkg::Pkg,
+        },
+        plan::phase::Phase,
+    };
+
+    fn get_index() -> NixpkgsIndex {
+        NixpkgsIndex::from_attributes(
+            [
+                "nodejs_18",
+                "nodejs_20",
+                "nodePackages",
+                "python3",
+                "python311",
+                "openssl",
+                "cowsay",
+                "hello",
+            ]
+            .map(ToString::to_string),
+        )
+    }
+
+    #[test]
+    fn test_referenced_attributes() {
+        assert_eq!(referenced_attributes("nodejs_18"), vec!["nodejs_18"]);
+        assert_eq!(
+            referenced_attributes("nodePackages.pnpm"),
+            vec!["nodePackages"]
+        );
+        assert_eq!(
+            referenced_attributes(
+                &Pkg::new("cowsay")
+                    .set_override("hello", "hello")
+                    .to_nix_string()
+            ),
+            vec!["cowsay", "hello"]
+        );
+        assert_eq!(
+            referenced_attributes("(php.withExtensions (pe: pe.enabled))"),
+            vec!["php"]
+        );
+        assert!(referenced_attributes("...").is_empty());
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(get_index().suggest("pyhton3"), vec!["python3"]);
+        assert_eq!(
+            get_index().suggest("nodejs_99"),
+            vec!["nodejs_18", "nodejs_20"]
+        );
+        assert!(get_index().suggest("ffmpeg").is_empty());
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(get_index().search("python3"), vec!["python3", "python311"]);
+        assert_eq!(
+            get_index().search("NODE"),
+            vec!["nodePackages", "nodejs_18", "nodejs_20"]
+        );
+        assert_eq!(get_index().search("ssl"), vec!["openssl"]);
+        assert!(get_index().search("ffmpeg").is_empty());
+    }
+
+    #[test]
+    fn test_find_unknown_packages() {
+        let mut setup = Phase::setup(Some(vec![Pkg::new("nodejs_99"), Pkg::new("pyhton3")]));
+        setup.add_pkgs_libs(vec!["openssl".to_string()]);
+        let mut legacy = Phase::new("legacy");
+        legacy.add_nix_pkgs(&[Pkg::new("python27")]);
+        legacy.nixpkgs_archive = Some("other".to_string());
+        let phases = Phases::from([("setup".to_string(), setup), ("legacy".to_string(), legacy)]);
+
+        let indexes = BTreeMap::from([(NIXPKGS_ARCHIVE.to_string(), get_index())]);
+        assert_eq!(
+            find_unknown_packages(&phases, &indexes),
+            vec![
+                format!("phase `setup` installs `nodejs_99`, which is not in nixpkgs {NIXPKGS_ARCHIVE}, did you mean `nodejs_18`, `nodejs_20`?"),
+                format!("phase `setup` installs `pyhton3`, which is not in nixpkgs {NIXPKGS_ARCHIVE}, did you mean `python3`?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_file_name() {
+        let lock = NixLock {
+            archives: BTreeMap::from([(
+                "nixos-unstable".to_string(),
+                LockedArchive {
+                    url: "https://example.com/nixpkgs.tar.gz".to_string(),
+                    sha256: "archivehash".to_string(),
+                    packages: BTreeMap::new(),
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            get_cache_file_name("nixos-unstable", NixSources::default()),
+            "nixpkgs-nixos-unstable.json"
+        );
+        assert_eq!(
+            get_cache_file_name("nixos-unstable", NixSources::new(Some(&lock), None)),
+            "nixpkgs-nixos-unstable-archivehash.json"
+        );
+    }
+
+    #[test]
+    fn test_overlaid_archives_are_not_checked() {
+        let mut setup = Phase::setup(Some(vec![Pkg::new("rust-bin")]));
+        setup.nix_overlays = Some(vec![
+            "https://github.com/oxalica/rust-overlay/archive/master.tar.gz".to_string(),
+        ]);
+        let phases = Phases::from([("setup".to_string(), setup)]);
+
+        assert!(get_checked_archives(&phases).is_empty());
+    }
+}
@@ -0,0 +1,416 @@
+use super::{
+    group_nix_packages_by_archive, mirror::NixSources, nixpkgs_archive_url, NixGroup,
+    NIXPKGS_ARCHIVE,
+};
+use crate::nixpacks::{app::App, plan::phase::Phases};
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, process::Command};
+
+pub const NIX_LOCK_FILE: &str = "nixpacks.lock";
+
+const NIX_LOCK_VERSION: u32 = 1;
+
+/// Pins the nixpkgs archives and overlays of a build plan, so the same plan always installs the same packages.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+pub struct NixLock {
+    pub version: u32,
+
+    /// Archives by nixpkgs commit.
+    #[serde(default)]
+    pub archives: BTreeMap<String, LockedArchive>,
+
+    /// Overlays by the URL the plan references them with.
+    #[serde(default)]
+    pub overlays: BTreeMap<String, LockedOverlay>,
+}
+
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+pub struct LockedArchive {
+    pub url: String,
+    pub sha256: String,
+
+    /// The versions the packages installed from this archive resolved to.
+    #[serde(default)]
+    pub packages: BTreeMap<String, String>,
+}
+
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+pub struct LockedOverlay {
+    /// Tarball of the overlay at `rev`.
+    pub url: String,
+    pub rev: String,
+    pub sha256: String,
+}
+
+impl NixLock {
+    /// Read the lockfile of an app, if it has one.
+    pub fn read(app: &App) -> Result<Option<NixLock>> {
+        if !app.includes_file(NIX_LOCK_FILE) {
+            return Ok(None);
+        }
+
+        let lock: NixLock = app.read_toml(NIX_LOCK_FILE)?;
+        if lock.version != NIX_LOCK_VERSION {
+            bail!(
+                "{NIX_LOCK_FILE} has version {}, but only version {NIX_LOCK_VERSION} is supported. Run `nixpacks lock --update` to recreate it",
+                lock.version
+            );
+        }
+
+        Ok(Some(lock))
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(format!(
+            "# This file is generated by `nixpacks lock`. Do not edit it by hand.\n\n{}",
+            toml::to_string_pretty(self)?
+        ))
+    }
+
+    /// Resolve the archives, overlays and packages the phases use. Entries of the previous lock are kept as they are.
+    pub fn resolve(phases: &Phases, previous: Option<&NixLock>) -> Result<NixLock> {
+        let previous = previous.cloned().unwrap_or_default();
+        let mut lock = NixLock {
+            version: NIX_LOCK_VERSION,
+            ..Default::default()
+        };
+
+        let groups = get_groups(phases);
+        for url in groups.iter().flat_map(|group| group.overlays.iter()) {
+            if lock.overlays.contains_key(url) {
+                continue;
+            }
+
+            let overlay = if let Some(overlay) = previous.overlays.get(url) {
+                overlay.clone()
+            } else {
+                let (pinned_url, rev) = pin_github_archive(url)?;
+                let sha256 = prefetch_tarball(&pinned_url)?;
+                LockedOverlay {
+                    url: pinned_url,
+                    rev,
+                    sha256,
+                }
+            };
+            lock.overlays.insert(url.clone(), overlay);
+        }
+
+        for group in &groups {
+            let archive = group_archive(group);
+            let mut locked = if let Some(locked) = previous.archives.get(&archive) {
+                locked.clone()
+            } else {
+                let url = nixpkgs_archive_url(&archive);
+                let sha256 = prefetch_tarball(&url)?;
+                LockedArchive {
+                    url,
+                    sha256,
+                    packages: BTreeMap::new(),
+                }
+            };
+
+            locked.packages.retain(|pkg, _| group.pkgs.contains(pkg));
+            let unresolved = group
+                .pkgs
+                .iter()
+                .filter(|pkg| !locked.packages.contains_key(*pkg))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !unresolved.is_empty() {
+                let versions = lock.resolve_package_versions(&locked, group, &unresolved)?;
+                locked.packages.extend(versions);
+            }
+
+            lock.archives.insert(archive, locked);
+        }
+
+        Ok(lock)
+    }
+
+    /// Describe what the phases use that isn't in the lockfile.
+    pub fn missing_entries(&self, phases: &Phases) -> Vec<String> {
+        let mut missing = Vec::new();
+        for group in get_groups(phases) {
+            let archive = group_archive(&group);
+            match self.archives.get(&archive) {
+                Some(locked) => missing.extend(
+                    group
+                        .pkgs
+                        .iter()
+                        .filter(|pkg| !locked.packages.contains_key(*pkg))
+                        .map(|pkg| format!("package {pkg}")),
+                ),
+                None => missing.push(format!("nixpkgs archive {archive}")),
+            }
+
+            missing.extend(
+                group
+                    .overlays
+                    .iter()
+                    .filter(|url| !self.overlays.contains_key(*url))
+                    .map(|url| format!("overlay {url}")),
+            );
+        }
+
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+
+    /// Evaluate the versions of packages, with the archive and overlays they are installed from.
+    fn resolve_package_versions(
+        &self,
+        archive: &LockedArchive,
+        group: &NixGroup,
+        pkgs: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        let overlays = group
+            .overlays
+            .iter()
+            .map(|url| {
+                format!(
+                    "(import (builtins.fetchTarball {}))",
+                    NixSources::new(Some(self), None).overlay_source(url)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let expression = format!(
+            "let pkgs = import (fetchTarball {}) {{ overlays = [ {overlays} ]; }}; in with pkgs; \
+             map (p: p.version or (if p ? name then (builtins.parseDrvName p.name).version else \"\")) [ {} ]",
+            fetch_tarball_args(&archive.url, &archive.sha256),
+            pkgs.join(" "),
+        );
+
+        let output = Command::new("nix-instantiate")
+            .args(["--eval", "--strict", "--json", "--expr", &expression])
+            .output()
+            .context("Running nix-instantiate. Nix is needed to resolve package versions")?;
+        if !output.status.success() {
+            bail!(
+                "Resolving package versions failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        let versions: Vec<String> = serde_json::from_slice(&output.stdout)
+            .context("Reading package versions from nix-instantiate")?;
+
+        Ok(pkgs.iter().cloned().zip(versions).collect())
+    }
+}
+
+fn get_groups(phases: &Phases) -> Vec<NixGroup> {
+    group_nix_packages_by_archive(&phases.values().cloned().collect::<Vec<_>>())
+}
+
+fn group_archive(group: &NixGroup) -> String {
+    group
+        .archive
+        .clone()
+        .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string())
+}
+
+fn fetch_tarball_args(url: &str, sha256: &str) -> String {
+    format!("{{ url = \"{url}\"; sha256 = \"{sha256}\"; }}")
+}
+
+/// Download and unpack a tarball into the Nix store, returning its hash.
+fn prefetch_tarball(url: &str) -> Result<String> {
+    let output = Command::new("nix-prefetch-url")
+        .arg("--unpack")
+        .arg(url)
+        .output()
+        .context("Running nix-prefetch-url. Nix is needed to lock dependencies")?;
+    if !output.status.success() {
+        bail!(
+            "Fetching {url} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Pin a GitHub archive URL of a branch or tag to the commit it currently points at.
+fn pin_github_archive(url: &str) -> Result<(String, String)> {
+    let (repo, reference) = parse_github_archive(url).with_context(|| {
+        format!("Can't lock overlay {url}, only GitHub archive URLs are supported")
+    })?;
+
+    if is_commit(&reference) {
+        return Ok((url.to_string(), reference));
+    }
+
+    let output = Command::new("git")
+        .arg("ls-remote")
+        .arg(format!("https://github.com/{repo}"))
+        .arg(&reference)
+        .output()
+        .context("Running git ls-remote")?;
+    if !output.status.success() {
+        bail!(
+            "Resolving {reference} of {repo} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rev = match stdout.split_whitespace().next() {
+        Some(rev) if is_commit(rev) => rev.to_string(),
+        _ => bail!("{repo} has no branch or tag named {reference}"),
+    };
+
+    Ok((
+        format!("https://github.com/{repo}/archive/{rev}.tar.gz"),
+        rev,
+    ))
+}
+
+/// Split `https://github.com/<owner>/<repo>/archive/<ref>.tar.gz` into the repository and the reference.
+fn parse_github_archive(url: &str) -> Option<(String, String)> {
+    let path = url.strip_prefix("https://github.com/")?;
+    let (repo, archive) = path.split_once("/archive/")?;
+    let reference = archive
+        .strip_prefix("refs/heads/")
+        .or_else(|| archive.strip_prefix("refs/tags/"))
+        .unwrap_or(archive)
+        .strip_suffix(".tar.gz")?;
+
+    if repo.split('/').count() != 2 || reference.is_empty() {
+        return None;
+    }
+
+    Some((repo.to_string(), reference.to_string()))
+}
+
+fn is_commit(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::{
+        environment::Environment,
+        nix::pkg::Pkg,
+        plan::{
+            generator::{GeneratePlanOptions, NixpacksBuildPlanGenerator},
+            phase::Phase,
+            PlanGenerator,
+        },
+    };
+    use std::fs;
+
+    const OVERLAY: &str = "https://github.com/oxalica/rust-overlay/archive/master.tar.gz";
+
+    fn get_phases() -> Phases {
+        let mut setup = Phase::setup(Some(vec![Pkg::new("nodejs"), Pkg::new("yarn")]));
+        setup.nix_overlays = Some(vec![OVERLAY.to_string()]);
+        Phases::from([("setup".to_string(), setup)])
+    }
+
+    fn get_lock() -> NixLock {
+        NixLock {
+            version: NIX_LOCK_VERSION,
+            archives: BTreeMap::from([(
+                NIXPKGS_ARCHIVE.to_string(),
+                LockedArchive {
+                    url: nixpkgs_archive_url(NIXPKGS_ARCHIVE),
+                    sha256: "archivehash".to_string(),
+                    packages: BTreeMap::from([("nodejs".to_string(), "18.17.1".to_string())]),
+                },
+            )]),
+            overlays: BTreeMap::from([(
+                OVERLAY.to_string(),
+                LockedOverlay {
+                    url: "https://github.com/oxalica/rust-overlay/archive/abc.tar.gz".to_string(),
+                    rev: "abc".to_string(),
+                    sha256: "overlayhash".to_string(),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_parse_github_archive() {
+        assert_eq!(
+            parse_github_archive(OVERLAY),
+            Some(("oxalica/rust-overlay".to_string(), "master".to_string()))
+        );
+        assert_eq!(
+            parse_github_archive(
+                "https://github.com/railwayapp/nix-npm-overlay/archive/refs/heads/main.tar.gz"
+            ),
+            Some(("railwayapp/nix-npm-overlay".to_string(), "main".to_string()))
+        );
+        assert_eq!(
+            parse_github_archive("https://example.com/overlay.tar.gz"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_missing_entries() {
+        assert_eq!(
+            get_lock().missing_entries(&get_phases()),
+            vec!["package yarn"]
+        );
+        assert_eq!(
+            NixLock::default().missing_entries(&get_phases()),
+            vec![
+                format!("nixpkgs archive {NIXPKGS_ARCHIVE}"),
+                format!("overlay {OVERLAY}")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let lock = get_lock();
+        let contents = lock.to_toml().unwrap();
+        assert_eq!(toml::from_str::<NixLock>(&contents).unwrap(), lock);
+    }
+
+    #[test]
+    fn test_resolve_keeps_locked_entries() {
+        let mut phases = get_phases();
+        phases.get_mut("setup").unwrap().nix_pkgs = Some(vec!["nodejs".to_string()]);
+
+        // Nothing needs to be fetched, so this works without Nix
+        assert_eq!(
+            NixLock::resolve(&phases, Some(&get_lock())).unwrap(),
+            get_lock()
+        );
+    }
+
+    #[test]
+    fn test_ignore_outdated_lock() {
+        let dir = tempdir::TempDir::new("nixpacks-lock").unwrap();
+        fs::write(
+            dir.path().join("nixpacks.toml"),
+            "[phases.setup]\nnixPkgs = ['hello']\n\n[start]\ncmd = 'hello'\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join(NIX_LOCK_FILE), "version = 0\n").unwrap();
+
+        let app = App::new(&dir.path().to_string_lossy()).unwrap();
+        let generate = |ignore_lock| {
+            NixpacksBuildPlanGenerator::new(
+                crate::get_providers(),
+                GeneratePlanOptions {
+                    ignore_lock,
+                    ..Default::default()
+                },
+            )
+            .generate_plan(&app, &Environment::default())
+        };
+
+        // `nixpacks lock --update` ignores the lock, so it can replace it
+        assert!(generate(false).is_err());
+        assert_eq!(generate(true).unwrap().0.nix_lock, None);
+    }
+}
@@ -0,0 +1,397 @@
+use super::{group_nix_packages_by_archive, lock::NixLock, nixpkgs_archive_url, NIXPKGS_ARCHIVE};
+use crate::nixpacks::plan::phase::Phases;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Records where the tarballs in a mirror were downloaded from, and their hashes.
+pub const MIRROR_SOURCES_FILE: &str = "sources.json";
+
+/// A tarball downloaded into a mirror.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug)]
+pub struct MirroredSource {
+    pub url: String,
+    pub sha256: Option<String>,
+}
+
+/// A directory or server with copies of the nixpkgs archives and overlays a build fetches,
+/// laid out like the directories `nixpacks prefetch` creates.
+#[derive(PartialEq, Eq, Default, Clone, Debug)]
+pub struct NixMirror {
+    url: String,
+    sources: BTreeMap<String, MirroredSource>,
+}
+
+impl NixMirror {
+    /// A mirror at a `file://`, `http://` or `https://` URL.
+    ///
+    /// The hashes of a local mirror are read from its sources file.
+    pub fn new(url: &str) -> Result<NixMirror> {
+        let url = url.trim_end_matches('/').to_string();
+        let mut mirror = NixMirror {
+            url,
+            sources: BTreeMap::new(),
+        };
+
+        if let Some(dir) = mirror.local_dir() {
+            let sources_file = dir.join(MIRROR_SOURCES_FILE);
+            if sources_file.exists() {
+                let contents = fs::read_to_string(&sources_file)?;
+                mirror.sources = serde_json::from_str(&contents)
+                    .with_context(|| format!("Reading {}", sources_file.display()))?;
+            }
+        } else if !mirror.url.starts_with("http://") && !mirror.url.starts_with("https://") {
+            bail!(
+                "Nixpkgs mirror {} must be a file://, http:// or https:// URL",
+                mirror.url
+            );
+        }
+
+        Ok(mirror)
+    }
+
+    /// The directory of a `file://` mirror.
+    pub fn local_dir(&self) -> Option<PathBuf> {
+        self.url.strip_prefix("file://").map(PathBuf::from)
+    }
+
+    fn url_of(&self, path: &str) -> String {
+        format!("{}/{path}", self.url)
+    }
+
+    fn hash_of(&self, path: &str) -> Option<String> {
+        self.sources
+            .get(path)
+            .and_then(|source| source.sha256.clone())
+    }
+}
+
+/// Where the nixpkgs archives and overlays of a build are fetched from.
+///
+/// Locked archives and overlays are pinned to their hash, and a mirror replaces GitHub.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NixSources<'a> {
+    pub lock: Option<&'a NixLock>,
+    pub mirror: Option<&'a NixMirror>,
+}
+
+impl<'a> NixSources<'a> {
+    pub fn new(lock: Option<&'a NixLock>, mirror: Option<&'a NixMirror>) -> Self {
+        NixSources { lock, mirror }
+    }
+
+    /// The argument to `fetchTarball` that fetches a nixpkgs archive.
+    pub fn archive_source(&self, archive: &str) -> String {
+        let locked = self.lock.and_then(|lock| lock.archives.get(archive));
+        let path = archive_mirror_path(archive);
+        match self.mirror {
+            Some(mirror) => tarball_source(
+                &mirror.url_of(&path),
+                locked
+                    .map(|locked| locked.sha256.clone())
+                    .or_else(|| mirror.hash_of(&path)),
+            ),
+            None => match locked {
+                Some(locked) => tarball_source(&locked.url, Some(locked.sha256.clone())),
+                None => tarball_source(&nixpkgs_archive_url(archive), None),
+            },
+        }
+    }
+
+    /// The argument to `fetchTarball` that fetches an overlay.
+    pub fn overlay_source(&self, url: &str) -> String {
+        let locked = self.lock.and_then(|lock| lock.overlays.get(url));
+        let path = overlay_mirror_path(url);
+        match self.mirror {
+            Some(mirror) => tarball_source(
+                &mirror.url_of(&path),
+                locked
+                    .map(|locked| locked.sha256.clone())
+                    .or_else(|| mirror.hash_of(&path)),
+            ),
+            None => match locked {
+                Some(locked) => tarball_source(&locked.url, Some(locked.sha256.clone())),
+                None => tarball_source(url, None),
+            },
+        }
+    }
+
+    /// The URL an archive or overlay is downloaded from when mirroring it, the locked one if there is one.
+    fn upstream(&self, path: &str) -> Option<MirroredSource> {
+        let lock = self.lock?;
+        lock.archives
+            .iter()
+            .map(|(archive, locked)| (archive_mirror_path(archive), &locked.url, &locked.sha256))
+            .chain(
+                lock.overlays
+                    .iter()
+                    .map(|(url, locked)| (overlay_mirror_path(url), &locked.url, &locked.sha256)),
+            )
+            .find(|(locked_path, _, _)| locked_path == path)
+            .map(|(_, url, sha256)| MirroredSource {
+                url: url.clone(),
+                sha256: Some(sha256.clone()),
+            })
+    }
+}
+
+/// Where a nixpkgs archive is stored in a mirror.
+pub fn archive_mirror_path(archive: &str) -> String {
+    format!("nixpkgs/{archive}.tar.gz")
+}
+
+/// Where an overlay is stored in a mirror, e.g. `overlays/oxalica-rust-overlay-master.tar.gz`.
+pub fn overlay_mirror_path(url: &str) -> String {
+    let name = url
+        .strip_prefix("https://github.com/")
+        .and_then(|path| path.split_once("/archive/"))
+        .map_or_else(
+            || {
+                url.split_once("://")
+                    .map_or(url, |(_, rest)| rest)
+                    .trim_end_matches(".tar.gz")
+                    .to_string()
+            },
+            |(repo, reference)| {
+                format!(
+                    "{repo}-{}",
+                    reference
+                        .trim_start_matches("refs/heads/")
+                        .trim_start_matches("refs/tags/")
+                        .trim_end_matches(".tar.gz")
+                )
+            },
+        )
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+
+    format!("overlays/{name}.tar.gz")
+}
+
+/// Download every nixpkgs archive and overlay the phases use into a mirror directory.
+///
+/// Tarballs already in the directory are kept. Returns the paths of the downloaded tarballs.
+pub fn prefetch(phases: &Phases, sources: NixSources, dir: &Path) -> Result<Vec<String>> {
+    let groups = group_nix_packages_by_archive(&phases.values().cloned().collect::<Vec<_>>());
+
+    // The base archive is always included, the OCI builder installs its base environment from it
+    let mut wanted = BTreeMap::from([(
+        archive_mirror_path(NIXPKGS_ARCHIVE),
+        nixpkgs_archive_url(NIXPKGS_ARCHIVE),
+    )]);
+    let mut overlays = BTreeSet::new();
+    for group in &groups {
+        let archive = group
+            .archive
+            .clone()
+            .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string());
+        wanted.insert(archive_mirror_path(&archive), nixpkgs_archive_url(&archive));
+        overlays.extend(group.overlays.iter().cloned());
+    }
+    for url in overlays {
+        wanted.insert(overlay_mirror_path(&url), url);
+    }
+
+    let sources_file = dir.join(MIRROR_SOURCES_FILE);
+    let mut mirrored: BTreeMap<String, MirroredSource> = if sources_file.exists() {
+        serde_json::from_str(&fs::read_to_string(&sources_file)?)
+            .with_context(|| format!("Reading {}", sources_file.display()))?
+    } else {
+        BTreeMap::new()
+    };
+
+    let mut downloaded = Vec::new();
+    for (path, url) in wanted {
+        let file = dir.join(&path);
+        if file.exists() && mirrored.contains_key(&path) {
+            continue;
+        }
+
+        let mut source = sources
+            .upstream(&path)
+            .unwrap_or(MirroredSource { url, sha256: None });
+        download(&source.url, &file)?;
+        if source.sha256.is_none() {
+            source.sha256 = hash_tarball(&file)?;
+        }
+
+        mirrored.insert(path.clone(), source);
+        downloaded.push(path);
+
+        // Written after every download, so an interrupted prefetch keeps what it fetched
+        fs::write(&sources_file, serde_json::to_string_pretty(&mirrored)?)?;
+    }
+
+    Ok(downloaded)
+}
+
+fn tarball_source(url: &str, sha256: Option<String>) -> String {
+    match sha256 {
+        Some(sha256) => format!("{{ url = \"{url}\"; sha256 = \"{sha256}\"; }}"),
+        None => format!("\"{url}\""),
+    }
+}
+
+fn download(url: &str, file: &Path) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let partial = file.with_extension("gz.part");
+    let status = Command::new("curl")
+        .args(["--fail", "--location", "--silent", "--show-error"])
+        .args(["--retry", "3", "--output"])
+        .arg(&partial)
+        .arg(url)
+        .status()
+        .context("Running curl. curl is needed to prefetch tarballs")?;
+    if !status.success() {
+        bail!("Downloading {url} failed");
+    }
+
+    fs::rename(&partial, file)?;
+    Ok(())
+}
+
+/// The hash Nix verifies the unpacked tarball with, if Nix is available to compute it.
+fn hash_tarball(file: &Path) -> Result<Option<String>> {
+    let file = fs::canonicalize(file)?;
+    let output = match Command::new("nix-prefetch-url")
+        .arg("--unpack")
+        .arg(format!("file://{}", file.display()))
+        .output()
+    {
+        Ok(output) => output,
+        Err(_) => return Ok(None),
+    };
+    if !output.status.success() {
+        bail!(
+            "Hashing {} failed: {}",
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::nix::lock::{LockedArchive, LockedOverlay};
+
+    const OVERLAY: &str = "https://github.com/oxalica/rust-overlay/archive/master.tar.gz";
+
+    fn get_lock() -> NixLock {
+        NixLock {
+            version: 1,
+            archives: BTreeMap::from([(
+                NIXPKGS_ARCHIVE.to_string(),
+                LockedArchive {
+                    url: nixpkgs_archive_url(NIXPKGS_ARCHIVE),
+                    sha256: "archivehash".to_string(),
+                    packages: BTreeMap::new(),
+                },
+            )]),
+            overlays: BTreeMap::from([(
+                OVERLAY.to_string(),
+                LockedOverlay {
+                    url: "https://github.com/oxalica/rust-overlay/archive/abc.tar.gz".to_string(),
+                    rev: "abc".to_string(),
+                    sha256: "overlayhash".to_string(),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_sources() {
+        let lock = get_lock();
+        let sources = NixSources::new(Some(&lock), None);
+        assert_eq!(
+            sources.archive_source(NIXPKGS_ARCHIVE),
+            format!(
+                "{{ url = \"{}\"; sha256 = \"archivehash\"; }}",
+                nixpkgs_archive_url(NIXPKGS_ARCHIVE)
+            )
+        );
+        assert_eq!(
+            NixSources::default().archive_source(NIXPKGS_ARCHIVE),
+            format!("\"{}\"", nixpkgs_archive_url(NIXPKGS_ARCHIVE))
+        );
+        assert_eq!(
+            sources.overlay_source(OVERLAY),
+            "{ url = \"https://github.com/oxalica/rust-overlay/archive/abc.tar.gz\"; sha256 = \"overlayhash\"; }"
+        );
+    }
+
+    #[test]
+    fn test_mirror_sources() {
+        let lock = get_lock();
+        let mut mirror = NixMirror::new("https://mirror.internal/nix/").unwrap();
+        mirror.sources.insert(
+            archive_mirror_path("abc123"),
+            MirroredSource {
+                url: nixpkgs_archive_url("abc123"),
+                sha256: Some("mirroredhash".to_string()),
+            },
+        );
+
+        let sources = NixSources::new(Some(&lock), Some(&mirror));
+        assert_eq!(
+            sources.archive_source(NIXPKGS_ARCHIVE),
+            format!("{{ url = \"https://mirror.internal/nix/nixpkgs/{NIXPKGS_ARCHIVE}.tar.gz\"; sha256 = \"archivehash\"; }}")
+        );
+        assert_eq!(
+            sources.archive_source("abc123"),
+            "{ url = \"https://mirror.internal/nix/nixpkgs/abc123.tar.gz\"; sha256 = \"mirroredhash\"; }"
+        );
+        assert_eq!(
+            NixSources::new(None, Some(&mirror)).overlay_source(OVERLAY),
+            "\"https://mirror.internal/nix/overlays/oxalica-rust-overlay-master.tar.gz\""
+        );
+    }
+
+    #[test]
+    fn test_mirror_url() {
+        assert_eq!(
+            NixMirror::new("file:///mirror/").unwrap().local_dir(),
+            Some(PathBuf::from("/mirror"))
+        );
+        assert_eq!(
+            NixMirror::new("https://mirror.internal")
+                .unwrap()
+                .local_dir(),
+            None
+        );
+        assert!(NixMirror::new("/mirror").is_err());
+    }
+
+    #[test]
+    fn test_overlay_mirror_path() {
+        assert_eq!(
+            overlay_mirror_path(
+                "https://github.com/railwayapp/nix-npm-overlay/archive/refs/heads/main.tar.gz"
+            ),
+            "overlays/railwayapp-nix-npm-overlay-main.tar.gz"
+        );
+        assert_eq!(
+            overlay_mirror_path("https://example.com/overlays/latest.tar.gz"),
+            "overlays/example.com-overlays-latest.tar.gz"
+        );
+    }
+}
@@ -3,6 +3,14 @@ use std::collections::{BTreeMap, BTreeSet};
 
 use crate::nixpacks::plan::phase::{Phase, Phases};
 
+use self::mirror::NixSources;
+
+pub mod cache;
+pub mod flake;
+pub mod index;
+pub mod lock;
+pub mod mirror;
+pub mod optimize;
 pub mod pkg;
 
 // This line is automatically updated.
@@ -21,6 +29,9 @@ struct NixGroup {
     libs: Vec<String>,
     overlays: Vec<String>,
     files: Vec<String>,
+    expressions: Vec<String>,
+    nix_files: Vec<String>,
+    exports: BTreeMap<String, String>,
 }
 
 /// Collect all Nix packages based on the nixpkgs revision they should be fetched from.
@@ -35,7 +46,15 @@ fn group_nix_packages_by_archive(phases: &[Phase]) -> Vec<NixGroup> {
             pkgs: phase.nix_pkgs.clone().unwrap_or_default(),
             libs: phase.nix_libs.clone().unwrap_or_default(),
             overlays: phase.nix_overlays.clone().unwrap_or_default(),
-            files: phase.only_include_files.clone().unwrap_or_default(),
+            // Nix files are copied in with the other files the install needs
+            files: [
+                phase.only_include_files.clone().unwrap_or_default(),
+                phase.nix_file.clone().into_iter().collect(),
+            ]
+            .concat(),
+            expressions: phase.nix_expression.clone().into_iter().collect(),
+            nix_files: phase.nix_file.clone().into_iter().collect(),
+            exports: phase.nix_exports.clone().unwrap_or_default(),
         });
 
     for g in groups {
@@ -45,6 +64,9 @@ fn group_nix_packages_by_archive(phases: &[Phase]) -> Vec<NixGroup> {
                 group.libs.extend(g.libs);
                 group.overlays.extend(g.overlays);
                 group.files.extend(g.files);
+                group.expressions.extend(g.expressions);
+                group.nix_files.extend(g.nix_files);
+                group.exports.extend(g.exports);
             }
             None => {
                 archive_to_packages.insert(g.archive.clone(), g);
@@ -59,7 +81,13 @@ fn group_nix_packages_by_archive(phases: &[Phase]) -> Vec<NixGroup> {
 }
 
 /// Turn the Nix dependencies for each phase into a Nix expression that installs them.
-pub fn create_nix_expressions_for_phases(phases: &Phases) -> BTreeMap<String, String> {
+///
+/// Archives and overlays are fetched from the given sources, and Nix files are read from the app in `app_dir`.
+pub fn create_nix_expressions_for_phases(
+    phases: &Phases,
+    sources: NixSources,
+    app_dir: &str,
+) -> BTreeMap<String, String> {
     let archive_to_packages = group_nix_packages_by_archive(
         &phases
             .values()
@@ -72,7 +100,7 @@ pub fn create_nix_expressions_for_phases(phases: &Phases) -> BTreeMap<String, St
         .fold(BTreeMap::new(), |mut acc, g| {
             acc.insert(
                 nix_file_name(g.archive.as_ref()),
-                nix_expression_for_group(g),
+                nix_expression_for_group(g, sources, app_dir),
             );
             acc
         })
@@ -103,6 +131,11 @@ pub fn setup_files_for_phases(phases: &Phases) -> Vec<String> {
     })
 }
 
+/// The tarball of a nixpkgs commit.
+pub fn nixpkgs_archive_url(archive: &str) -> String {
+    format!("https://github.com/NixOS/nixpkgs/archive/{archive}.tar.gz")
+}
+
 /// Generates the filename for each Nix expression file.
 fn nix_file_name(archive: Option<&String>) -> String {
     match archive {
@@ -111,8 +144,30 @@ fn nix_file_name(archive: Option<&String>) -> String {
     }
 }
 
+/// A Nix path to a file in the app.
+fn app_file_path(app_dir: &str, file: &str) -> String {
+    let path = format!(
+        "{}/{}",
+        app_dir.trim_end_matches('/'),
+        file.trim_start_matches("./")
+    );
+    format!(
+        "(/. + \"{}\")",
+        path.replace('"', "\\\"").replace("${", "\\${")
+    )
+}
+
+/// The `export` lines that set the environment variables of a group from the locations of its Nix packages.
+fn nix_exports(exports: &BTreeMap<String, String>) -> String {
+    exports
+        .iter()
+        .map(|(name, value)| format!("export {name}=\"{value}\"\n"))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
 /// Generates an expression that installs Nix packages in the container environment and makes them available in PATH.
-fn nix_expression_for_group(group: &NixGroup) -> String {
+fn nix_expression_for_group(group: &NixGroup, sources: NixSources, app_dir: &str) -> String {
     let archive = group
         .archive
         .clone()
@@ -120,6 +175,13 @@ fn nix_expression_for_group(group: &NixGroup) -> String {
 
     let mut pkgs = group.pkgs.clone();
     pkgs.sort();
+    pkgs.extend(group.expressions.iter().map(|expr| format!("({expr})")));
+    pkgs.extend(
+        group
+            .nix_files
+            .iter()
+            .map(|file| format!("(callPackage {} {{ }})", app_file_path(app_dir, file))),
+    );
     let pkgs = pkgs.join(" ");
 
     let mut libs = group.libs.clone();
@@ -129,25 +191,18 @@ fn nix_expression_for_group(group: &NixGroup) -> String {
     let overlays_string = group
         .overlays
         .iter()
-        .map(|url| format!("(import (builtins.fetchTarball \"{url}\"))"))
+        .map(|url| {
+            format!(
+                "(import (builtins.fetchTarball {}))",
+                sources.overlay_source(url)
+            )
+        })
         .collect::<Vec<String>>()
         .join("\n");
 
-    let pkg_import = format!(
-        "import (fetchTarball \"https://github.com/NixOS/nixpkgs/archive/{archive}.tar.gz\")"
-    );
+    let pkg_import = format!("import (fetchTarball {})", sources.archive_source(&archive));
 
-    // If the openssl library is added, set the OPENSSL_DIR and OPENSSL_LIB_DIR environment variables
-    // In the future, we will probably want a generic way for providers to set variables based off Nix package locations
-    let openssl_dirs =
-        if let Some(openssl_lib) = group.libs.iter().find(|lib| lib.contains("openssl")) {
-            formatdoc! {"
-          export OPENSSL_DIR=\"${{{openssl_lib}.dev}}\"
-          export OPENSSL_LIB_DIR=\"${{{openssl_lib}.out}}/lib\"
-        "}
-        } else {
-            String::new()
-        };
+    let exports = nix_exports(&group.exports);
 
     let name = format!("{archive}-env");
     let nix_expression = formatdoc! {"
@@ -176,7 +231,7 @@ fn nix_expression_for_group(group: &NixGroup) -> String {
         pkg_import,
         overlays_string,
         libs,
-        openssl_dirs,
+        exports,
         pkgs,
         name=name,
     };
@@ -208,7 +263,8 @@ mod tests {
                 pkgs: vec!["foo".to_string(), "bar".to_string(), "baz".to_string()],
                 libs: vec!["lib1".to_string()],
                 overlays: vec![],
-                files: vec!["test-file".to_string()]
+                files: vec!["test-file".to_string()],
+                ..Default::default()
             }
         );
         assert_eq!(
@@ -218,8 +274,69 @@ mod tests {
                 pkgs: vec!["hello".to_string(), "world".to_string()],
                 libs: vec![],
                 overlays: vec![],
-                files: vec![]
+                files: vec![],
+                ..Default::default()
             }
         );
     }
+    #[test]
+    fn test_nix_expression_with_lock() {
+        let group = NixGroup {
+            pkgs: vec!["nodejs".to_string()],
+            overlays: vec!["https://example.com/overlay.tar.gz".to_string()],
+            ..Default::default()
+        };
+        let lock = lock::NixLock {
+            archives: BTreeMap::from([(
+                NIXPKGS_ARCHIVE.to_string(),
+                lock::LockedArchive {
+                    url: nixpkgs_archive_url(NIXPKGS_ARCHIVE),
+                    sha256: "archivehash".to_string(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let expression =
+            nix_expression_for_group(&group, NixSources::new(Some(&lock), None), "/app/");
+        assert!(expression.contains(&format!(
+            "import (fetchTarball {{ url = \"{}\"; sha256 = \"archivehash\"; }})",
+            nixpkgs_archive_url(NIXPKGS_ARCHIVE)
+        )));
+        // Overlays that aren't locked are still fetched by URL
+        assert!(expression
+            .contains("(import (builtins.fetchTarball \"https://example.com/overlay.tar.gz\"))"));
+    }
+
+    #[test]
+    fn test_nix_expression_with_custom_expressions() {
+        let mut setup = Phase::setup(Some(vec![Pkg::new("nodejs")]));
+        setup.nix_expression = Some("python3.withPackages (ps: [ ps.requests ])".to_string());
+        let mut tools = Phase::new("tools");
+        tools.nix_file = Some("./nix/tools.nix".to_string());
+
+        let groups = group_nix_packages_by_archive(&[setup, tools]);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].files, vec!["./nix/tools.nix"]);
+
+        let expression = nix_expression_for_group(&groups[0], NixSources::default(), "/app/");
+        assert!(expression.contains(
+            "nodejs (python3.withPackages (ps: [ ps.requests ])) (callPackage (/. + \"/app/nix/tools.nix\") { })"
+        ));
+    }
+
+    #[test]
+    fn test_nix_expression_with_exports() {
+        let setup = Phase::setup(Some(vec![
+            Pkg::new("postgresql").set_export("PKG_CONFIG_PATH", "${postgresql.dev}/lib/pkgconfig"),
+            Pkg::new("jdk").set_export("JAVA_HOME", "${jdk}"),
+        ]));
+
+        let groups = group_nix_packages_by_archive(&[setup]);
+        let expression = nix_expression_for_group(&groups[0], NixSources::default(), "/app/");
+        assert!(expression.contains("export JAVA_HOME=\"${jdk}\""));
+        assert!(expression.contains("export PKG_CONFIG_PATH=\"${postgresql.dev}/lib/pkgconfig\""));
+        assert!(!expression.contains("OPENSSL_DIR"));
+    }
 }
@@ -0,0 +1,310 @@
+use super::{
+    index::{referenced_attributes, NixpkgsIndex},
+    mirror::NixSources,
+    NIXPKGS_ARCHIVE,
+};
+use crate::nixpacks::plan::phase::{Phase, Phases};
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+/// Why the packages of one archive can be installed from another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeReason {
+    /// The other archive already installs every package.
+    Subset,
+    /// The other archive has every package, possibly at a different version.
+    Available,
+}
+
+/// Packages that can be moved from one nixpkgs archive to another, so the closure of the first isn't installed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveMerge {
+    pub from: String,
+    pub into: String,
+    pub phases: Vec<String>,
+    pub packages: Vec<String>,
+    pub reason: MergeReason,
+}
+
+/// The nixpkgs archives a build plan installs packages from, and which of them can be collapsed into another.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ArchiveReport {
+    pub archives: Vec<String>,
+    pub merges: Vec<ArchiveMerge>,
+}
+
+/// The packages a single archive installs, across all phases.
+#[derive(Default)]
+struct ArchiveGroup {
+    phases: Vec<String>,
+    packages: BTreeSet<String>,
+    /// Whether the packages can be checked against another archive's index.
+    /// Overlays, Nix expressions and Nix files can refer to anything, so those phases are never moved.
+    movable: bool,
+}
+
+impl ArchiveReport {
+    /// Find the archives that can be collapsed, using the indexes of the archives that are available.
+    pub fn load(phases: &Phases, sources: NixSources) -> Result<Self> {
+        let groups = group_phases_by_archive(phases);
+        let mut indexes = BTreeMap::new();
+        if groups.len() > 1 {
+            for archive in groups.keys() {
+                if let Some(index) = NixpkgsIndex::load(archive, sources)? {
+                    indexes.insert(archive.clone(), index);
+                }
+            }
+        }
+
+        Ok(ArchiveReport::new(phases, &indexes))
+    }
+
+    /// Find the archives that can be collapsed. Archives without an index only receive packages they already install.
+    pub fn new(phases: &Phases, indexes: &BTreeMap<String, NixpkgsIndex>) -> Self {
+        let groups = group_phases_by_archive(phases);
+        let archives = groups.keys().cloned().collect::<Vec<_>>();
+
+        // Packages are moved into the archives that install the most already, preferring the default archive
+        let mut targets = archives.clone();
+        targets.sort_by_key(|archive| {
+            (
+                std::cmp::Reverse(groups[archive].packages.len()),
+                archive != NIXPKGS_ARCHIVE,
+            )
+        });
+
+        let mut merges: Vec<ArchiveMerge> = Vec::new();
+        for from in targets.iter().rev() {
+            let group = &groups[from];
+            if !group.movable || merges.iter().any(|merge| &merge.into == from) {
+                continue;
+            }
+
+            let target = targets
+                .iter()
+                .filter(|into| *into != from && !merges.iter().any(|merge| &merge.from == *into))
+                .find_map(|into| {
+                    let installed = &groups[into].packages;
+                    if group.packages.is_subset(installed) {
+                        return Some((into, MergeReason::Subset));
+                    }
+
+                    let index = indexes.get(into)?;
+                    group
+                        .packages
+                        .iter()
+                        .flat_map(|pkg| referenced_attributes(pkg))
+                        .all(|attribute| index.contains(&attribute))
+                        .then(|| (into, MergeReason::Available))
+                });
+
+            if let Some((into, reason)) = target {
+                merges.push(ArchiveMerge {
+                    from: from.clone(),
+                    into: into.clone(),
+                    phases: group.phases.clone(),
+                    packages: group.packages.iter().cloned().collect(),
+                    reason,
+                });
+            }
+        }
+
+        ArchiveReport { archives, merges }
+    }
+
+    /// The archives that are left after collapsing.
+    pub fn remaining_archives(&self) -> Vec<String> {
+        self.archives
+            .iter()
+            .filter(|archive| !self.merges.iter().any(|merge| &merge.from == *archive))
+            .cloned()
+            .collect()
+    }
+
+    /// Install the packages of the collapsed archives from the archives they were merged into.
+    pub fn apply(&self, phases: &mut Phases) {
+        for merge in &self.merges {
+            for name in &merge.phases {
+                if let Some(phase) = phases.get_mut(name) {
+                    phase.nixpkgs_archive = if merge.into == NIXPKGS_ARCHIVE {
+                        None
+                    } else {
+                        Some(merge.into.clone())
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ArchiveReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.merges.is_empty() {
+            return writeln!(
+                f,
+                "{} nixpkgs archives, none can be collapsed",
+                self.archives.len()
+            );
+        }
+
+        writeln!(
+            f,
+            "{} nixpkgs archives, {} after collapsing:",
+            self.archives.len(),
+            self.remaining_archives().len()
+        )?;
+        for merge in &self.merges {
+            let reason = match merge.reason {
+                MergeReason::Subset => "already installed from there",
+                MergeReason::Available => "versions may change",
+            };
+            writeln!(
+                f,
+                "  {} -> {} ({}): {}",
+                merge.from,
+                merge.into,
+                merge.packages.join(", "),
+                reason
+            )?;
+        }
+
+        writeln!(f, "{} fewer nixpkgs closures to install", self.merges.len())
+    }
+}
+
+/// The packages of the phases that install any, by the archive they are installed from.
+fn group_phases_by_archive(phases: &Phases) -> BTreeMap<String, ArchiveGroup> {
+    let mut groups: BTreeMap<String, ArchiveGroup> = BTreeMap::new();
+    for (name, phase) in phases.iter().filter(|(_, phase)| phase.uses_nix()) {
+        let archive = phase
+            .nixpkgs_archive
+            .clone()
+            .unwrap_or_else(|| NIXPKGS_ARCHIVE.to_string());
+        let group = groups.entry(archive).or_insert_with(|| ArchiveGroup {
+            movable: true,
+            ..Default::default()
+        });
+
+        group.phases.push(name.clone());
+        group.packages.extend(
+            phase
+                .nix_pkgs
+                .iter()
+                .chain(phase.nix_libs.iter())
+                .flatten()
+                .cloned(),
+        );
+        group.movable &= is_movable(phase);
+    }
+
+    groups
+}
+
+fn is_movable(phase: &Phase) -> bool {
+    phase.nix_overlays.clone().unwrap_or_default().is_empty()
+        && phase.nix_expression.is_none()
+        && phase.nix_file.is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nixpacks::nix::pkg::Pkg;
+
+    fn phase(archive: Option<&str>, pkgs: &[&str]) -> Phase {
+        let mut phase = Phase::setup(Some(pkgs.iter().map(|pkg| Pkg::new(pkg)).collect()));
+        phase.nixpkgs_archive = archive.map(ToString::to_string);
+        phase
+    }
+
+    #[test]
+    fn test_collapse_subset() {
+        let phases = Phases::from([
+            (
+                "node".to_string(),
+                phase(Some("node-archive"), &["nodejs", "yarn"]),
+            ),
+            (
+                "tools".to_string(),
+                phase(Some("tools-archive"), &["nodejs"]),
+            ),
+        ]);
+
+        let report = ArchiveReport::new(&phases, &BTreeMap::new());
+        assert_eq!(
+            report.merges,
+            vec![ArchiveMerge {
+                from: "tools-archive".to_string(),
+                into: "node-archive".to_string(),
+                phases: vec!["tools".to_string()],
+                packages: vec!["nodejs".to_string()],
+                reason: MergeReason::Subset,
+            }]
+        );
+        assert_eq!(report.remaining_archives(), vec!["node-archive"]);
+
+        let mut applied = phases;
+        report.apply(&mut applied);
+        assert_eq!(
+            applied["tools"].nixpkgs_archive,
+            Some("node-archive".to_string())
+        );
+    }
+
+    #[test]
+    fn test_collapse_into_default_archive() {
+        let phases = Phases::from([
+            ("python".to_string(), phase(None, &["python3", "gcc"])),
+            ("node".to_string(), phase(Some("node-archive"), &["nodejs"])),
+            (
+                "legacy".to_string(),
+                phase(Some("legacy-archive"), &["python27"]),
+            ),
+        ]);
+        let indexes = BTreeMap::from([(
+            NIXPKGS_ARCHIVE.to_string(),
+            NixpkgsIndex::from_attributes(
+                ["python3", "gcc", "nodejs"].iter().map(ToString::to_string),
+            ),
+        )]);
+
+        let mut report = ArchiveReport::new(&phases, &indexes);
+        assert_eq!(report.merges.len(), 1);
+        let merge = report.merges.remove(0);
+        assert_eq!(merge.from, "node-archive");
+        assert_eq!(merge.into, NIXPKGS_ARCHIVE);
+        assert_eq!(merge.reason, MergeReason::Available);
+
+        let mut applied = phases;
+        ArchiveReport {
+            archives: Vec::new(),
+            merges: vec![merge],
+        }
+        .apply(&mut applied);
+        assert_eq!(applied["node"].nixpkgs_archive, None);
+        assert_eq!(
+            applied["legacy"].nixpkgs_archive,
+            Some("legacy-archive".to_string())
+        );
+    }
+
+    #[test]
+    fn test_overlays_are_not_moved() {
+        let mut overlaid = phase(Some("rust-archive"), &["rustc"]);
+        overlaid.nix_overlays = Some(vec!["https://example.com/overlay.tar.gz".to_string()]);
+        let phases = Phases::from([
+            ("setup".to_string(), phase(None, &["rustc", "gcc"])),
+            ("rust".to_string(), overlaid),
+        ]);
+
+        let report = ArchiveReport::new(&phases, &BTreeMap::new());
+        assert!(report.merges.is_empty());
+        assert_eq!(
+            report.to_string(),
+            "2 nixpkgs archives, none can be collapsed\n"
+        );
+    }
+}
@@ -1,13 +1,14 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-/// Represents a Nix package, any derivation overrides for it, and the nixpkgs overlay to fetch it from.
+/// Represents a Nix package, any derivation overrides for it, the nixpkgs overlay to fetch it from, and the environment variables it sets.
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug)]
 pub struct Pkg {
     pub name: String,
     pub overlay: Option<String>,
     pub overrides: Option<HashMap<String, String>>,
+    pub exports: Option<BTreeMap<String, String>>,
 }
 
 impl Pkg {
@@ -16,6 +17,7 @@ impl Pkg {
             name: name.to_string(),
             overrides: None,
             overlay: None,
+            exports: None,
         }
     }
 
@@ -47,6 +49,15 @@ impl Pkg {
         self
     }
 
+    /// Set an environment variable from the location of a Nix package, e.g. `PKG_CONFIG_PATH` to `${libpq.dev}/lib/pkgconfig`.
+    #[must_use]
+    pub fn set_export(mut self, name: &str, value: &str) -> Self {
+        self.exports
+            .get_or_insert_with(BTreeMap::new)
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
     /// Add an overlay to fetch the package from.
     #[must_use]
     pub fn from_overlay(mut self, overlay: &str) -> Self {
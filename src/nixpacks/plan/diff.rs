@@ -0,0 +1,663 @@
+use super::{
+    phase::{Phase, StartPhase},
+    BuildPlan,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt};
+
+/// A single semantic difference between two build plans.
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PlanChange {
+    PhaseAdded {
+        phase: String,
+    },
+    PhaseRemoved {
+        phase: String,
+    },
+    /// Packages, libraries, overlays, dependencies, or directories added to a phase.
+    ItemsAdded {
+        phase: String,
+        field: String,
+        items: Vec<String>,
+    },
+    ItemsRemoved {
+        phase: String,
+        field: String,
+        items: Vec<String>,
+    },
+    /// Commands are ordered, so they are compared as a whole.
+    CommandsChanged {
+        phase: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A single value changed, e.g. the nixpkgs archive of a phase or the start command.
+    ValueChanged {
+        phase: Option<String>,
+        field: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+/// The semantic differences between two build plans.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+pub struct PlanDiff {
+    pub changes: Vec<PlanChange>,
+}
+
+impl PlanDiff {
+    /// Compare two build plans. Changes describe going from `old` to `new`.
+    pub fn new(old: &BuildPlan, new: &BuildPlan) -> Self {
+        let mut changes = Vec::new();
+
+        diff_value(
+            &mut changes,
+            None,
+            "buildImage",
+            old.build_image.as_ref(),
+            new.build_image.as_ref(),
+        );
+
+        let old_phases = old.phases.clone().unwrap_or_default();
+        let new_phases = new.phases.clone().unwrap_or_default();
+        let phase_names = old_phases
+            .keys()
+            .chain(new_phases.keys())
+            .collect::<BTreeSet<_>>();
+
+        for name in phase_names {
+            let (old_phase, new_phase) = match (old_phases.get(name), new_phases.get(name)) {
+                (Some(old_phase), Some(new_phase)) => (old_phase.clone(), new_phase.clone()),
+                (Some(old_phase), None) => {
+                    changes.push(PlanChange::PhaseRemoved {
+                        phase: name.clone(),
+                    });
+                    (old_phase.clone(), Phase::default())
+                }
+                (None, Some(new_phase)) => {
+                    changes.push(PlanChange::PhaseAdded {
+                        phase: name.clone(),
+                    });
+                    (Phase::default(), new_phase.clone())
+                }
+                (None, None) => continue,
+            };
+
+            diff_phase(&mut changes, name, &old_phase, &new_phase);
+        }
+
+        let old_start = old.start_phase.clone().unwrap_or_default();
+        let new_start = new.start_phase.clone().unwrap_or_default();
+        diff_start_phase(&mut changes, &old_start, &new_start);
+
+        let old_variables = old.variables.clone().unwrap_or_default();
+        let new_variables = new.variables.clone().unwrap_or_default();
+        let variable_names = old_variables
+            .keys()
+            .chain(new_variables.keys())
+            .collect::<BTreeSet<_>>();
+        for name in variable_names {
+            diff_value(
+                &mut changes,
+                None,
+                &format!("variables.{name}"),
+                old_variables.get(name),
+                new_variables.get(name),
+            );
+        }
+
+        let old_secrets = old.secrets.clone().unwrap_or_default();
+        let new_secrets = new.secrets.clone().unwrap_or_default();
+        let secret_ids = old_secrets
+            .keys()
+            .chain(new_secrets.keys())
+            .collect::<BTreeSet<_>>();
+        for id in secret_ids {
+            diff_value(
+                &mut changes,
+                None,
+                &format!("secrets.{id}"),
+                old_secrets.get(id).map(|s| s.get_build_arg(id)).as_ref(),
+                new_secrets.get(id).map(|s| s.get_build_arg(id)).as_ref(),
+            );
+        }
+
+        PlanDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+fn diff_phase(changes: &mut Vec<PlanChange>, name: &str, old: &Phase, new: &Phase) {
+    let lists = [
+        ("nixPkgs", &old.nix_pkgs, &new.nix_pkgs),
+        ("nixLibs", &old.nix_libs, &new.nix_libs),
+        ("nixOverlays", &old.nix_overlays, &new.nix_overlays),
+        ("aptPkgs", &old.apt_pkgs, &new.apt_pkgs),
+        ("dependsOn", &old.depends_on, &new.depends_on),
+        (
+            "cacheDirectories",
+            &old.cache_directories,
+            &new.cache_directories,
+        ),
+        ("paths", &old.paths, &new.paths),
+        ("secrets", &old.secrets, &new.secrets),
+        (
+            "onlyIncludeFiles",
+            &old.only_include_files,
+            &new.only_include_files,
+        ),
+    ];
+
+    for (field, old_items, new_items) in lists {
+        diff_items(changes, name, field, old_items.as_ref(), new_items.as_ref());
+    }
+
+    let old_cmds = old.cmds.clone().unwrap_or_default();
+    let new_cmds = new.cmds.clone().unwrap_or_default();
+    if old_cmds != new_cmds {
+        changes.push(PlanChange::CommandsChanged {
+            phase: name.to_string(),
+            old: old_cmds,
+            new: new_cmds,
+        });
+    }
+
+    diff_value(
+        changes,
+        Some(name),
+        "nixpkgsArchive",
+        old.nixpkgs_archive.as_ref(),
+        new.nixpkgs_archive.as_ref(),
+    );
+    diff_value(
+        changes,
+        Some(name),
+        "nixExpression",
+        old.nix_expression.as_ref(),
+        new.nix_expression.as_ref(),
+    );
+    diff_value(
+        changes,
+        Some(name),
+        "nixFile",
+        old.nix_file.as_ref(),
+        new.nix_file.as_ref(),
+    );
+
+    let old_exports = old.nix_exports.clone().unwrap_or_default();
+    let new_exports = new.nix_exports.clone().unwrap_or_default();
+    for export in old_exports
+        .keys()
+        .chain(new_exports.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        diff_value(
+            changes,
+            Some(name),
+            &format!("nixExports.{export}"),
+            old_exports.get(export),
+            new_exports.get(export),
+        );
+    }
+}
+
+fn diff_start_phase(changes: &mut Vec<PlanChange>, old: &StartPhase, new: &StartPhase) {
+    diff_value(
+        changes,
+        None,
+        "start.cmd",
+        old.cmd.as_ref(),
+        new.cmd.as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.runImage",
+        old.run_image.as_ref(),
+        new.run_image.as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.closureOnly",
+        old.closure_only.map(|v| v.to_string()).as_ref(),
+        new.closure_only.map(|v| v.to_string()).as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.user",
+        old.user.as_ref(),
+        new.user.as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.restart",
+        old.restart.map(|v| v.to_string()).as_ref(),
+        new.restart.map(|v| v.to_string()).as_ref(),
+    );
+
+    let old_processes = old.processes.clone().unwrap_or_default();
+    let new_processes = new.processes.clone().unwrap_or_default();
+    for process in old_processes
+        .keys()
+        .chain(new_processes.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        diff_value(
+            changes,
+            None,
+            &format!("start.processes.{process}"),
+            old_processes.get(process),
+            new_processes.get(process),
+        );
+    }
+
+    diff_items(
+        changes,
+        "start",
+        "onlyIncludeFiles",
+        old.only_include_files.as_ref(),
+        new.only_include_files.as_ref(),
+    );
+    diff_items(
+        changes,
+        "start",
+        "ports",
+        port_list(old).as_ref(),
+        port_list(new).as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.healthcheck.cmd",
+        old.healthcheck.as_ref().and_then(|h| h.cmd.as_ref()),
+        new.healthcheck.as_ref().and_then(|h| h.cmd.as_ref()),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.healthcheck.interval",
+        old.healthcheck.as_ref().and_then(|h| h.interval.as_ref()),
+        new.healthcheck.as_ref().and_then(|h| h.interval.as_ref()),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.healthcheck.timeout",
+        old.healthcheck.as_ref().and_then(|h| h.timeout.as_ref()),
+        new.healthcheck.as_ref().and_then(|h| h.timeout.as_ref()),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.healthcheck.retries",
+        old.healthcheck
+            .as_ref()
+            .and_then(|h| h.retries)
+            .map(|v| v.to_string())
+            .as_ref(),
+        new.healthcheck
+            .as_ref()
+            .and_then(|h| h.retries)
+            .map(|v| v.to_string())
+            .as_ref(),
+    );
+    diff_value(
+        changes,
+        None,
+        "start.stopSignal",
+        old.stop_signal.as_ref(),
+        new.stop_signal.as_ref(),
+    );
+}
+
+fn port_list(start: &StartPhase) -> Option<Vec<String>> {
+    start
+        .ports
+        .as_ref()
+        .map(|ports| ports.iter().map(ToString::to_string).collect())
+}
+
+fn diff_items(
+    changes: &mut Vec<PlanChange>,
+    phase: &str,
+    field: &str,
+    old: Option<&Vec<String>>,
+    new: Option<&Vec<String>>,
+) {
+    let old = old.cloned().unwrap_or_default();
+    let new = new.cloned().unwrap_or_default();
+
+    let added = new
+        .iter()
+        .filter(|item| !old.contains(item))
+        .cloned()
+        .collect::<Vec<_>>();
+    let removed = old
+        .iter()
+        .filter(|item| !new.contains(item))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if !added.is_empty() {
+        changes.push(PlanChange::ItemsAdded {
+            phase: phase.to_string(),
+            field: field.to_string(),
+            items: added,
+        });
+    }
+
+    if !removed.is_empty() {
+        changes.push(PlanChange::ItemsRemoved {
+            phase: phase.to_string(),
+            field: field.to_string(),
+            items: removed,
+        });
+    }
+}
+
+fn diff_value(
+    changes: &mut Vec<PlanChange>,
+    phase: Option<&str>,
+    field: &str,
+    old: Option<&String>,
+    new: Option<&String>,
+) {
+    if old != new {
+        changes.push(PlanChange::ValueChanged {
+            phase: phase.map(ToString::to_string),
+            field: field.to_string(),
+            old: old.cloned(),
+            new: new.cloned(),
+        });
+    }
+}
+
+impl fmt::Display for PlanChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanChange::PhaseAdded { phase } => write!(f, "+ phase {phase}"),
+            PlanChange::PhaseRemoved { phase } => write!(f, "- phase {phase}"),
+            PlanChange::ItemsAdded {
+                phase,
+                field,
+                items,
+            } => write!(f, "{phase}: + {field} {}", items.join(", ")),
+            PlanChange::ItemsRemoved {
+                phase,
+                field,
+                items,
+            } => write!(f, "{phase}: - {field} {}", items.join(", ")),
+            PlanChange::CommandsChanged { phase, old, new } => {
+                write!(f, "{phase}: cmds changed")?;
+                for cmd in old {
+                    write!(f, "\n    - {cmd}")?;
+                }
+                for cmd in new {
+                    write!(f, "\n    + {cmd}")?;
+                }
+                fmt::Result::Ok(())
+            }
+            PlanChange::ValueChanged {
+                phase,
+                field,
+                old,
+                new,
+            } => {
+                let describe =
+                    |value: &Option<String>| value.clone().unwrap_or_else(|| "(none)".to_string());
+                if let Some(phase) = phase {
+                    write!(f, "{phase}: ")?;
+                }
+                write!(f, "{field} {} -> {}", describe(old), describe(new))
+            }
+        }
+    }
+}
+
+impl fmt::Display for PlanDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "No differences");
+        }
+
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+
+        fmt::Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_diff() {
+        let old = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs", "yarn"]
+            aptPkgs = ["curl"]
+            nixpkgsArchive = "abc"
+
+            [phases.build]
+            dependsOn = ["setup"]
+            cmds = ["yarn run build"]
+
+            [start]
+            cmd = "yarn start"
+            "#,
+        )
+        .unwrap();
+        let new = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs", "cowsay"]
+            aptPkgs = ["curl"]
+            nixpkgsArchive = "def"
+
+            [phases.install]
+            cmds = ["yarn install"]
+
+            [phases.build]
+            dependsOn = ["install"]
+            cmds = ["yarn run build"]
+
+            [start]
+            cmd = "yarn start"
+            runImage = "ubuntu:jammy"
+            "#,
+        )
+        .unwrap();
+
+        let diff = PlanDiff::new(&old, &new);
+
+        assert_eq!(
+            diff.changes,
+            vec![
+                PlanChange::ItemsAdded {
+                    phase: "build".to_string(),
+                    field: "dependsOn".to_string(),
+                    items: vec!["install".to_string()]
+                },
+                PlanChange::ItemsRemoved {
+                    phase: "build".to_string(),
+                    field: "dependsOn".to_string(),
+                    items: vec!["setup".to_string()]
+                },
+                PlanChange::PhaseAdded {
+                    phase: "install".to_string()
+                },
+                PlanChange::CommandsChanged {
+                    phase: "install".to_string(),
+                    old: vec![],
+                    new: vec!["yarn install".to_string()]
+                },
+                PlanChange::ItemsAdded {
+                    phase: "setup".to_string(),
+                    field: "nixPkgs".to_string(),
+                    items: vec!["cowsay".to_string()]
+                },
+                PlanChange::ItemsRemoved {
+                    phase: "setup".to_string(),
+                    field: "nixPkgs".to_string(),
+                    items: vec!["yarn".to_string()]
+                },
+                PlanChange::ValueChanged {
+                    phase: Some("setup".to_string()),
+                    field: "nixpkgsArchive".to_string(),
+                    old: Some("abc".to_string()),
+                    new: Some("def".to_string())
+                },
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.runImage".to_string(),
+                    old: None,
+                    new: Some("ubuntu:jammy".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_diff_processes() {
+        let old = BuildPlan::from_toml(
+            r#"
+            [start.processes]
+            web = "npm start"
+            worker = "npm run worker"
+            "#,
+        )
+        .unwrap();
+        let new = BuildPlan::from_toml(
+            r#"
+            [start.processes]
+            web = "npm run serve"
+            cron = "npm run cron"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            PlanDiff::new(&old, &new).changes,
+            vec![
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.processes.cron".to_string(),
+                    old: None,
+                    new: Some("npm run cron".to_string())
+                },
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.processes.web".to_string(),
+                    old: Some("npm start".to_string()),
+                    new: Some("npm run serve".to_string())
+                },
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.processes.worker".to_string(),
+                    old: Some("npm run worker".to_string()),
+                    new: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_diff_restart() {
+        let old = BuildPlan::from_toml(
+            r#"
+            [start]
+            restart = "never"
+            "#,
+        )
+        .unwrap();
+        let new = BuildPlan::from_toml(
+            r#"
+            [start]
+            restart = "on-failure"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            PlanDiff::new(&old, &new).changes,
+            vec![PlanChange::ValueChanged {
+                phase: None,
+                field: "start.restart".to_string(),
+                old: Some("never".to_string()),
+                new: Some("on-failure".to_string())
+            }]
+        );
+    }
+
+    #[test]
+    fn test_plan_diff_healthcheck() {
+        let old = BuildPlan::from_toml(
+            r#"
+            [start.healthcheck]
+            cmd = "curl -f http://localhost/"
+            interval = "30s"
+            retries = 3
+            "#,
+        )
+        .unwrap();
+        let new = BuildPlan::from_toml(
+            r#"
+            [start.healthcheck]
+            cmd = "curl -f http://localhost/"
+            interval = "10s"
+            timeout = "5s"
+            retries = 5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            PlanDiff::new(&old, &new).changes,
+            vec![
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.healthcheck.interval".to_string(),
+                    old: Some("30s".to_string()),
+                    new: Some("10s".to_string())
+                },
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.healthcheck.timeout".to_string(),
+                    old: None,
+                    new: Some("5s".to_string())
+                },
+                PlanChange::ValueChanged {
+                    phase: None,
+                    field: "start.healthcheck.retries".to_string(),
+                    old: Some("3".to_string()),
+                    new: Some("5".to_string())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_diff_identical() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+            "#,
+        )
+        .unwrap();
+
+        assert!(PlanDiff::new(&plan, &plan).is_empty());
+    }
+}
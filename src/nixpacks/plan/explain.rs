@@ -0,0 +1,307 @@
+use super::BuildPlan;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt};
+
+/// Where a plan (or a single value of it) came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlanSource {
+    Provider(String),
+    Procfile,
+    ConfigFile(String),
+    /// Variables passed with `--env`, including the `NIXPACKS_*` config variables.
+    Environment,
+    Cli,
+    /// Filled in by Nixpacks itself, e.g. the base image or the pinned nixpkgs archive.
+    Default,
+}
+
+/// A source that set a value, and the value it set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanValueSource {
+    /// The source, narrowed down to the variable or flag when possible.
+    pub source: String,
+    pub value: String,
+}
+
+/// Where a single value of the final plan came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanValueOrigin {
+    /// Path of the value in the plan, e.g. `phases.build.cmds` or `start.cmd`.
+    pub key: String,
+    pub value: String,
+    pub source: String,
+    /// Whether the value extends the values below it with `...` rather than replacing them.
+    pub extends: bool,
+    /// Values set by lower precedence sources, most recent first.
+    pub overridden: Vec<PlanValueSource>,
+}
+
+/// The origin of every value in a build plan.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlanExplanation {
+    pub values: Vec<PlanValueOrigin>,
+}
+
+impl PlanExplanation {
+    /// Attribute each value of the final plan to the layers that produced it.
+    ///
+    /// Layers are ordered from lowest to highest precedence, the same order they are merged in.
+    pub fn new(layers: &[(PlanSource, BuildPlan)], plan: &BuildPlan) -> Self {
+        let mut history: BTreeMap<String, Vec<(String, Value)>> = BTreeMap::new();
+
+        for (source, layer) in layers {
+            for (key, value) in flatten_plan(layer) {
+                if is_implicit(source, &key, &value) {
+                    continue;
+                }
+
+                history
+                    .entry(key.clone())
+                    .or_default()
+                    .push((describe_source(source, &key), value));
+            }
+        }
+
+        let values = flatten_plan(plan)
+            .into_iter()
+            .map(|(key, value)| {
+                let mut setters = history.remove(&key).unwrap_or_default();
+                let (source, extends) = match setters.pop() {
+                    Some((source, set_value)) => (source, contains_auto(&set_value)),
+                    None => (PlanSource::Default.to_string(), false),
+                };
+
+                let overridden = setters
+                    .into_iter()
+                    .rev()
+                    .map(|(source, value)| PlanValueSource {
+                        source,
+                        value: render_value(&key, &value),
+                    })
+                    .collect();
+
+                PlanValueOrigin {
+                    value: render_value(&key, &value),
+                    key,
+                    source,
+                    extends,
+                    overridden,
+                }
+            })
+            .collect();
+
+        PlanExplanation { values }
+    }
+
+    /// Returns the origin of the value at the given key, if the plan has one.
+    pub fn get(&self, key: &str) -> Option<&PlanValueOrigin> {
+        self.values.iter().find(|origin| origin.key == key)
+    }
+}
+
+impl fmt::Display for PlanSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanSource::Provider(name) => write!(f, "{name} provider"),
+            PlanSource::Procfile => write!(f, "Procfile"),
+            PlanSource::ConfigFile(path) => write!(f, "{path}"),
+            PlanSource::Environment => write!(f, "environment"),
+            PlanSource::Cli => write!(f, "CLI"),
+            PlanSource::Default => write!(f, "Nixpacks default"),
+        }
+    }
+}
+
+impl fmt::Display for PlanExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for origin in &self.values {
+            writeln!(f, "{} = {}", origin.key, origin.value)?;
+
+            let verb = if origin.extends { "extended" } else { "set" };
+            writeln!(f, "    {verb} by {}", origin.source)?;
+
+            for overridden in &origin.overridden {
+                let verb = if origin.extends {
+                    "extends"
+                } else {
+                    "overrides"
+                };
+                writeln!(
+                    f,
+                    "    {verb} {} from {}",
+                    overridden.value, overridden.source
+                )?;
+            }
+        }
+
+        fmt::Result::Ok(())
+    }
+}
+
+/// Flattens a plan into its individual values, keyed by their path in the serialized plan.
+fn flatten_plan(plan: &BuildPlan) -> BTreeMap<String, Value> {
+    let mut plan = plan.clone();
+    plan.remove_phase_names();
+
+    let mut values = BTreeMap::new();
+    let object = match serde_json::to_value(&plan) {
+        Ok(Value::Object(object)) => object,
+        _ => return values,
+    };
+
+    for (key, value) in object {
+        match (key.as_str(), value) {
+            ("phases", Value::Object(phases)) => {
+                for (phase_name, phase) in phases {
+                    if let Value::Object(fields) = phase {
+                        for (field, value) in fields {
+                            values.insert(format!("phases.{phase_name}.{field}"), value);
+                        }
+                    }
+                }
+            }
+            ("variables" | "secrets" | "staticAssets" | "start", Value::Object(fields)) => {
+                for (field, value) in fields {
+                    values.insert(format!("{key}.{field}"), value);
+                }
+            }
+            (_, value) => {
+                values.insert(key, value);
+            }
+        }
+    }
+
+    values
+}
+
+/// Narrow a source down to the environment variable or CLI flag that sets the given key.
+fn describe_source(source: &PlanSource, key: &str) -> String {
+    match source {
+        PlanSource::Environment => {
+            let name = match key {
+                "phases.setup.nixPkgs" => "NIXPACKS_PKGS",
+                "phases.setup.aptPkgs" => "NIXPACKS_APT_PKGS",
+                "phases.setup.nixLibs" => "NIXPACKS_LIBS",
+                "phases.install.cmds" => "NIXPACKS_INSTALL_CMD",
+                "phases.install.cacheDirectories" => "NIXPACKS_INSTALL_CACHE_DIRS",
+                "phases.build.cmds" => "NIXPACKS_BUILD_CMD",
+                "phases.build.cacheDirectories" => "NIXPACKS_BUILD_CACHE_DIRS",
+                "start.cmd" => "NIXPACKS_START_CMD",
+                _ => key.strip_prefix("variables.").unwrap_or(key),
+            };
+            format!("env {name}")
+        }
+        PlanSource::Cli => {
+            let flag = match key {
+                "phases.setup.nixPkgs" => "--pkgs",
+                "phases.setup.aptPkgs" => "--apt",
+                "phases.setup.nixLibs" => "--libs",
+                "phases.install.cmds" => "--install-cmd",
+                "phases.build.cmds" => "--build-cmd",
+                "start.cmd" => "--start-cmd",
+                _ if key.starts_with("secrets.") => "--secret",
+                _ => "--json-plan",
+            };
+            format!("CLI {flag}")
+        }
+        _ => source.to_string(),
+    }
+}
+
+/// Values that the environment and CLI plans fill in without the user asking for them.
+fn is_implicit(source: &PlanSource, key: &str, value: &Value) -> bool {
+    if !matches!(source, PlanSource::Environment | PlanSource::Cli) {
+        return false;
+    }
+
+    match value {
+        Value::Array(items) if key.ends_with(".dependsOn") => {
+            items == &[Value::from("setup")] || items == &[Value::from("install")]
+        }
+        Value::Array(items) => items.iter().all(|item| item == "..."),
+        _ => false,
+    }
+}
+
+/// Whether a list value includes the values below it with `...`.
+fn contains_auto(value: &Value) -> bool {
+    matches!(value, Value::Array(items) if items.iter().any(|item| item == "..."))
+}
+
+fn render_value(key: &str, value: &Value) -> String {
+    match value {
+        // Static assets are whole files, so only their size is useful here
+        Value::String(contents) if key.starts_with("staticAssets.") => {
+            format!("<{} bytes>", contents.len())
+        }
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_overridden_values() {
+        let provider_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+
+            [start]
+            cmd = "npm run start"
+            "#,
+        )
+        .unwrap();
+        let file_plan = BuildPlan::from_toml(
+            r#"
+            [phases.setup]
+            nixPkgs = ["...", "cowsay"]
+            "#,
+        )
+        .unwrap();
+        let env_plan = BuildPlan::from_toml(
+            r#"
+            [start]
+            cmd = "node server.js"
+            "#,
+        )
+        .unwrap();
+
+        let layers = vec![
+            (PlanSource::Provider("node".to_string()), provider_plan),
+            (
+                PlanSource::ConfigFile("nixpacks.toml".to_string()),
+                file_plan,
+            ),
+            (PlanSource::Environment, env_plan),
+        ];
+        let mut plan =
+            BuildPlan::merge_plans(&layers.iter().map(|(_, p)| p.clone()).collect::<Vec<_>>());
+        plan.build_image = Some("ubuntu".to_string());
+
+        let explanation = PlanExplanation::new(&layers, &plan);
+
+        let start = explanation.get("start.cmd").unwrap();
+        assert_eq!(start.source, "env NIXPACKS_START_CMD");
+        assert!(!start.extends);
+        assert_eq!(
+            start.overridden,
+            vec![PlanValueSource {
+                source: "node provider".to_string(),
+                value: "\"npm run start\"".to_string()
+            }]
+        );
+
+        let pkgs = explanation.get("phases.setup.nixPkgs").unwrap();
+        assert_eq!(pkgs.source, "nixpacks.toml");
+        assert!(pkgs.extends);
+        assert_eq!(pkgs.overridden[0].source, "node provider");
+
+        assert_eq!(
+            explanation.get("buildImage").unwrap().source,
+            "Nixpacks default"
+        );
+    }
+}
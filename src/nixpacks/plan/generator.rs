@@ -1,33 +1,128 @@
-use std::path::Path;
+use std::{fmt, path::Path};
 
 use crate::{
     nixpacks::{
         app::App,
         environment::{Environment, EnvironmentVariables},
+        nix::{
+            index::check_nix_packages, lock::NixLock, mirror::NixMirror, optimize::ArchiveReport,
+        },
         plan::{BuildPlan, PlanGenerator},
     },
-    providers::{procfile::ProcfileProvider, Provider},
+    providers::{
+        external::ExternalProvider, procfile::ProcfileProvider, Detection, Provider,
+        DEFAULT_CONFIDENCE,
+    },
 };
 use anyhow::{bail, Context, Ok, Result};
 
 use super::{
-    merge::Mergeable,
+    explain::{PlanExplanation, PlanSource},
     utils::{fill_auto_in_vec, remove_autos_from_vec},
 };
 
 const NIXPACKS_METADATA: &str = "NIXPACKS_METADATA";
 
+/// The plans merged into a build plan, from lowest to highest precedence, with the source of each.
+type PlanLayers = Vec<(PlanSource, BuildPlan)>;
+
+/// Minimum confidence for a compatible provider to be added to the build automatically.
+const SECONDARY_PROVIDER_CONFIDENCE: u8 = DEFAULT_CONFIDENCE;
+
 /// Holds plan options defined in config files or existing build plans.
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Clone, Default, Debug)]
 pub struct GeneratePlanOptions {
     pub plan: Option<BuildPlan>,
     pub config_file: Option<String>,
+    /// Fail on unknown fields and invalid phase dependencies in the config file.
+    pub strict: bool,
+    /// Fail on Nix packages that don't exist in the nixpkgs archive they are installed from.
+    pub check_packages: bool,
+    /// Install packages from fewer nixpkgs archives where the other archives have them too.
+    pub optimize_archives: bool,
+    /// Leave out the app's `nixpacks.lock`, e.g. to recreate it.
+    pub ignore_lock: bool,
+}
+
+/// Whether a provider is used for the build, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DetectionOutcome {
+    /// The first detected provider, or a later one that is more confident than the default.
+    Primary,
+    /// Detected and compatible with the primary provider.
+    Secondary,
+    /// Detected, but the primary provider can't be combined with it.
+    NotCompatible,
+    /// Detected and compatible, but not confident enough to be added automatically.
+    LowConfidence,
+    NotDetected,
+    /// Specified in the config file, environment, or CLI.
+    Manual,
+    /// Detected, but replaced by the providers that were specified manually.
+    Overridden,
+}
+
+/// The detection result of a single provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderDetection {
+    pub name: String,
+    pub detection: Detection,
+    pub outcome: DetectionOutcome,
+}
+
+impl ProviderDetection {
+    /// Whether the provider is used to generate the build plan.
+    pub fn is_used(&self) -> bool {
+        matches!(
+            self.outcome,
+            DetectionOutcome::Primary | DetectionOutcome::Secondary | DetectionOutcome::Manual
+        )
+    }
+}
+
+impl fmt::Display for ProviderDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.outcome {
+            DetectionOutcome::Primary => "primary provider",
+            DetectionOutcome::Secondary => "added, compatible with the primary provider",
+            DetectionOutcome::NotCompatible => {
+                "not used, can't be combined with the primary provider"
+            }
+            DetectionOutcome::LowConfidence => "not used, confidence too low to add automatically",
+            DetectionOutcome::NotDetected => "not detected",
+            DetectionOutcome::Manual => "specified manually",
+            DetectionOutcome::Overridden => "not used, providers were specified manually",
+        };
+
+        write!(
+            f,
+            "{} (confidence {}): {}",
+            self.name, self.detection.confidence, reason
+        )?;
+
+        if !self.detection.evidence.is_empty() {
+            write!(f, " [{}]", self.detection.evidence.join(", "))?;
+        }
+
+        fmt::Result::Ok(())
+    }
+}
+
+/// The providers used to build an app, along with the detection result of every available provider.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderSelection {
+    /// The names of the providers, in the order they are applied to the plan.
+    pub names: Vec<String>,
+    pub detections: Vec<ProviderDetection>,
 }
 
 /// Holds plan options and providers for a build.
 pub struct NixpacksBuildPlanGenerator<'a> {
     providers: &'a [&'a (dyn Provider)],
     config: GeneratePlanOptions,
+    /// External providers found on the `PATH`, discovered once per generator.
+    discovered_providers: Vec<ExternalProvider>,
 }
 
 /// NixpacksBuildPlanGenerators produce build plans using the options and providers they contain.
@@ -53,34 +148,86 @@ impl NixpacksBuildPlanGenerator<'_> {
         providers: &'a [&'a (dyn Provider)],
         config: GeneratePlanOptions,
     ) -> NixpacksBuildPlanGenerator<'a> {
-        NixpacksBuildPlanGenerator { providers, config }
+        NixpacksBuildPlanGenerator {
+            providers,
+            config,
+            discovered_providers: ExternalProvider::discover(),
+        }
     }
 
     /// Get a build plan from the provider and by applying a config from the environment.
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<(BuildPlan, App)> {
-        let plan_before_providers = self.get_plan_before_providers(app, env)?;
-
-        // Add the variables from the nixpacks.toml to environment
-        let new_env = &Environment::append_variables(
-            env,
-            plan_before_providers.variables.clone().unwrap_or_default(),
-        );
-
-        let provider_plan =
-            self.get_plan_from_providers(app, new_env, plan_before_providers.providers.clone())?;
+        let (plan, app, _) = self.get_build_plan_with_layers(app, env)?;
 
-        let procfile_plan = (ProcfileProvider {})
-            .get_build_plan(app, new_env)?
-            .unwrap_or_default();
+        Ok((plan, app))
+    }
 
-        let mut plan =
-            BuildPlan::merge_plans(&vec![provider_plan, procfile_plan, plan_before_providers]);
+    /// Get the build plan along with the plans that were merged into it.
+    fn get_build_plan_with_layers(
+        &self,
+        app: &App,
+        env: &Environment,
+    ) -> Result<(BuildPlan, App, PlanLayers)> {
+        let (layers, new_env) = self.get_plan_layers(app, env)?;
+        let new_env = &new_env;
+
+        let mut plan = BuildPlan::merge_plans(
+            &layers
+                .iter()
+                .map(|(_, layer)| layer.clone())
+                .collect::<Vec<_>>(),
+        );
+        plan.remove_secret_variables();
 
-        if !new_env.get_variable_names().is_empty() {
-            plan.add_variables(Environment::clone_variables(new_env));
+        if self.config.strict {
+            self.validate_file_plan(app, env, &plan)?;
         }
 
+        plan.add_closure_only_pkgs();
         plan.pin(new_env.is_config_variable_truthy("DEBIAN"));
+        plan.nix_lock = if self.config.ignore_lock {
+            None
+        } else {
+            NixLock::read(app)?
+        };
+        for (name, phase) in plan.phases.clone().unwrap_or_default() {
+            if let Some(file) = &phase.nix_file {
+                if !app.includes_file(file) {
+                    bail!("Phase `{name}` installs the Nix file {file}, which is not in the app");
+                }
+            }
+        }
+        if let Some(healthcheck) = plan
+            .start_phase
+            .as_ref()
+            .and_then(|start| start.healthcheck.as_ref())
+        {
+            healthcheck.validate()?;
+        }
+        if let Err(e) = plan.validate_secrets_used() {
+            if self.config.strict {
+                return Err(e);
+            }
+            eprintln!("Warning: {e}");
+        }
+        plan.nix_mirror = new_env
+            .get_config_variable("NIXPKGS_MIRROR")
+            .map(|url| NixMirror::new(&url))
+            .transpose()?;
+
+        if self.config.optimize_archives || new_env.is_config_variable_truthy("OPTIMIZE_ARCHIVES") {
+            let mut phases = plan.phases.clone().unwrap_or_default();
+            let report = ArchiveReport::load(&phases, plan.nix_sources())?;
+            if !report.merges.is_empty() {
+                eprint!("{report}");
+                report.apply(&mut phases);
+                plan.phases = Some(phases);
+            }
+        }
+
+        if self.config.check_packages && !new_env.is_config_variable_truthy("NO_PACKAGE_CHECK") {
+            check_nix_packages(&plan.phases.clone().unwrap_or_default(), plan.nix_sources())?;
+        }
         if plan.clone().phases.unwrap_or_default().is_empty() {
             // try again in a subdir
             let dir_count = app.paths.clone().iter().filter(|p| p.is_dir()).count();
@@ -88,11 +235,13 @@ impl NixpacksBuildPlanGenerator<'_> {
                 // there is 1 sub dir, try and generate a plan from that
                 let paths = app.paths.clone();
                 let new_dir = paths.iter().find(|p| p.is_dir()).unwrap();
-                return self
-                    .get_build_plan(&App::new(new_dir.display().to_string().as_str())?, env);
+                return self.get_build_plan_with_layers(
+                    &App::new(new_dir.display().to_string().as_str())?,
+                    env,
+                );
             }
         }
-        Ok((plan, app.clone()))
+        Ok((plan, app.clone(), layers))
     }
 
     /// Generate a build plan based on config files, environment variables, and CLI arguments.
@@ -105,20 +254,144 @@ impl NixpacksBuildPlanGenerator<'_> {
         Ok(plan_before_providers)
     }
 
-    /// Use each provider's detect method to determine which providers are needed for the build.
-    fn get_detected_providers(&self, app: &App, env: &Environment) -> Result<Vec<String>> {
-        let mut providers = Vec::new();
+    /// The config file to read the plan from, if there is one.
+    pub fn get_config_file_path(&self, app: &App, env: &Environment) -> Result<Option<String>> {
+        let file_path = if let Some(file_path) = &self.config.config_file {
+            Some(file_path.clone())
+        } else if let Some(env_config_file) = env.get_config_variable("CONFIG_FILE") {
+            if !app.includes_file(&env_config_file) {
+                bail!("Config file {} does not exist", env_config_file);
+            }
+
+            Some(env_config_file)
+        } else if app.includes_file("nixpacks.toml") {
+            Some("nixpacks.toml".to_owned())
+        } else if app.includes_file("nixpacks.json") {
+            Some("nixpacks.json".to_owned())
+        } else {
+            None
+        };
+
+        Ok(file_path)
+    }
+
+    /// Pick the primary provider, then add any detected providers it is compatible with.
+    ///
+    /// The first detected provider is the primary one, unless a later provider is more confident than the default.
+    /// The used providers come first, in the order they should be applied to the plan.
+    fn detect_providers(
+        &self,
+        providers: &[&dyn Provider],
+        app: &App,
+        env: &Environment,
+    ) -> Result<Vec<ProviderDetection>> {
+        let detections = providers
+            .iter()
+            .map(|provider| provider.detect_confidence(app, env))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Ties go to the provider that comes first, and a lower confidence than the default doesn't lose one
+        let primary = detections
+            .iter()
+            .enumerate()
+            .filter(|(_, detection)| detection.is_detected())
+            .fold(None, |best: Option<(usize, u8)>, (i, detection)| {
+                let confidence = detection.confidence.max(DEFAULT_CONFIDENCE);
+                match best {
+                    Some((_, best_confidence)) if best_confidence >= confidence => best,
+                    _ => Some((i, confidence)),
+                }
+            })
+            .map(|(i, _)| i);
+
+        let compatible = primary
+            .map(|i| providers[i].compatible_providers())
+            .unwrap_or_default();
 
-        for provider in self.providers {
-            if provider.detect(app, env)? {
-                providers.push(provider.name().to_string());
+        let mut results = providers
+            .iter()
+            .zip(detections)
+            .enumerate()
+            .map(|(i, (provider, detection))| {
+                let outcome = if Some(i) == primary {
+                    DetectionOutcome::Primary
+                } else if !detection.is_detected() {
+                    DetectionOutcome::NotDetected
+                } else if !compatible.contains(&provider.name()) {
+                    DetectionOutcome::NotCompatible
+                } else if detection.confidence < SECONDARY_PROVIDER_CONFIDENCE {
+                    DetectionOutcome::LowConfidence
+                } else {
+                    DetectionOutcome::Secondary
+                };
 
-                // Only match a single provider... for now
-                break;
+                ProviderDetection {
+                    name: provider.name().to_string(),
+                    detection,
+                    outcome,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        results.sort_by_key(|detection| match detection.outcome {
+            DetectionOutcome::Primary => 0,
+            DetectionOutcome::Secondary => 1,
+            _ => 2,
+        });
+
+        Ok(results)
+    }
+
+    /// Explain which providers are used for the build and why.
+    pub fn get_provider_detections(
+        &self,
+        app: &App,
+        env: &Environment,
+    ) -> Result<ProviderSelection> {
+        let plan_before_providers = self.get_plan_before_providers(app, env)?;
+        let manual_providers = plan_before_providers.providers;
+
+        let external_providers = self.get_external_providers(app, manual_providers.as_ref())?;
+        let providers = self.get_available_providers(&external_providers);
+
+        self.select_providers(&providers, app, env, manual_providers)
+    }
+
+    /// Find the external providers on the PATH, along with any referenced by path in the manually specified providers.
+    fn get_external_providers(
+        &self,
+        app: &App,
+        manual_providers: Option<&Vec<String>>,
+    ) -> Result<Vec<ExternalProvider>> {
+        let mut external_providers = Vec::new();
+        for provider in manual_providers.cloned().unwrap_or_default() {
+            if ExternalProvider::is_path_reference(&provider) {
+                external_providers.push(ExternalProvider::from_path_reference(app, &provider)?);
             }
         }
 
-        Ok(providers)
+        for provider in &self.discovered_providers {
+            if !external_providers
+                .iter()
+                .any(|p| p.name() == provider.name())
+            {
+                external_providers.push(provider.clone());
+            }
+        }
+
+        Ok(external_providers)
+    }
+
+    /// All providers that can be used for the build. External providers are checked first as they are more specific.
+    fn get_available_providers<'p>(
+        &'p self,
+        external_providers: &'p [ExternalProvider],
+    ) -> Vec<&'p dyn Provider> {
+        external_providers
+            .iter()
+            .map(|p| p as &dyn Provider)
+            .chain(self.providers.iter().copied())
+            .collect()
     }
 
     /// Get a list of providers that will be used to create the plan.
@@ -128,8 +401,45 @@ impl NixpacksBuildPlanGenerator<'_> {
         env: &Environment,
         manual_providers: Option<Vec<String>>,
     ) -> Result<Vec<String>> {
-        let detected_providers = self.get_detected_providers(app, env)?;
-        let provider_names = remove_autos_from_vec(
+        let external_providers = self.get_external_providers(app, manual_providers.as_ref())?;
+        let providers = self.get_available_providers(&external_providers);
+
+        Ok(self
+            .select_providers(&providers, app, env, manual_providers)?
+            .names)
+    }
+
+    /// Combine the detected providers with the manually specified ones, detecting each provider once.
+    fn select_providers(
+        &self,
+        providers: &[&dyn Provider],
+        app: &App,
+        env: &Environment,
+        manual_providers: Option<Vec<String>>,
+    ) -> Result<ProviderSelection> {
+        let detections = self.detect_providers(providers, app, env)?;
+        let detected_providers = detections
+            .iter()
+            .filter(|detection| detection.is_used())
+            .map(|detection| detection.name.clone())
+            .collect();
+
+        // Providers referenced by path are known by their name from here on
+        let manual_providers = manual_providers.map(|names| {
+            names
+                .into_iter()
+                .map(|name| {
+                    if ExternalProvider::is_path_reference(&name) {
+                        ExternalProvider::from_path_reference(app, &name)
+                            .map_or(name, |p| p.name().to_string())
+                    } else {
+                        name
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let names = remove_autos_from_vec(
             fill_auto_in_vec(
                 Some(detected_providers),
                 Some(manual_providers.unwrap_or_else(|| vec!["...".to_string()])),
@@ -137,73 +447,168 @@ impl NixpacksBuildPlanGenerator<'_> {
             .unwrap_or_default(),
         );
 
-        Ok(provider_names)
+        let detections = detections
+            .into_iter()
+            .map(|mut detection| {
+                let used = names.contains(&detection.name);
+                if used && !detection.is_used() {
+                    detection.outcome = DetectionOutcome::Manual;
+                } else if !used && detection.is_used() {
+                    detection.outcome = DetectionOutcome::Overridden;
+                }
+
+                detection
+            })
+            .collect();
+
+        Ok(ProviderSelection { names, detections })
     }
 
-    /// Use all detected and specified providers to generate a build plan.
-    fn get_plan_from_providers(
+    /// The plans of all detected and specified providers, from lowest to highest precedence.
+    fn get_provider_layers(
         &self,
         app: &App,
         env: &Environment,
         manual_providers: Option<Vec<String>>,
-    ) -> Result<BuildPlan> {
-        let provider_names = self.get_all_providers(app, env, manual_providers)?;
-
-        let mut plan = BuildPlan::default();
-        let mut count = 0;
+    ) -> Result<PlanLayers> {
+        let external_providers = self.get_external_providers(app, manual_providers.as_ref())?;
+        let providers = self.get_available_providers(&external_providers);
+        let provider_names = self
+            .select_providers(&providers, app, env, manual_providers)?
+            .names;
 
         let mut metadata = Vec::new();
+        let mut layers = Vec::new();
+        for (provider, provider_plan) in
+            self.get_provider_plans(&providers, &provider_names, app, env)?
+        {
+            let metadata_string = provider
+                .metadata(app, env)?
+                .join_as_comma_separated(provider.name().to_owned());
+            metadata.push(metadata_string);
+
+            layers.push((
+                PlanSource::Provider(provider.name().to_string()),
+                provider_plan,
+            ));
+        }
 
-        for name in provider_names {
-            let provider = self.providers.iter().find(|p| p.name() == name);
+        // Earlier providers take precedence over later ones
+        layers.reverse();
+        if !provider_names.is_empty() {
+            layers.push((
+                PlanSource::Default,
+                BuildPlan {
+                    variables: Some(EnvironmentVariables::from([(
+                        NIXPACKS_METADATA.to_string(),
+                        metadata.join(","),
+                    )])),
+                    ..Default::default()
+                },
+            ));
+        }
+
+        Ok(layers)
+    }
+
+    /// Get the plan of each provider, in order. All but the first provider have their phases prefixed with their name.
+    fn get_provider_plans<'p>(
+        &self,
+        providers: &[&'p dyn Provider],
+        provider_names: &[String],
+        app: &App,
+        env: &Environment,
+    ) -> Result<Vec<(&'p dyn Provider, BuildPlan)>> {
+        let mut plans = Vec::new();
+
+        for (count, name) in provider_names.iter().enumerate() {
+            let provider = providers.iter().find(|p| p.name() == name);
             if let Some(provider) = provider {
                 if let Some(mut provider_plan) = provider.get_build_plan(app, env)? {
-                    // All but the first provider have their phases prefixed with their name
+                    // For install scripts that need more than the dependency manifests
+                    if env.is_config_variable_truthy("INSTALL_ALL_FILES") {
+                        provider_plan.include_all_files_in_install();
+                    }
                     if count > 0 {
                         provider_plan.prefix_phases(provider.name());
                     }
 
-                    let metadata_string = provider
-                        .metadata(app, env)?
-                        .join_as_comma_separated(provider.name().to_owned());
-                    metadata.push(metadata_string);
-
-                    plan = BuildPlan::merge(&provider_plan, &plan);
+                    plans.push((*provider, provider_plan));
                 }
             } else if name != "..." && name != "@auto" {
                 bail!("Provider {} not found", name);
             }
+        }
+
+        Ok(plans)
+    }
+
+    /// Generate a build plan and find out which source set each of its values.
+    pub fn explain_plan(&self, app: &App, env: &Environment) -> Result<PlanExplanation> {
+        let (plan, _, layers) = self.get_build_plan_with_layers(app, env)?;
 
-            count += 1;
+        Ok(PlanExplanation::new(&layers, &plan))
+    }
+
+    /// The plans that are merged into the build plan, from lowest to highest precedence,
+    /// along with the environment the providers see, which includes the variables of the config file.
+    fn get_plan_layers(&self, app: &App, env: &Environment) -> Result<(PlanLayers, Environment)> {
+        let file_plan = self.read_file_plan(app, env)?;
+        let env_plan = BuildPlan::from_environment(env);
+        let cli_plan = self.config.plan.clone().unwrap_or_default();
+        let plan_before_providers =
+            BuildPlan::merge_plans(&[file_plan.clone(), env_plan.clone(), cli_plan.clone()]);
+
+        // Add the variables from the nixpacks.toml to environment
+        let file_variables = plan_before_providers.variables.clone().unwrap_or_default();
+        let new_env = Environment::append_variables(env, file_variables.clone());
+
+        let mut layers =
+            self.get_provider_layers(app, &new_env, plan_before_providers.providers)?;
+
+        if let Some(procfile_plan) = (ProcfileProvider {}).get_build_plan(app, &new_env)? {
+            layers.push((PlanSource::Procfile, procfile_plan));
         }
 
-        if count > 0 {
-            plan.add_variables(EnvironmentVariables::from([(
-                NIXPACKS_METADATA.to_string(),
-                metadata.join(","),
-            )]));
+        let file_source = self
+            .get_config_file_path(app, env)?
+            .unwrap_or_else(|| "config file".to_string());
+        layers.push((PlanSource::ConfigFile(file_source), file_plan));
+        layers.push((PlanSource::Environment, env_plan));
+        layers.push((PlanSource::Cli, cli_plan));
+
+        // Variables passed to the build are added last, but don't replace the ones from the config file
+        let mut env_variables = Environment::clone_variables(env);
+        env_variables.retain(|name, _| !file_variables.contains_key(name));
+        if !env_variables.is_empty() {
+            layers.push((
+                PlanSource::Environment,
+                BuildPlan {
+                    variables: Some(env_variables),
+                    ..Default::default()
+                },
+            ));
         }
 
-        Ok(plan)
+        Ok((layers, new_env))
+    }
+
+    /// Check the phases of the config file against the merged plan.
+    fn validate_file_plan(&self, app: &App, env: &Environment, plan: &BuildPlan) -> Result<()> {
+        if let Some(file_path) = self.get_config_file_path(app, env)? {
+            let contents = app.read_file(file_path.as_str())?;
+            let file_plan = self.read_file_plan(app, env)?;
+
+            plan.validate_config_phases(&file_plan, &contents)
+                .with_context(|| format!("Invalid Nixpacks config file `{file_path}`"))?;
+        }
+
+        Ok(())
     }
 
     /// If a supported config file exists, use it to generate a build plan.
     fn read_file_plan(&self, app: &App, env: &Environment) -> Result<BuildPlan> {
-        let file_path = if let Some(file_path) = &self.config.config_file {
-            Some(file_path.clone())
-        } else if let Some(env_config_file) = env.get_config_variable("CONFIG_FILE") {
-            if !app.includes_file(&env_config_file) {
-                bail!("Config file {} does not exist", env_config_file);
-            }
-
-            Some(env_config_file)
-        } else if app.includes_file("nixpacks.toml") {
-            Some("nixpacks.toml".to_owned())
-        } else if app.includes_file("nixpacks.json") {
-            Some("nixpacks.json".to_owned())
-        } else {
-            None
-        };
+        let file_path = self.get_config_file_path(app, env)?;
 
         let plan =
             if let Some(file_path) = file_path {
@@ -213,8 +618,12 @@ impl NixpacksBuildPlanGenerator<'_> {
                 let contents = app.read_file(file_path.as_str()).with_context(|| {
                     format!("Failed to read Nixpacks config file `{file_path}`")
                 })?;
-                let plan = if ext == "toml" {
+                let plan = if ext == "toml" && self.config.strict {
+                    BuildPlan::from_toml_strict(&contents)
+                } else if ext == "toml" {
                     BuildPlan::from_toml(&contents)
+                } else if ext == "json" && self.config.strict {
+                    BuildPlan::from_json_strict(&contents)
                 } else if ext == "json" {
                     BuildPlan::from_json(&contents)
                 } else {
@@ -1,8 +1,9 @@
 use super::{
-    phase::{Phase, StartPhase},
+    phase::{Healthcheck, Phase, StartPhase},
     utils::fill_auto_in_vec,
     BuildPlan,
 };
+use crate::nixpacks::nix::cache::NixCacheConfig;
 
 /// Types that impl this trait can be pairwise combined.
 pub trait Mergeable {
@@ -17,6 +18,8 @@ impl Mergeable for BuildPlan {
 
         new_plan.providers = fill_auto_in_vec(new_plan.providers.clone(), plan2.providers.clone());
         new_plan.build_image = plan2.build_image.or(new_plan.build_image);
+        new_plan.nix_lock = plan2.nix_lock.or(new_plan.nix_lock);
+        new_plan.nix_mirror = plan2.nix_mirror.or(new_plan.nix_mirror);
 
         new_plan.static_assets = match (new_plan.static_assets, plan2.static_assets) {
             (None, assets) | (assets, None) => assets,
@@ -36,6 +39,15 @@ impl Mergeable for BuildPlan {
             }
         };
 
+        new_plan.secrets = match (new_plan.secrets, plan2.secrets) {
+            (None, secrets) | (secrets, None) => secrets,
+            (Some(secrets1), Some(secrets2)) => {
+                let mut secrets = secrets1;
+                secrets.extend(secrets2);
+                Some(secrets)
+            }
+        };
+
         if new_plan.phases.is_none() {
             new_plan.phases = plan2.phases;
         } else {
@@ -57,6 +69,18 @@ impl Mergeable for BuildPlan {
             }
         }
 
+        new_plan.nix_cache = match (new_plan.nix_cache, plan2.nix_cache) {
+            (None, cache) | (cache, None) => cache,
+            (Some(cache1), Some(cache2)) => Some(NixCacheConfig {
+                substituters: merge_lists(cache1.substituters, cache2.substituters),
+                trusted_public_keys: merge_lists(
+                    cache1.trusted_public_keys,
+                    cache2.trusted_public_keys,
+                ),
+                push: cache2.push.or(cache1.push),
+            }),
+        };
+
         new_plan.start_phase = match (new_plan.start_phase, plan2.start_phase) {
             (None, s) | (s, None) => s,
             (Some(s1), Some(s2)) => Some(StartPhase::merge(&s1, &s2)),
@@ -73,6 +97,16 @@ impl Mergeable for Phase {
         let mut phase = c1.clone();
         let c2 = c2.clone();
         phase.nixpkgs_archive = c2.nixpkgs_archive.or_else(|| phase.nixpkgs_archive.clone());
+        phase.nix_expression = c2.nix_expression.or_else(|| phase.nix_expression.clone());
+        phase.nix_file = c2.nix_file.or_else(|| phase.nix_file.clone());
+        phase.nix_exports = match (phase.nix_exports.clone(), c2.nix_exports) {
+            (None, exports) | (exports, None) => exports,
+            (Some(exports1), Some(exports2)) => {
+                let mut exports = exports1;
+                exports.extend(exports2);
+                Some(exports)
+            }
+        };
 
         phase.cmds = fill_auto_in_vec(phase.cmds.clone(), c2.cmds);
         phase.depends_on = fill_auto_in_vec(phase.depends_on.clone(), c2.depends_on);
@@ -85,6 +119,7 @@ impl Mergeable for Phase {
         phase.cache_directories =
             fill_auto_in_vec(phase.cache_directories.clone(), c2.cache_directories);
         phase.paths = fill_auto_in_vec(phase.paths.clone(), c2.paths);
+        phase.secrets = fill_auto_in_vec(phase.secrets.clone(), c2.secrets);
 
         phase
     }
@@ -95,17 +130,49 @@ impl Mergeable for StartPhase {
     fn merge(c1: &StartPhase, c2: &StartPhase) -> StartPhase {
         let mut start_phase = c1.clone();
         let c2 = c2.clone();
-        start_phase.cmd = c2.cmd.or_else(|| start_phase.cmd.clone());
+        // The command and processes both say what to run, so they are replaced together
+        if c2.cmd.is_some() || c2.processes.is_some() {
+            start_phase.cmd = c2.cmd;
+            start_phase.processes = c2.processes;
+        }
+        start_phase.restart = c2.restart.or(start_phase.restart);
         start_phase.run_image = c2.run_image.or_else(|| start_phase.run_image.clone());
+        start_phase.closure_only = c2.closure_only.or(start_phase.closure_only);
         start_phase.only_include_files = fill_auto_in_vec(
             start_phase.only_include_files.clone(),
             c2.only_include_files,
         );
         start_phase.user = c2.user.or_else(|| start_phase.user.clone());
+        start_phase.ports = c2.ports.or_else(|| start_phase.ports.clone());
+        start_phase.healthcheck = match (start_phase.healthcheck.clone(), c2.healthcheck) {
+            (Some(h1), Some(h2)) => Some(Healthcheck {
+                cmd: h2.cmd.or(h1.cmd),
+                interval: h2.interval.or(h1.interval),
+                timeout: h2.timeout.or(h1.timeout),
+                retries: h2.retries.or(h1.retries),
+            }),
+            (h1, h2) => h2.or(h1),
+        };
+        start_phase.stop_signal = c2.stop_signal.or_else(|| start_phase.stop_signal.clone());
         start_phase
     }
 }
 
+/// Both lists, without repeating the items of the first in the second.
+fn merge_lists(l1: Option<Vec<String>>, l2: Option<Vec<String>>) -> Option<Vec<String>> {
+    match (l1, l2) {
+        (None, l) | (l, None) => l,
+        (Some(mut l1), Some(l2)) => {
+            for item in l2 {
+                if !l1.contains(&item) {
+                    l1.push(item);
+                }
+            }
+            Some(l1)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -209,4 +276,73 @@ mod test {
             merged
         );
     }
+    #[test]
+    fn test_merge_healthcheck() {
+        let merged = BuildPlan::merge(
+            &BuildPlan::from_toml(
+                r#"
+                [start]
+                ports = [80]
+                healthcheck = { cmd = "curl -f http://localhost/", interval = "30s" }
+                "#,
+            )
+            .unwrap(),
+            &BuildPlan::from_toml(
+                r#"
+                [start]
+                stopSignal = "SIGQUIT"
+                healthcheck = { interval = "10s", retries = 5 }
+                "#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            BuildPlan::from_toml(
+                r#"
+                [start]
+                ports = [80]
+                stopSignal = "SIGQUIT"
+                healthcheck = { cmd = "curl -f http://localhost/", interval = "10s", retries = 5 }
+                "#,
+            )
+            .unwrap(),
+            merged
+        );
+    }
+
+    #[test]
+    fn test_merge_nix_cache() {
+        let merged = BuildPlan::merge(
+            &BuildPlan::from_toml(
+                r#"
+                [nixCache]
+                substituters = ["https://cache.example.com"]
+                push = "file:///var/cache/nix"
+                "#,
+            )
+            .unwrap(),
+            &BuildPlan::from_toml(
+                r#"
+                [nixCache]
+                substituters = ["https://cache.example.com", "file:///var/cache/nix"]
+                trustedPublicKeys = ["cache.example.com-1:abc="]
+                "#,
+            )
+            .unwrap(),
+        );
+
+        assert_eq!(
+            BuildPlan::from_toml(
+                r#"
+                [nixCache]
+                substituters = ["https://cache.example.com", "file:///var/cache/nix"]
+                trustedPublicKeys = ["cache.example.com-1:abc="]
+                push = "file:///var/cache/nix"
+                "#,
+            )
+            .unwrap(),
+            merged
+        );
+    }
 }
@@ -1,24 +1,36 @@
 use self::{
     merge::Mergeable,
     phase::{Phase, Phases, StartPhase},
+    secrets::Secrets,
     topological_sort::topological_sort,
 };
 use super::images::{DEBIAN_BASE_IMAGE, UBUNTU_BASE_IMAGE};
 use crate::nixpacks::{
     app::{App, StaticAssets},
     environment::{Environment, EnvironmentVariables},
+    nix::{
+        cache::NixCacheConfig,
+        lock::NixLock,
+        mirror::{NixMirror, NixSources},
+        pkg::Pkg,
+    },
 };
 use anyhow::Result;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 // pub mod config;
+pub mod diff;
+pub mod explain;
 pub mod generator;
 pub mod merge;
 pub mod phase;
 pub mod pretty_print;
+pub mod secrets;
 mod topological_sort;
 pub mod utils;
+pub mod validate;
 
 /// Types that impl this trait can generate build plans.
 pub trait PlanGenerator {
@@ -27,7 +39,7 @@ pub trait PlanGenerator {
 }
 
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Eq, Default, Debug, Serialize, Deserialize, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 /// Contains all information needed to build a project.
 ///
@@ -41,6 +53,8 @@ pub struct BuildPlan {
 
     pub variables: Option<EnvironmentVariables>,
 
+    pub secrets: Option<Secrets>,
+
     #[serde(rename = "staticAssets")]
     pub static_assets: Option<StaticAssets>,
 
@@ -48,6 +62,17 @@ pub struct BuildPlan {
 
     #[serde(rename = "start")]
     pub start_phase: Option<StartPhase>,
+
+    #[serde(rename = "nixCache")]
+    pub nix_cache: Option<NixCacheConfig>,
+
+    /// The app's `nixpacks.lock`, which pins the Nix dependencies of the phases.
+    #[serde(skip)]
+    pub nix_lock: Option<NixLock>,
+
+    /// Where nixpkgs archives and overlays are fetched from instead of GitHub, from `NIXPACKS_NIXPKGS_MIRROR`.
+    #[serde(skip)]
+    pub nix_mirror: Option<NixMirror>,
 }
 
 impl BuildPlan {
@@ -314,6 +339,36 @@ impl BuildPlan {
         }
     }
 
+    /// Install the Nix packages a closure-only image runs its start command with: `bash`, and `coreutils` for the supervisor.
+    pub fn add_closure_only_pkgs(&mut self) {
+        if !self
+            .start_phase
+            .clone()
+            .unwrap_or_default()
+            .is_closure_only()
+        {
+            return;
+        }
+
+        let pkgs = ["bash", "coreutils"].map(Pkg::new);
+        match self.get_phase_mut("setup") {
+            Some(setup) => {
+                let installed = setup.nix_pkgs.clone().unwrap_or_default();
+                let missing = pkgs
+                    .into_iter()
+                    .filter(|pkg| !installed.contains(&pkg.to_nix_string()))
+                    .collect::<Vec<_>>();
+                setup.add_nix_pkgs(&missing);
+            }
+            None => self.add_phase(Phase::setup(Some(pkgs.to_vec()))),
+        }
+    }
+
+    /// Where the Nix dependencies of the phases are fetched from.
+    pub fn nix_sources(&self) -> NixSources<'_> {
+        NixSources::new(self.nix_lock.as_ref(), self.nix_mirror.as_ref())
+    }
+
     /// Prefix each phase name with the name of the provider that generated the phase, in the case of multiple providers.
     pub fn prefix_phases(&mut self, prefix: &str) {
         if let Some(phases) = self.phases.clone() {
@@ -331,6 +386,35 @@ impl BuildPlan {
         }
     }
 
+    /// Whether the phases copy some files of the app into the image, like dependency manifests, but not the whole app.
+    pub fn copies_some_files(&self) -> Result<bool> {
+        let file_lists = self
+            .get_sorted_phases()?
+            .into_iter()
+            .filter(Phase::runs_docker_commands)
+            .map(|phase| phase.only_include_files)
+            .collect::<Vec<_>>();
+
+        let copies_app = file_lists.iter().any(|files| match files {
+            Some(files) => files.iter().any(|file| file == "." || file == "./"),
+            None => true,
+        });
+        let copies_files = file_lists
+            .iter()
+            .any(|files| files.as_ref().map_or(false, |files| !files.is_empty()));
+
+        Ok(copies_files && !copies_app)
+    }
+
+    /// Copy the whole app before the install phases, instead of only the dependency manifests they declare.
+    pub fn include_all_files_in_install(&mut self) {
+        for (name, phase) in self.phases.iter_mut().flatten() {
+            if name == "install" || name.ends_with(":install") {
+                phase.only_include_files = None;
+            }
+        }
+    }
+
     /// Combine plans from multiple sources (environment variables, config files, providers) into a single plan.
     pub fn merge_plans(plans: &[BuildPlan]) -> BuildPlan {
         plans.iter().fold(BuildPlan::default(), |acc, plan| {
@@ -493,6 +577,32 @@ mod test {
         assert!(plan.get_phase("setup").unwrap().nixpkgs_archive.is_some());
     }
 
+    #[test]
+    fn test_include_all_files_in_install() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [phases.install]
+            cmds = ["npm ci"]
+            onlyIncludeFiles = ["package.json", "package-lock.json"]
+
+            [phases.build]
+            cmds = ["npm run build"]
+            dependsOn = ["install"]
+            "#,
+        )
+        .unwrap();
+        assert!(!plan.copies_some_files().unwrap());
+
+        let mut build = plan.get_phase("build").unwrap().clone();
+        build.add_file_dependency("dist".to_string());
+        plan.add_phase(build);
+        assert!(plan.copies_some_files().unwrap());
+
+        plan.include_all_files_in_install();
+        assert_eq!(plan.get_phase("install").unwrap().only_include_files, None);
+        assert!(!plan.copies_some_files().unwrap());
+    }
+
     #[test]
     fn test_split_env_string() {
         assert_eq!(
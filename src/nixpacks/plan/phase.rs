@@ -2,17 +2,21 @@ use crate::nixpacks::{
     images::{DEFAULT_BASE_IMAGE, STANDALONE_IMAGE},
     nix::{pkg::Pkg, NIXPACKS_ARCHIVE_LEGACY_OPENSSL, NIXPKGS_ARCHIVE},
 };
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::hash::Hash;
+use std::time::Duration;
 
-use super::utils::remove_autos_from_vec;
+use super::utils::{parse_duration, remove_autos_from_vec};
 
 pub type Phases = BTreeMap<String, Phase>;
 
 /// Holds the packages, commands, and directories needed for part of a build.
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct Phase {
     pub name: Option<String>,
@@ -30,6 +34,16 @@ pub struct Phase {
 
     pub nixpkgs_archive: Option<String>,
 
+    /// A Nix expression installed alongside the packages, with the packages of the archive in scope,
+    /// e.g. `python3.withPackages (ps: [ ps.numpy ])`.
+    pub nix_expression: Option<String>,
+
+    /// A Nix file in the app, called with `callPackage` and installed alongside the packages.
+    pub nix_file: Option<String>,
+
+    /// Environment variables set from the locations of Nix packages, e.g. `PKG_CONFIG_PATH = "${libpq.dev}/lib/pkgconfig"`.
+    pub nix_exports: Option<BTreeMap<String, String>>,
+
     #[serde(alias = "aptPackages")]
     pub apt_pkgs: Option<Vec<String>>,
 
@@ -44,17 +58,112 @@ pub struct Phase {
 
     #[serde(alias = "envPaths")]
     pub paths: Option<Vec<String>>,
+
+    /// Ids of the secrets the commands of this phase can read. Defaults to none.
+    pub secrets: Option<Vec<String>>,
 }
 
 /// Represents the final step of a container image, contains the startup command, any necessary files, and the final image that gets run by Docker.
 #[serde_with::skip_serializing_none]
-#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug)]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct StartPhase {
     pub cmd: Option<String>,
     pub run_image: Option<String>,
     pub only_include_files: Option<Vec<String>>,
     pub user: Option<String>,
+
+    /// Copy only the Nix store paths the start command and app files need into the final image, on top of `run_image` or an empty image.
+    pub closure_only: Option<bool>,
+
+    /// Named commands that run side by side under a supervisor. Replaces `cmd` when set.
+    pub processes: Option<BTreeMap<String, String>>,
+
+    /// Whether the supervisor restarts processes that exit.
+    pub restart: Option<RestartPolicy>,
+
+    /// Ports the app listens on.
+    pub ports: Option<Vec<u16>>,
+
+    /// How the container engine checks that the app is healthy.
+    pub healthcheck: Option<Healthcheck>,
+
+    /// The signal that stops the container, e.g. `SIGQUIT`.
+    pub stop_signal: Option<String>,
+}
+
+/// A command run periodically in the container, which exits with zero while the app is healthy.
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Healthcheck {
+    pub cmd: Option<String>,
+
+    /// Time between checks, e.g. `30s`.
+    pub interval: Option<String>,
+
+    /// Time after which a check is considered failed, e.g. `5s`.
+    pub timeout: Option<String>,
+
+    /// Number of failed checks in a row before the container is unhealthy.
+    pub retries: Option<u32>,
+}
+
+impl Healthcheck {
+    /// Time between checks, if set.
+    pub fn get_interval(&self) -> Result<Option<Duration>> {
+        self.interval
+            .as_deref()
+            .map(|interval| parse_duration(interval, &HEALTHCHECK_DURATION_UNITS))
+            .transpose()
+    }
+
+    /// Time after which a check is considered failed, if set.
+    pub fn get_timeout(&self) -> Result<Option<Duration>> {
+        self.timeout
+            .as_deref()
+            .map(|timeout| parse_duration(timeout, &HEALTHCHECK_DURATION_UNITS))
+            .transpose()
+    }
+
+    /// Check the healthcheck when the plan is generated, instead of when the image is built.
+    pub fn validate(&self) -> Result<()> {
+        if self.cmd.is_none() {
+            bail!("The healthcheck of the start phase is missing a command");
+        }
+        self.get_interval()
+            .context("Invalid healthcheck interval")?;
+        self.get_timeout().context("Invalid healthcheck timeout")?;
+        Ok(())
+    }
+}
+
+/// The units healthcheck durations can use, as Docker accepts them.
+const HEALTHCHECK_DURATION_UNITS: [&str; 4] = ["ms", "s", "m", "h"];
+
+/// When the supervisor restarts a process of the start phase.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Never
+    }
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::OnFailure => write!(f, "on-failure"),
+            RestartPolicy::Always => write!(f, "always"),
+        }
+    }
 }
 
 impl Phase {
@@ -104,7 +213,9 @@ impl Phase {
                 .clone()
                 .map(|pkgs| pkgs.iter().map(Pkg::to_nix_string).collect()),
             nix_overlays: pkgs
+                .clone()
                 .map(|pkgs| pkgs.iter().filter_map(|pkg| pkg.overlay.clone()).collect()),
+            nix_exports: pkgs.and_then(|pkgs| get_pkg_exports(&pkgs)),
             name: Some("setup".to_string()),
             ..Default::default()
         }
@@ -134,6 +245,8 @@ impl Phase {
     pub fn uses_nix(&self) -> bool {
         !self.nix_pkgs.clone().unwrap_or_default().is_empty()
             || !self.nix_libs.clone().unwrap_or_default().is_empty()
+            || self.nix_expression.is_some()
+            || self.nix_file.is_some()
     }
 
     /// Whether or not the phase runs any docker commands
@@ -160,6 +273,16 @@ impl Phase {
             self.nix_pkgs.clone(),
             new_pkgs.iter().map(Pkg::to_nix_string).collect(),
         ));
+        if let Some(exports) = get_pkg_exports(new_pkgs) {
+            self.add_nix_exports(exports);
+        }
+    }
+
+    /// Set environment variables from the locations of Nix packages in this phase.
+    pub fn add_nix_exports(&mut self, exports: BTreeMap<String, String>) {
+        self.nix_exports
+            .get_or_insert_with(BTreeMap::new)
+            .extend(exports);
     }
 
     /// Add a collection of libraries to install with Nix in this phase.
@@ -170,6 +293,14 @@ impl Phase {
         ));
     }
 
+    /// Add a collection of libraries to install with Nix in this phase, along with the environment variables they set.
+    pub fn add_nix_libs(&mut self, new_libraries: &[Pkg]) {
+        self.add_pkgs_libs(new_libraries.iter().map(Pkg::to_nix_string).collect());
+        if let Some(exports) = get_pkg_exports(new_libraries) {
+            self.add_nix_exports(exports);
+        }
+    }
+
     /// Add a collection of packages to install with apt in this phase.
     pub fn add_apt_pkgs(&mut self, new_pkgs: Vec<String>) {
         self.apt_pkgs = Some(add_multiple_to_option_vec(self.apt_pkgs.clone(), new_pkgs));
@@ -188,6 +319,14 @@ impl Phase {
         ));
     }
 
+    /// Only copy these files into the container before this phase, e.g. the dependency manifests of an install phase.
+    pub fn add_file_dependencies(&mut self, files: Vec<String>) {
+        self.only_include_files = Some(add_multiple_to_option_vec(
+            self.only_include_files.clone(),
+            files,
+        ));
+    }
+
     /// Add a directory in which language-specific packages get installed.
     pub fn add_cache_directory<S: Into<String>>(&mut self, dir: S) {
         let mut new_directories = prevent_duplicates_vec(add_to_option_vec(
@@ -238,6 +377,19 @@ impl StartPhase {
         }
     }
 
+    /// Run each of the named commands as a separate process.
+    pub fn from_processes(processes: BTreeMap<String, String>) -> Self {
+        Self {
+            processes: Some(processes),
+            ..Default::default()
+        }
+    }
+
+    /// Add a port the app listens on.
+    pub fn expose_port(&mut self, port: u16) {
+        self.ports = Some(add_to_option_vec(self.ports.clone(), port));
+    }
+
     /// Set the container image in which to run the StartPhase.
     pub fn run_in_image(&mut self, image_name: String) {
         self.run_image = Some(image_name);
@@ -253,6 +405,16 @@ impl StartPhase {
         self.run_image = Some(STANDALONE_IMAGE.to_string());
     }
 
+    /// Run the StartPhase in an image with only the Nix store paths the start command needs.
+    pub fn run_closure_only(&mut self) {
+        self.closure_only = Some(true);
+    }
+
+    /// Whether the final image only gets the Nix store paths the start command needs.
+    pub fn is_closure_only(&self) -> bool {
+        self.closure_only.unwrap_or_default()
+    }
+
     /// Add a file to the set of files to copy into the container image.
     pub fn add_file_dependency<S: Into<String>>(&mut self, file: S) {
         self.only_include_files = Some(add_to_option_vec(
@@ -267,6 +429,19 @@ impl StartPhase {
     }
 }
 
+/// The environment variables a collection of packages set, if any.
+fn get_pkg_exports(pkgs: &[Pkg]) -> Option<BTreeMap<String, String>> {
+    let exports = pkgs
+        .iter()
+        .flat_map(|pkg| pkg.exports.clone().unwrap_or_default())
+        .collect::<BTreeMap<_, _>>();
+    if exports.is_empty() {
+        None
+    } else {
+        Some(exports)
+    }
+}
+
 /// Store the list of options for this phase for later reproducibility.
 fn pin_option_vec(vec: Option<&Vec<String>>) -> Option<Vec<String>> {
     vec.map(|vec| remove_autos_from_vec(vec.clone()))
@@ -297,3 +472,63 @@ fn prevent_duplicates_vec<T: Clone + Eq + Hash>(values: Vec<T>) -> Vec<T> {
     let set: HashSet<T> = values.iter().cloned().collect::<HashSet<_>>();
     set.into_iter().collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_healthcheck_durations() {
+        let healthcheck = Healthcheck {
+            interval: Some("1m30s".to_string()),
+            timeout: Some("500ms".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            healthcheck.get_interval().unwrap(),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            healthcheck.get_timeout().unwrap(),
+            Some(Duration::from_millis(500))
+        );
+
+        // Docker doesn't accept days
+        assert!(Healthcheck {
+            interval: Some("1d".to_string()),
+            ..Default::default()
+        }
+        .get_interval()
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_healthcheck() {
+        let healthcheck = Healthcheck {
+            cmd: Some("curl -f http://localhost/".to_string()),
+            interval: Some("30s".to_string()),
+            timeout: Some("5s".to_string()),
+            retries: Some(3),
+        };
+        assert!(healthcheck.validate().is_ok());
+
+        assert!(Healthcheck {
+            interval: Some("30 seconds".to_string()),
+            ..healthcheck.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Healthcheck {
+            timeout: Some("5".to_string()),
+            ..healthcheck.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(Healthcheck {
+            cmd: None,
+            ..healthcheck
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -22,12 +22,15 @@ impl BuildPlan {
             .map(|phase| (phase.get_name(), self.get_phase_content(phase).unwrap()))
             .collect::<Vec<_>>();
 
-        let start_contents = self
-            .start_phase
-            .clone()
-            .unwrap_or_default()
-            .cmd
-            .unwrap_or_default();
+        let start = self.start_phase.clone().unwrap_or_default();
+        let start_contents = match &start.processes {
+            Some(processes) if !processes.is_empty() => processes
+                .iter()
+                .map(|(name, cmd)| format!("{name}: {cmd}"))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => start.cmd.unwrap_or_default(),
+        };
 
         let max_right_content = phase_contents
             .iter()
@@ -0,0 +1,284 @@
+use super::{phase::Phase, BuildPlan};
+use crate::nixpacks::environment::Environment;
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env, fs};
+
+pub type Secrets = BTreeMap<String, Secret>;
+
+/// Where the value of a build secret comes from. Plans only ever hold the source, never the value.
+#[serde_with::skip_serializing_none]
+#[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone, Debug, JsonSchema)]
+pub struct Secret {
+    /// Environment variable holding the value. Defaults to the id of the secret.
+    pub env: Option<String>,
+
+    /// File holding the value.
+    pub src: Option<String>,
+}
+
+impl Secret {
+    /// Parse a `--secret` argument, e.g. `id=NPM_TOKEN,env=NPM_TOKEN` or `id=NETRC,src=./.netrc`.
+    pub fn parse(arg: &str) -> Result<(String, Secret)> {
+        let mut id = None;
+        let mut secret = Secret::default();
+
+        for field in arg.split(',') {
+            match field.split_once('=') {
+                Some(("id", value)) => id = Some(value.to_string()),
+                Some(("env", value)) => secret.env = Some(value.to_string()),
+                Some(("src" | "source", value)) => secret.src = Some(value.to_string()),
+                _ => bail!("Invalid secret `{arg}`, expected `id=<id>[,env=<var>|,src=<file>]`"),
+            }
+        }
+
+        match id {
+            Some(id) if !id.is_empty() => {
+                if secret.env.is_some() && secret.src.is_some() {
+                    bail!("Secret {id} can only have one of `env` and `src`");
+                }
+                Ok((id, secret))
+            }
+            _ => bail!("Secret `{arg}` is missing an id"),
+        }
+    }
+
+    /// The environment variable holding the value, if it doesn't come from a file.
+    pub fn get_env_name(&self, id: &str) -> Option<String> {
+        match (&self.env, &self.src) {
+            (Some(env), _) => Some(env.clone()),
+            (None, Some(_)) => None,
+            (None, None) => Some(id.to_string()),
+        }
+    }
+
+    /// The `--secret` argument that passes this secret to `docker build`.
+    pub fn get_build_arg(&self, id: &str) -> String {
+        match &self.src {
+            Some(src) => format!("id={id},src={src}"),
+            None => format!("id={id},env={}", self.env.as_deref().unwrap_or(id)),
+        }
+    }
+
+    /// Read the value of this secret, from the build environment or the calling environment.
+    pub fn read_value(&self, id: &str, environment: &Environment) -> Result<String> {
+        if let Some(src) = &self.src {
+            return fs::read_to_string(src).with_context(|| format!("Reading secret {id}"));
+        }
+
+        let name = self.env.as_deref().unwrap_or(id);
+        match environment.get_variable(name) {
+            Some(value) => Ok(value.to_string()),
+            None => env::var(name)
+                .with_context(|| format!("Secret {id} is not set, set the {name} variable")),
+        }
+    }
+}
+
+impl BuildPlan {
+    /// The ids of the secrets a phase can read. Phases that don't list any get none.
+    pub fn get_phase_secrets(&self, phase: &Phase) -> Result<Vec<String>> {
+        let secrets = self.secrets.clone().unwrap_or_default();
+        // Secrets are exposed to commands as environment variables named after their id
+        if let Some(id) = secrets.keys().find(|id| !is_variable_name(id)) {
+            bail!("Secret id {id} must be a valid environment variable name");
+        }
+
+        phase
+            .secrets
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|id| id.as_str() != "...")
+            .map(|id| {
+                if secrets.contains_key(&id) {
+                    Ok(id)
+                } else {
+                    bail!(
+                        "Phase {} uses secret {id}, which is not defined",
+                        phase.get_name()
+                    )
+                }
+            })
+            .collect()
+    }
+
+    /// Check that every secret is listed by a phase, naming the phases that could list the ones that aren't.
+    pub fn validate_secrets_used(&self) -> Result<()> {
+        let phases = self.phases.clone().unwrap_or_default();
+        let unused = self
+            .secrets
+            .clone()
+            .unwrap_or_default()
+            .into_keys()
+            .filter(|id| {
+                !phases
+                    .values()
+                    .any(|phase| phase.secrets.clone().unwrap_or_default().contains(id))
+            })
+            .collect::<Vec<_>>();
+        if unused.is_empty() {
+            return Ok(());
+        }
+
+        let phase_names = phases
+            .iter()
+            .filter(|(_, phase)| !phase.cmds.clone().unwrap_or_default().is_empty())
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        bail!(
+            "Secret {} is not used by any phase, list it in the `secrets` of the phases that need it ({})",
+            unused.join(", "),
+            phase_names.join(", ")
+        )
+    }
+
+    /// Remove variables holding secret values, so they never end up in the image.
+    pub fn remove_secret_variables(&mut self) {
+        let secrets = self.secrets.clone().unwrap_or_default();
+        if let Some(variables) = &mut self.variables {
+            variables.retain(|name, _| {
+                !secrets.iter().any(|(id, secret)| {
+                    name == id || secret.get_env_name(id).as_ref() == Some(name)
+                })
+            });
+        }
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_secret() {
+        assert_eq!(
+            Secret::parse("id=NPM_TOKEN,env=MY_NPM_TOKEN").unwrap(),
+            (
+                "NPM_TOKEN".to_string(),
+                Secret {
+                    env: Some("MY_NPM_TOKEN".to_string()),
+                    src: None
+                }
+            )
+        );
+        assert_eq!(
+            Secret::parse("id=NETRC,src=.netrc")
+                .unwrap()
+                .1
+                .get_build_arg("NETRC"),
+            "id=NETRC,src=.netrc"
+        );
+        assert_eq!(
+            Secret::parse("id=NPM_TOKEN")
+                .unwrap()
+                .1
+                .get_build_arg("NPM_TOKEN"),
+            "id=NPM_TOKEN,env=NPM_TOKEN"
+        );
+        assert!(Secret::parse("env=NPM_TOKEN").is_err());
+        assert!(Secret::parse("id=NPM_TOKEN,foo=bar").is_err());
+    }
+
+    #[test]
+    fn test_phase_secrets() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [secrets]
+            NPM_TOKEN = {}
+            SENTRY_TOKEN = { env = "SENTRY_AUTH_TOKEN" }
+
+            [phases.install]
+            secrets = ["NPM_TOKEN"]
+
+            [phases.build]
+
+            [phases.release]
+            secrets = ["GITHUB_TOKEN"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            plan.get_phase_secrets(plan.get_phase("install").unwrap())
+                .unwrap(),
+            vec!["NPM_TOKEN"]
+        );
+        assert_eq!(
+            plan.get_phase_secrets(plan.get_phase("build").unwrap())
+                .unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(plan
+            .get_phase_secrets(plan.get_phase("release").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_validate_secrets_used() {
+        let plan = BuildPlan::from_toml(
+            r#"
+            [secrets]
+            NPM_TOKEN = {}
+            SENTRY_TOKEN = {}
+
+            [phases.install]
+            cmds = ["npm ci"]
+            secrets = ["NPM_TOKEN"]
+
+            [phases.build]
+            cmds = ["npm run build"]
+
+            [phases.setup]
+            nixPkgs = ["nodejs"]
+            "#,
+        )
+        .unwrap();
+
+        let err = plan.validate_secrets_used().unwrap_err().to_string();
+        assert!(err.contains("Secret SENTRY_TOKEN is not used"));
+        assert!(err.contains("(build, install)"));
+
+        let plan = BuildPlan::from_toml(
+            r#"
+            [secrets]
+            NPM_TOKEN = {}
+
+            [phases.install]
+            secrets = ["NPM_TOKEN"]
+            "#,
+        )
+        .unwrap();
+        assert!(plan.validate_secrets_used().is_ok());
+    }
+
+    #[test]
+    fn test_remove_secret_variables() {
+        let mut plan = BuildPlan::from_toml(
+            r#"
+            [secrets]
+            NPM_TOKEN = {}
+            SENTRY_TOKEN = { env = "SENTRY_AUTH_TOKEN" }
+
+            [variables]
+            NPM_TOKEN = "abc"
+            SENTRY_AUTH_TOKEN = "def"
+            NODE_ENV = "production"
+            "#,
+        )
+        .unwrap();
+        plan.remove_secret_variables();
+
+        assert_eq!(
+            plan.variables.unwrap().keys().collect::<Vec<_>>(),
+            vec!["NODE_ENV"]
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use anyhow::{bail, Context, Result};
+use std::time::Duration;
+
 /// Removes all the `"..."`'s or `"@auto"`'s from the `original`
 pub fn remove_autos_from_vec(original: Vec<String>) -> Vec<String> {
     original
@@ -43,6 +46,58 @@ pub fn fill_auto_in_vec(
     }
 }
 
+/// Milliseconds in each unit a duration can be written in.
+const DURATION_UNITS: [(&str, u64); 6] = [
+    ("ms", 1),
+    ("s", 1000),
+    ("m", 60 * 1000),
+    ("h", 60 * 60 * 1000),
+    ("d", 24 * 60 * 60 * 1000),
+    ("w", 7 * 24 * 60 * 60 * 1000),
+];
+
+/// Parse a duration like `30s`, `1m30s` or `7d`, accepting only the given units.
+pub fn parse_duration(value: &str, units: &[&str]) -> Result<Duration> {
+    let mut total = Duration::ZERO;
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        bail!("Invalid duration `{value}`");
+    }
+
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount: u64 = rest[..digits]
+            .parse()
+            .with_context(|| format!("Invalid duration `{value}`"))?;
+        rest = &rest[digits..];
+
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = &rest[..unit_end];
+        let millis = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit && units.contains(name))
+            .map(|(_, millis)| *millis)
+            .with_context(|| {
+                format!(
+                    "Invalid duration `{value}`, expected a unit of {}",
+                    units.join(", ")
+                )
+            })?;
+        rest = &rest[unit_end..];
+
+        total = amount
+            .checked_mul(millis)
+            .and_then(|millis| total.checked_add(Duration::from_millis(millis)))
+            .with_context(|| format!("Invalid duration `{value}`, it is too long"))?;
+    }
+
+    Ok(total)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -88,4 +143,49 @@ mod test {
             .unwrap()
         );
     }
+
+    #[test]
+    fn test_parse_duration() {
+        let units = ["ms", "s", "m", "h", "d", "w"];
+        assert_eq!(
+            parse_duration("30s", &units).unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            parse_duration("1m30s", &units).unwrap(),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            parse_duration("500ms", &units).unwrap(),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            parse_duration("2w", &units).unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60)
+        );
+        assert!(parse_duration("30", &units).is_err());
+        assert!(parse_duration("s", &units).is_err());
+        assert!(parse_duration("", &units).is_err());
+        assert!(parse_duration("7 days", &units).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(
+            parse_duration("7d", &["d"]).unwrap(),
+            Duration::from_secs(7 * 24 * 60 * 60)
+        );
+        let err = parse_duration("500ms", &["s", "m"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid duration `500ms`, expected a unit of s, m"
+        );
+    }
+
+    #[test]
+    fn test_parse_duration_overflow() {
+        let units = ["s", "w"];
+        assert!(parse_duration("99999999999999999w", &units).is_err());
+        assert!(parse_duration("18446744073709551615s18446744073709551615s", &units).is_err());
+    }
 }
@@ -0,0 +1,348 @@
+use super::{phase::Phases, BuildPlan};
+use anyhow::{bail, Result};
+use std::{collections::BTreeSet, fmt};
+
+/// The largest edit distance at which a phase name is considered a typo of another one.
+const MAX_TYPO_DISTANCE: usize = 2;
+
+/// A problem found in a config file, with its position when it can be found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub message: String,
+    /// 1-based line and column.
+    pub position: Option<(usize, usize)>,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "line {line}, column {column}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The JSON Schema of `nixpacks.toml` and `nixpacks.json` files.
+pub fn get_plan_json_schema() -> Result<String> {
+    let schema = schemars::schema_for!(BuildPlan);
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+impl BuildPlan {
+    /// Create a BuildPlan from a toml config file, failing on unknown fields.
+    pub fn from_toml_strict<S: Into<String>>(toml: S) -> Result<Self> {
+        let contents = toml.into();
+        let mut deserializer = toml::Deserializer::new(&contents);
+        deserialize_strict(&mut deserializer, &contents)
+    }
+
+    /// Create a BuildPlan from a json config file, failing on unknown fields.
+    pub fn from_json_strict<S: Into<String>>(json: S) -> Result<Self> {
+        let contents = json.into();
+        let mut deserializer = serde_json::Deserializer::from_str(&contents);
+        deserialize_strict(&mut deserializer, &contents)
+    }
+
+    /// Check the phases of a config file against the final plan.
+    ///
+    /// Every dependency must exist, dependencies must not form a cycle, and phases the config file adds must not look like typos of existing ones.
+    pub fn validate_config_phases(&self, config: &BuildPlan, contents: &str) -> Result<()> {
+        let phases = self.phases.clone().unwrap_or_default();
+        let config_phases = config.phases.clone().unwrap_or_default();
+        let mut issues = Vec::new();
+
+        for (name, phase) in &config_phases {
+            for dependency in phase.depends_on.clone().unwrap_or_default() {
+                if dependency != "..." && !phases.contains_key(&dependency) {
+                    issues.push(ConfigIssue {
+                        message: format!(
+                            "phase `{name}` depends on `{dependency}`, which does not exist"
+                        ),
+                        position: find_value_position(contents, &dependency),
+                    });
+                }
+            }
+
+            // Phases without dependencies that only the config file defines are most likely misspelled
+            let is_new = name != "setup"
+                && phases.get(name).map_or(false, |p| {
+                    p.depends_on.clone().unwrap_or_default().is_empty()
+                });
+            if is_new {
+                if let Some(similar) = find_similar_phase(name, &phases) {
+                    issues.push(ConfigIssue {
+                        message: format!("unknown phase `{name}`, did you mean `{similar}`?"),
+                        position: find_key_position(contents, name),
+                    });
+                }
+            }
+        }
+
+        if let Some(cycle) = find_cycle(&phases) {
+            let position = cycle
+                .iter()
+                .find(|name| config_phases.contains_key(*name))
+                .and_then(|name| find_key_position(contents, name));
+            issues.push(ConfigIssue {
+                message: format!("phase dependencies form a cycle: {}", cycle.join(" -> ")),
+                position,
+            });
+        }
+
+        bail_on_issues(&issues)
+    }
+}
+
+/// Deserialize a plan, collecting every field serde would otherwise silently ignore.
+fn deserialize_strict<'de, D>(deserializer: D, contents: &str) -> Result<BuildPlan>
+where
+    D: serde::Deserializer<'de>,
+    D::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mut unknown_fields = Vec::new();
+    let mut plan: BuildPlan = serde_ignored::deserialize(deserializer, |path| {
+        // Options show up as `?` in the path
+        let path = path
+            .to_string()
+            .split('.')
+            .filter(|segment| *segment != "?")
+            .collect::<Vec<_>>()
+            .join(".");
+        unknown_fields.push(path);
+    })?;
+
+    let issues = unknown_fields
+        .iter()
+        .map(|path| ConfigIssue {
+            message: format!("unknown field `{path}`"),
+            position: find_key_position(contents, path.rsplit('.').next().unwrap_or(path)),
+        })
+        .collect::<Vec<_>>();
+    bail_on_issues(&issues)?;
+
+    plan.resolve_phase_names();
+    Ok(plan)
+}
+
+fn bail_on_issues(issues: &[ConfigIssue]) -> Result<()> {
+    match issues {
+        [] => Ok(()),
+        [issue] => bail!("{}", issue),
+        issues => bail!(
+            "{} problems found:\n{}",
+            issues.len(),
+            issues
+                .iter()
+                .map(|issue| format!("  {issue}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        ),
+    }
+}
+
+fn find_similar_phase(name: &str, phases: &Phases) -> Option<String> {
+    phases
+        .keys()
+        .filter(|other| other.as_str() != name)
+        .map(|other| (strsim::levenshtein(name, other), other))
+        .filter(|(distance, _)| *distance <= MAX_TYPO_DISTANCE)
+        .min()
+        .map(|(_, other)| other.clone())
+}
+
+/// Returns the names of the phases in a dependency cycle, starting and ending with the same phase.
+fn find_cycle(phases: &Phases) -> Option<Vec<String>> {
+    fn visit(
+        name: &str,
+        phases: &Phases,
+        done: &mut BTreeSet<String>,
+        stack: &mut Vec<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = stack.iter().position(|n| n == name) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+
+        if done.contains(name) {
+            return None;
+        }
+
+        stack.push(name.to_string());
+        let dependencies = phases
+            .get(name)
+            .and_then(|phase| phase.depends_on.clone())
+            .unwrap_or_default();
+        for dependency in dependencies {
+            if phases.contains_key(&dependency) {
+                if let Some(cycle) = visit(&dependency, phases, done, stack) {
+                    return Some(cycle);
+                }
+            }
+        }
+        stack.pop();
+        done.insert(name.to_string());
+
+        None
+    }
+
+    let mut done = BTreeSet::new();
+    phases
+        .keys()
+        .find_map(|name| visit(name, phases, &mut done, &mut Vec::new()))
+}
+
+/// Find where a key is defined, either as `key = ...`, `"key": ...`, or in a `[table.key]` header.
+fn find_key_position(contents: &str, key: &str) -> Option<(usize, usize)> {
+    let patterns = [
+        format!("{key} ="),
+        format!("{key}="),
+        format!("\"{key}\":"),
+        format!("\"{key}\" :"),
+        format!(".{key}]"),
+        format!("[{key}]"),
+        format!(".{key}."),
+        format!("\"{key}\" ="),
+    ];
+
+    find_position(contents, &patterns, |line, index, pattern| {
+        // Don't match the end of a longer key
+        let before = line[..index].chars().last();
+        let offset = usize::from(pattern.starts_with(['.', '[', '"']));
+        if before.map_or(true, |c| !(c.is_alphanumeric() || c == '_')) || offset == 1 {
+            Some(index + offset)
+        } else {
+            None
+        }
+    })
+}
+
+/// Find where a string value is used.
+fn find_value_position(contents: &str, value: &str) -> Option<(usize, usize)> {
+    let patterns = [format!("\"{value}\""), format!("'{value}'")];
+    find_position(contents, &patterns, |_, index, _| Some(index + 1))
+}
+
+fn find_position(
+    contents: &str,
+    patterns: &[String],
+    accept: impl Fn(&str, usize, &str) -> Option<usize>,
+) -> Option<(usize, usize)> {
+    for (line_number, line) in contents.lines().enumerate() {
+        for pattern in patterns {
+            for (index, _) in line.match_indices(pattern.as_str()) {
+                if let Some(column) = accept(line, index, pattern) {
+                    return Some((line_number + 1, line[..column].chars().count() + 1));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strict_unknown_fields() {
+        let err = BuildPlan::from_toml_strict(
+            r#"
+[phases.build]
+nixPkg = ["cowsay"]
+cmds = ["echo hello"]
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 3, column 1: unknown field `phases.build.nixPkg`"
+        );
+
+        let err = BuildPlan::from_json_strict(
+            r#"{
+  "start": { "command": "node index.js" }
+}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 2, column 15: unknown field `start.command`"
+        );
+    }
+
+    #[test]
+    fn test_strict_accepts_aliases() {
+        let plan = BuildPlan::from_toml_strict(
+            r#"
+[phases.setup]
+nixPackages = ["cowsay"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            plan.get_phase("setup").unwrap().nix_pkgs,
+            Some(vec!["cowsay".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_validate_config_phases() {
+        let contents = r#"
+[phases.biuld]
+cmds = ["make"]
+
+[phases.release]
+dependsOn = ["deploy"]
+"#;
+        let config = BuildPlan::from_toml_strict(contents).unwrap();
+        let plan = BuildPlan::merge_plans(&[
+            BuildPlan::from_toml(
+                r#"
+                [phases.setup]
+                [phases.build]
+                dependsOn = ["setup"]
+                "#,
+            )
+            .unwrap(),
+            config.clone(),
+        ]);
+
+        let err = plan
+            .validate_config_phases(&config, contents)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "2 problems found:\n  line 2, column 9: unknown phase `biuld`, did you mean `build`?\n  line 6, column 15: phase `release` depends on `deploy`, which does not exist"
+        );
+    }
+
+    #[test]
+    fn test_validate_cycles() {
+        let contents = r#"
+[phases.a]
+dependsOn = ["b"]
+
+[phases.b]
+dependsOn = ["a"]
+"#;
+        let config = BuildPlan::from_toml_strict(contents).unwrap();
+        let err = config
+            .validate_config_phases(&config, contents)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "line 2, column 9: phase dependencies form a cycle: a -> b -> a"
+        );
+    }
+
+    #[test]
+    fn test_plan_json_schema() {
+        let schema = get_plan_json_schema().unwrap();
+        assert!(schema.contains("\"nixPkgs\""));
+        assert!(schema.contains("\"dependsOn\""));
+    }
+}
@@ -1,8 +1,8 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::Environment,
-    nix::pkg::Pkg,
+    nix::{pkg::Pkg, NIXPKGS_ARCHIVE},
     plan::{
         phase::{Phase, StartPhase},
         BuildPlan,
@@ -13,6 +13,9 @@ use regex::Regex;
 
 const DEFAULT_JDK_PKG_NAME: &str = "jdk8";
 
+/// JDK versions that can be asked for, with their Nix package.
+const JDK_VERSIONS: &[(&str, &str)] = &[("8", "jdk8"), ("11", "jdk11"), ("latest", "jdk")];
+
 pub struct ClojureProvider {}
 
 impl Provider for ClojureProvider {
@@ -20,6 +23,16 @@ impl Provider for ClojureProvider {
         "clojure"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        JDK_VERSIONS
+            .iter()
+            .map(|(version, pkg)| {
+                ProviderVersion::new("jdk", version, pkg, NIXPKGS_ARCHIVE)
+                    .set_default(*pkg == DEFAULT_JDK_PKG_NAME)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(self.is_using_lein(app) || self.is_using_tools_build(app))
     }
@@ -122,11 +135,11 @@ impl ClojureProvider {
         let custom_version = ClojureProvider::get_custom_version(app, env)?;
         let parsed_version = ClojureProvider::parse_custom_version(&custom_version)?;
 
-        let pkg_name = match parsed_version.as_str() {
-            "latest" => "jdk",
-            "11" => "jdk11",
-            _ => DEFAULT_JDK_PKG_NAME, // 8 or any other value
-        };
+        // 8 or any other value gets the default
+        let pkg_name = JDK_VERSIONS
+            .iter()
+            .find(|(version, _)| *version == parsed_version)
+            .map_or(DEFAULT_JDK_PKG_NAME, |(_, pkg)| pkg);
 
         Ok(Pkg::new(pkg_name))
     }
@@ -208,4 +221,20 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        let versions = ClojureProvider {}.versions();
+        let defaults = versions.iter().filter(|v| v.is_default).collect::<Vec<_>>();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(
+            ClojureProvider::get_nix_jdk_package(
+                &App::new("./examples/clojure")?,
+                &Environment::default()
+            )?,
+            Pkg::new(&defaults[0].nix_pkg)
+        );
+
+        Ok(())
+    }
 }
@@ -33,7 +33,17 @@ impl Provider for CrystalProvider {
 
     fn get_build_plan(&self, app: &App, _env: &Environment) -> Result<Option<BuildPlan>> {
         let setup = Phase::setup(Some(vec![Pkg::new("crystal"), Pkg::new("shards")]));
-        let install = Phase::install(Some("shards install".to_string()));
+        let mut install = Phase::install(Some("shards install".to_string()));
+        // Shards from a directory of the app need the rest of it
+        if !app.read_file("shard.yml")?.contains("path:") {
+            install.add_file_dependencies(
+                ["shard.yml", "shard.lock", "shard.override.yml"]
+                    .iter()
+                    .filter(|file| app.includes_file(file))
+                    .map(ToString::to_string)
+                    .collect(),
+            );
+        }
         let build = Phase::build(Some("shards build".to_string()));
 
         let config = CrystalProvider::get_config(app)?;
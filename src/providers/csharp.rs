@@ -37,7 +37,10 @@ impl Provider for CSharpProvider {
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
         let sdk = CSharpProvider::get_sdk_version(app, env);
         let setup = Phase::setup(Some(vec![Pkg::new(sdk?.as_str())]));
-        let install = Phase::install(Some("dotnet restore".to_string()));
+        let mut install = Phase::install(Some("dotnet restore".to_string()));
+        if let Some(manifests) = CSharpProvider::get_install_manifests(app)? {
+            install.add_file_dependencies(manifests);
+        }
         let build = Phase::build(Some(format!(
             "dotnet publish --no-restore -c Release -o {ARTIFACT_DIR}"
         )));
@@ -48,7 +51,8 @@ impl Provider for CSharpProvider {
             .context("Invalid file_name")?
             .to_str()
             .context("Invalid project_name")?;
-        let start = StartPhase::new(format!("./{ARTIFACT_DIR}/{project_name}"));
+        let mut start = StartPhase::new(format!("./{ARTIFACT_DIR}/{project_name}"));
+        start.expose_port(3000);
 
         let mut plan = BuildPlan::new(&vec![setup, install, build], Some(start));
         plan.add_variables(EnvironmentVariables::from([
@@ -71,6 +75,27 @@ impl Provider for CSharpProvider {
 }
 
 impl CSharpProvider {
+    /// The files `dotnet restore` reads, or `None` when the app has build output that restoring would conflict with.
+    pub fn get_install_manifests(app: &App) -> Result<Option<Vec<String>>> {
+        if !app.find_directories("**/obj")?.is_empty() {
+            return Ok(None);
+        }
+
+        let manifests = app.find_relative_files(&[
+            "**/*.sln",
+            "**/*.csproj",
+            "**/*.fsproj",
+            "**/*.props",
+            "**/*.targets",
+            "**/packages.lock.json",
+            "NuGet.Config",
+            "nuget.config",
+            "global.json",
+        ])?;
+
+        Ok(Some(manifests))
+    }
+
     fn get_sdk_version(app: &App, env: &Environment) -> Result<String> {
         // Check if a version is specified in global.json
         let global_json = if app.includes_file("global.json") {
@@ -35,6 +35,9 @@ impl Provider for DartProvider {
 
         let mut install = Phase::install(Some("dart pub get".to_string()));
         install.add_file_dependency("pubspec.yaml".to_string());
+        if app.includes_file("pubspec.lock") {
+            install.add_file_dependency("pubspec.lock".to_string());
+        }
 
         let pubspec = DartProvider::get_pubspec(app)?;
         let build = Phase::build(Some(format!("dart compile exe bin/{}.dart", pubspec.name)));
@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::{Environment, EnvironmentVariables},
@@ -13,6 +13,19 @@ use regex::{Match, Regex};
 const DEFAULT_ELIXIR_PKG_NAME: &str = "elixir";
 const ELIXIR_NIXPKGS_ARCHIVE: &str = "c5702bd28cbde41a191a9c2a00501f18941efbd0";
 
+/// Elixir versions that can be asked for, with their Nix package.
+const ELIXIR_VERSIONS: &[(&str, &str)] = &[
+    ("1.9", "elixir_1_9"),
+    ("1.10", "elixir_1_10"),
+    ("1.11", "elixir_1_11"),
+    ("1.12", "elixir_1_12"),
+    ("1.13", "elixir_1_13"),
+    ("1.14", "elixir_1_14"),
+    ("1.15", "elixir_1_15"),
+    ("1.16", "elixir_1_16"),
+    ("1.17", "elixir_1_17"),
+];
+
 pub struct ElixirProvider {}
 
 impl Provider for ElixirProvider {
@@ -20,6 +33,24 @@ impl Provider for ElixirProvider {
         "elixir"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        let default = ProviderVersion::new(
+            "elixir",
+            "latest",
+            DEFAULT_ELIXIR_PKG_NAME,
+            ELIXIR_NIXPKGS_ARCHIVE,
+        )
+        .set_default(true);
+
+        ELIXIR_VERSIONS
+            .iter()
+            .map(|(version, pkg)| {
+                ProviderVersion::new("elixir", version, pkg, ELIXIR_NIXPKGS_ARCHIVE)
+            })
+            .chain(std::iter::once(default))
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("mix.exs"))
     }
@@ -35,6 +66,9 @@ impl Provider for ElixirProvider {
         let mut install_phase = Phase::install(Some("mix local.hex --force".to_string()));
         install_phase.add_cmd("mix local.rebar --force");
         install_phase.add_cmd("mix deps.get --only prod");
+        if let Some(manifests) = ElixirProvider::get_install_manifests(app)? {
+            install_phase.add_file_dependencies(manifests);
+        }
         plan.add_phase(install_phase);
 
         // Build Phase
@@ -60,6 +94,27 @@ impl Provider for ElixirProvider {
 }
 
 impl ElixirProvider {
+    /// The files Mix reads to fetch the dependencies, or `None` when they may need the rest of the app too.
+    ///
+    /// That is the case for umbrella apps, dependencies from a directory, and mix.exs files that read other files.
+    fn get_install_manifests(app: &App) -> Result<Option<Vec<String>>> {
+        let mix_exs_content = app.read_file("mix.exs")?;
+        if ["apps_path", "in_umbrella", "path:", "File.", "Code."]
+            .iter()
+            .any(|pattern| mix_exs_content.contains(pattern))
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            ["mix.exs", "mix.lock", "config"]
+                .iter()
+                .filter(|file| app.includes_file(file) || app.includes_directory(file))
+                .map(ToString::to_string)
+                .collect(),
+        ))
+    }
+
     fn setup(&self, app: &App, env: &Environment) -> Result<Option<Phase>> {
         let elixir_pkg = ElixirProvider::get_nix_elixir_package(app, env)?;
         // TODO should try to extract and optionally set the OTP version
@@ -134,20 +189,46 @@ impl ElixirProvider {
             return Ok(Pkg::new(DEFAULT_ELIXIR_PKG_NAME));
         }
         let matches = matches.unwrap();
-        let parsed_version = (as_default(matches.get(1)), as_default(matches.get(2)));
+        let parsed_version = format!(
+            "{}.{}",
+            as_default(matches.get(1)),
+            as_default(matches.get(2))
+        );
 
         // Match major and minor versions
-        match parsed_version {
-            ("1", "9") => Ok(Pkg::new("elixir_1_9")),
-            ("1", "10") => Ok(Pkg::new("elixir_1_10")),
-            ("1", "11") => Ok(Pkg::new("elixir_1_11")),
-            ("1", "12") => Ok(Pkg::new("elixir_1_12")),
-            ("1", "13") => Ok(Pkg::new("elixir_1_13")),
-            ("1", "14") => Ok(Pkg::new("elixir_1_14")),
-            ("1", "15") => Ok(Pkg::new("elixir_1_15")),
-            ("1", "16") => Ok(Pkg::new("elixir_1_16")),
-            ("1", "17") => Ok(Pkg::new("elixir_1_17")),
-            _ => Ok(Pkg::new(DEFAULT_ELIXIR_PKG_NAME)),
+        let pkg_name = ELIXIR_VERSIONS
+            .iter()
+            .find(|(version, _)| *version == parsed_version)
+            .map_or(DEFAULT_ELIXIR_PKG_NAME, |(_, pkg)| pkg);
+        Ok(Pkg::new(pkg_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        let versions = ElixirProvider {}.versions();
+        let defaults = versions.iter().filter(|v| v.is_default).collect::<Vec<_>>();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].nix_pkg, DEFAULT_ELIXIR_PKG_NAME);
+
+        // Every listed version can be asked for
+        let app = App::new("./examples/elixir-phx-no-ecto")?;
+        for version in versions.iter().filter(|v| !v.is_default) {
+            let env = Environment::new(BTreeMap::from([(
+                "NIXPACKS_ELIXIR_VERSION".to_string(),
+                version.version.clone(),
+            )]));
+            assert_eq!(
+                ElixirProvider::get_nix_elixir_package(&app, &env)?,
+                Pkg::new(&version.nix_pkg)
+            );
         }
+
+        Ok(())
     }
 }
@@ -0,0 +1,337 @@
+use super::{Provider, ProviderMetadata};
+use crate::nixpacks::{app::App, environment::Environment, plan::BuildPlan};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+use wait_timeout::ChildExt;
+
+pub const EXTERNAL_PROVIDER_PREFIX: &str = "nixpacks-provider-";
+
+/// How long a provider has to answer a single command.
+const PROVIDER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A provider implemented by an executable outside of Nixpacks.
+///
+/// External providers are `nixpacks-provider-<name>` executables found on the `PATH`, or executables referenced by path in the `providers` list of a config file.
+/// They are invoked as `<executable> <command> <app source>` with the build environment variables written to stdin as a JSON object.
+/// The `detect` command answers `true` or `false`, `plan` answers a JSON build plan (or `null`), and `metadata` answers an array of strings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalProvider {
+    name: String,
+    executable: PathBuf,
+    timeout: Duration,
+}
+
+impl Provider for ExternalProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn detect(&self, app: &App, env: &Environment) -> Result<bool> {
+        let output = self.call("detect", app, env)?;
+        serde_json::from_str(&output)
+            .with_context(|| format!("Provider {} returned an invalid detect response", self.name))
+    }
+
+    fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
+        let output = self.call("plan", app, env)?;
+        if output.trim() == "null" {
+            return Ok(None);
+        }
+
+        let plan = BuildPlan::from_json(output)
+            .with_context(|| format!("Provider {} returned an invalid build plan", self.name))?;
+
+        Ok(Some(plan))
+    }
+
+    fn metadata(&self, app: &App, env: &Environment) -> Result<ProviderMetadata> {
+        let output = self.call("metadata", app, env)?;
+        let values: Option<Vec<String>> = serde_json::from_str(&output)
+            .with_context(|| format!("Provider {} returned invalid metadata", self.name))?;
+
+        Ok(ProviderMetadata { values })
+    }
+}
+
+impl ExternalProvider {
+    pub fn new<S: Into<String>, P: Into<PathBuf>>(name: S, executable: P) -> Self {
+        Self {
+            name: name.into(),
+            executable: executable.into(),
+            timeout: PROVIDER_TIMEOUT,
+        }
+    }
+
+    /// Find all `nixpacks-provider-*` executables on the `PATH`.
+    ///
+    /// If the same provider name is found more than once, the first one on the `PATH` wins.
+    pub fn discover() -> Vec<ExternalProvider> {
+        let mut providers: BTreeMap<String, ExternalProvider> = BTreeMap::new();
+
+        let paths = env::var_os("PATH").unwrap_or_default();
+        for dir in env::split_paths(&paths) {
+            let entries = match dir.read_dir() {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            let mut executables = entries
+                .filter_map(std::result::Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| is_executable(path))
+                .collect::<Vec<_>>();
+            executables.sort();
+
+            for path in executables {
+                if let Some(name) = provider_name_from_path(&path) {
+                    providers
+                        .entry(name.clone())
+                        .or_insert_with(|| ExternalProvider::new(name, path));
+                }
+            }
+        }
+
+        providers.into_values().collect()
+    }
+
+    /// Whether a provider listed in a config file refers to an executable rather than a provider name.
+    pub fn is_path_reference(provider: &str) -> bool {
+        provider.contains('/') || provider.contains('\\')
+    }
+
+    /// Create a provider from an executable path listed in a config file.
+    ///
+    /// Relative paths are resolved against the app source.
+    pub fn from_path_reference(app: &App, provider: &str) -> Result<ExternalProvider> {
+        let executable = app.source.join(provider);
+        if !executable.is_file() {
+            bail!("External provider {} does not exist", provider);
+        }
+
+        let name = provider_name_from_path(&executable).unwrap_or_else(|| {
+            executable
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+
+        Ok(ExternalProvider::new(name, executable))
+    }
+
+    /// Run the provider executable with the given command and return its stdout.
+    fn call(&self, command: &str, app: &App, env: &Environment) -> Result<String> {
+        let mut child = Command::new(&self.executable)
+            .arg(command)
+            .arg(&app.source)
+            .current_dir(&app.source)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| {
+                format!(
+                    "Failed to run external provider {} ({})",
+                    self.name,
+                    self.executable.display()
+                )
+            })?;
+
+        // Write the input and drain the output on separate threads so a provider that answers
+        // before reading all of its input can't block on a full pipe
+        let variables = serde_json::to_string(&Environment::clone_variables(env))?;
+        let stdin = child.stdin.take().map(|mut stdin| {
+            thread::spawn(move || {
+                // The provider may exit without reading its input, which is fine
+                let _ = stdin.write_all(variables.as_bytes());
+            })
+        });
+        let stdout = child.stdout.take().map(read_to_end);
+        let stderr = child.stderr.take().map(read_to_end);
+
+        let status = if let Some(status) = child.wait_timeout(self.timeout)? {
+            status
+        } else {
+            let _ = child.kill();
+            child.wait()?;
+            bail!(
+                "External provider {} did not answer `{}` within {} seconds",
+                self.name,
+                command,
+                self.timeout.as_secs()
+            );
+        };
+
+        if let Some(stdin) = stdin {
+            let _ = stdin.join();
+        }
+        let stdout = stdout.map(join_output).unwrap_or_default();
+        let stderr = stderr.map(join_output).unwrap_or_default();
+
+        if !status.success() {
+            bail!(
+                "External provider {} failed to run `{}`: {}",
+                self.name,
+                command,
+                String::from_utf8_lossy(&stderr).trim()
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&stdout).to_string())
+    }
+}
+
+/// Read a pipe to the end on a separate thread.
+fn read_to_end<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = reader.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+fn join_output(handle: thread::JoinHandle<Vec<u8>>) -> Vec<u8> {
+    handle.join().unwrap_or_default()
+}
+
+/// Returns the provider name for an executable named `nixpacks-provider-<name>`.
+fn provider_name_from_path(path: &Path) -> Option<String> {
+    let file_name = path.file_stem()?.to_str()?;
+    file_name
+        .strip_prefix(EXTERNAL_PROVIDER_PREFIX)
+        .filter(|name| !name.is_empty())
+        .map(ToString::to_string)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    matches!(
+        path.metadata(),
+        Ok(metadata) if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+    )
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt};
+    use tempdir::TempDir;
+
+    fn write_provider(dir: &Path, script: &str) -> PathBuf {
+        let path = dir.join("nixpacks-provider-internal");
+        fs::write(&path, script).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_external_provider_protocol() -> Result<()> {
+        let dir = TempDir::new("nixpacks-provider")?;
+        let path = write_provider(
+            dir.path(),
+            indoc::indoc! {r#"
+                #!/bin/sh
+                case "$1" in
+                  detect) echo true ;;
+                  plan) echo '{"phases":{"build":{"cmds":["make"]}},"start":{"cmd":"./server"}}' ;;
+                  metadata) echo '["internal"]' ;;
+                esac
+            "#},
+        );
+
+        let provider = ExternalProvider::new("internal", path);
+        let app = App::new("./examples/node")?;
+        let env = Environment::default();
+
+        assert!(provider.detect(&app, &env)?);
+
+        let plan = provider.get_build_plan(&app, &env)?.unwrap();
+        assert_eq!(
+            plan.get_phase("build").unwrap().cmds,
+            Some(vec!["make".to_string()])
+        );
+        assert_eq!(plan.start_phase.unwrap().cmd, Some("./server".to_string()));
+
+        assert_eq!(
+            provider
+                .metadata(&app, &env)?
+                .join_as_comma_separated("internal".to_string()),
+            "internal,internal"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_provider_failure() -> Result<()> {
+        let dir = TempDir::new("nixpacks-provider")?;
+        let path = write_provider(dir.path(), "#!/bin/sh\necho 'boom' >&2\nexit 1\n");
+
+        let provider = ExternalProvider::new("internal", path);
+        let err = provider
+            .detect(&App::new("./examples/node")?, &Environment::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("boom"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_provider_large_output_before_input() -> Result<()> {
+        let dir = TempDir::new("nixpacks-provider")?;
+        let path = write_provider(
+            dir.path(),
+            "#!/bin/sh\nhead -c 1048576 /dev/zero | tr '\\0' ' '\necho true\ncat >/dev/null\n",
+        );
+
+        let provider = ExternalProvider::new("internal", path);
+        let env = Environment::from_envs(vec![&format!("LARGE={}", "x".repeat(1 << 20))])?;
+        assert!(provider.detect(&App::new("./examples/node")?, &env)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_external_provider_timeout() -> Result<()> {
+        let dir = TempDir::new("nixpacks-provider")?;
+        let path = write_provider(dir.path(), "#!/bin/sh\nexec sleep 10\n");
+
+        let provider = ExternalProvider {
+            timeout: Duration::from_millis(100),
+            ..ExternalProvider::new("internal", path)
+        };
+        let err = provider
+            .detect(&App::new("./examples/node")?, &Environment::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("did not answer"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_provider_name_from_path() {
+        assert_eq!(
+            provider_name_from_path(Path::new("/usr/bin/nixpacks-provider-internal")),
+            Some("internal".to_string())
+        );
+        assert_eq!(
+            provider_name_from_path(Path::new("/usr/bin/nixpacks-provider-")),
+            None
+        );
+        assert_eq!(provider_name_from_path(Path::new("/usr/bin/node")), None);
+    }
+}
@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{csharp::CSharpProvider, Provider};
 use crate::nixpacks::{
     app::App,
     environment::{Environment, EnvironmentVariables},
@@ -25,7 +25,10 @@ impl Provider for FSharpProvider {
 
     fn get_build_plan(&self, app: &App, _env: &Environment) -> Result<Option<BuildPlan>> {
         let setup = Phase::setup(Some(vec![Pkg::new("dotnet-sdk")]));
-        let install = Phase::install(Some("dotnet restore".to_string()));
+        let mut install = Phase::install(Some("dotnet restore".to_string()));
+        if let Some(manifests) = CSharpProvider::get_install_manifests(app)? {
+            install.add_file_dependencies(manifests);
+        }
         let build = Phase::build(Some(format!(
             "dotnet publish --no-restore -c Release -o {ARTIFACT_DIR}"
         )));
@@ -36,7 +39,8 @@ impl Provider for FSharpProvider {
             .context("Invalid file_name")?
             .to_str()
             .context("Invalid project_name")?;
-        let start = StartPhase::new(format!("./{ARTIFACT_DIR}/{project_name}"));
+        let mut start = StartPhase::new(format!("./{ARTIFACT_DIR}/{project_name}"));
+        start.expose_port(3000);
 
         let mut plan = BuildPlan::new(&vec![setup, install, build], Some(start));
         plan.add_variables(EnvironmentVariables::from([
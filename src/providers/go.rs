@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::{Environment, EnvironmentVariables},
@@ -51,10 +51,24 @@ impl Provider for GolangProvider {
         "go"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        AVAILABLE_GO_VERSIONS
+            .iter()
+            .map(|(version, pkg, archive)| {
+                ProviderVersion::new("go", version, pkg, archive)
+                    .set_default(*pkg == DEFAULT_GO_PKG_NAME && *archive == DEFAULT_ARCHIVE)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("main.go") || app.includes_file("go.mod"))
     }
 
+    fn compatible_providers(&self) -> &[&str] {
+        &["node"]
+    }
+
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
         let mut plan = BuildPlan::default();
 
@@ -70,6 +84,21 @@ impl Provider for GolangProvider {
         if is_go_module {
             let mut install = Phase::install(Some("go mod download".to_string()));
             install.add_cache_directory(GO_BUILD_CACHE_DIR.to_string());
+            // Modules replaced with a directory of the app need the rest of it
+            let replaces_with_directory = go_mod.as_ref().map_or(false, |go_mod| {
+                go_mod
+                    .lines()
+                    .any(|line| line.contains("=> .") || line.contains("=> /"))
+            });
+            if !replaces_with_directory && !app.includes_file("go.work") {
+                install.add_file_dependencies(
+                    ["go.mod", "go.sum"]
+                        .iter()
+                        .filter(|file| app.includes_file(file))
+                        .map(ToString::to_string)
+                        .collect(),
+                );
+            }
             plan.add_phase(install);
         }
 
@@ -212,4 +241,17 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_versions() -> Result<()> {
+        let versions = GolangProvider {}.versions();
+        let defaults = versions.iter().filter(|v| v.is_default).collect::<Vec<_>>();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(
+            GolangProvider::get_nix_golang_pkg(None)?.0,
+            defaults[0].nix_pkg
+        );
+
+        Ok(())
+    }
 }
@@ -53,6 +53,9 @@ impl Provider for HaskellStackProvider {
 
         let mut install = Phase::install(Some("stack setup".to_string()));
         install.add_cache_directory(STACK_CACHE_DIR.to_string());
+        if let Some(manifests) = HaskellStackProvider::get_install_manifests(app)? {
+            install.add_file_dependencies(manifests);
+        }
 
         let mut build = Phase::build(Some("stack install".to_string()));
         build.add_cache_directory(STACK_CACHE_DIR.to_string());
@@ -73,6 +76,35 @@ impl Provider for HaskellStackProvider {
     }
 }
 
+impl HaskellStackProvider {
+    /// The files `stack setup` reads, or `None` when stack.yaml lists packages in other directories of the app.
+    fn get_install_manifests(app: &App) -> Result<Option<Vec<String>>> {
+        if app.includes_file("stack.yaml") {
+            let stack: HaskellStackYaml = app.read_yaml("stack.yaml")?;
+            if stack.packages.map_or(false, |packages| {
+                packages.iter().any(|package| package != ".")
+            }) {
+                return Ok(None);
+            }
+        }
+
+        let mut manifests = app.find_relative_files(&["*.cabal"])?;
+        manifests.extend(
+            ["stack.yaml", "stack.yaml.lock", "package.yaml"]
+                .iter()
+                .filter(|file| app.includes_file(file))
+                .map(ToString::to_string),
+        );
+
+        Ok(Some(manifests))
+    }
+}
+
+#[derive(Deserialize)]
+struct HaskellStackYaml {
+    pub packages: Option<Vec<String>>,
+}
+
 #[derive(Deserialize)]
 #[allow(clippy::zero_sized_map_values)]
 struct HaskellStackPackageYaml {
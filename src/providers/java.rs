@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::Environment,
@@ -17,11 +17,43 @@ const DEFAULT_JDK_VERSION: u32 = 17;
 const DEFAULT_GRADLE_VERSION: u32 = 8;
 const JAVA_NIXPKGS_ARCHIVE: &str = "59dc10b5a6f2a592af36375c68fda41246794b86";
 
+// (jdk_version, nix_pkg_name)
+const JDK_VERSIONS: &[(u32, &str)] = &[
+    (8, "jdk8"),
+    (11, "jdk11"),
+    (17, "jdk17"),
+    (19, "jdk"),
+    (20, "jdk20"),
+    (21, "jdk21"),
+];
+
+// (gradle_version, nix_pkg_name)
+const GRADLE_VERSIONS: &[(u32, &str)] = &[
+    (4, "gradle_4"),
+    (5, "gradle_5"),
+    (6, "gradle_6"),
+    (7, "gradle_7"),
+    (8, "gradle"),
+];
+
 impl Provider for JavaProvider {
     fn name(&self) -> &'static str {
         "java"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        let jdks = JDK_VERSIONS.iter().map(|(version, pkg)| {
+            ProviderVersion::new("jdk", &version.to_string(), pkg, JAVA_NIXPKGS_ARCHIVE)
+                .set_default(*version == DEFAULT_JDK_VERSION)
+        });
+        let gradles = GRADLE_VERSIONS.iter().map(|(version, pkg)| {
+            ProviderVersion::new("gradle", &version.to_string(), pkg, JAVA_NIXPKGS_ARCHIVE)
+                .set_default(*version == DEFAULT_GRADLE_VERSION)
+        });
+
+        jdks.chain(gradles).collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("pom.xml")
             || app.includes_directory("pom.atom")
@@ -169,30 +201,23 @@ impl JavaProvider {
     }
 
     fn get_jdk_pkg(&self, jdk_version: u32) -> Result<Pkg> {
-        let pkg = match jdk_version {
-            21 => Pkg::new("jdk21"),
-            20 => Pkg::new("jdk20"),
-            19 => Pkg::new("jdk"),
-            17 => Pkg::new("jdk17"),
-            11 => Pkg::new("jdk11"),
-            8 => Pkg::new("jdk8"),
-            _ => bail!("Unsupported JDK version: {}", jdk_version),
-        };
-
-        Ok(pkg)
+        match JDK_VERSIONS
+            .iter()
+            .find(|(version, _)| *version == jdk_version)
+        {
+            Some((_, pkg)) => Ok(Pkg::new(pkg)),
+            None => bail!("Unsupported JDK version: {}", jdk_version),
+        }
     }
 
     fn get_gradle_pkg(&self, gradle_version: u32) -> Result<Pkg> {
-        let pkg = match gradle_version {
-            8 => Pkg::new("gradle"),
-            7 => Pkg::new("gradle_7"),
-            6 => Pkg::new("gradle_6"),
-            5 => Pkg::new("gradle_5"),
-            4 => Pkg::new("gradle_4"),
-            _ => bail!("Unsupported Gradle version: {}", gradle_version),
-        };
-
-        Ok(pkg)
+        match GRADLE_VERSIONS
+            .iter()
+            .find(|(version, _)| *version == gradle_version)
+        {
+            Some((_, pkg)) => Ok(Pkg::new(pkg)),
+            None => bail!("Unsupported Gradle version: {}", gradle_version),
+        }
     }
 
     fn get_jdk_version(&self, app: &App, env: &Environment) -> Result<u32> {
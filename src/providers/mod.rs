@@ -8,6 +8,7 @@ pub mod csharp;
 pub mod dart;
 pub mod deno;
 pub mod elixir;
+pub mod external;
 pub mod fsharp;
 pub mod gleam;
 pub mod go;
@@ -26,15 +27,108 @@ pub mod staticfile;
 pub mod swift;
 pub mod zig;
 
+/// Confidence given to providers that only implement `detect`.
+pub const DEFAULT_CONFIDENCE: u8 = 50;
+
 pub trait Provider: Send + Sync {
     fn name(&self) -> &str;
     fn detect(&self, _app: &App, _env: &Environment) -> Result<bool> {
         Ok(false)
     }
+    /// Scores how likely it is that this provider should build the app, from 0 (not detected) to 100.
+    ///
+    /// The first detected provider is used as the primary provider for the build, unless a later one is more
+    /// confident than `DEFAULT_CONFIDENCE`. Lower confidences only keep a provider from being added as a secondary one.
+    fn detect_confidence(&self, app: &App, env: &Environment) -> Result<Detection> {
+        if self.detect(app, env)? {
+            Ok(Detection::new(
+                DEFAULT_CONFIDENCE,
+                vec!["matched detection rules".to_string()],
+            ))
+        } else {
+            Ok(Detection::default())
+        }
+    }
+    /// Providers that are automatically added to the build when this is the primary provider and they are detected.
+    fn compatible_providers(&self) -> &[&str] {
+        &[]
+    }
     fn get_build_plan(&self, _app: &App, _environment: &Environment) -> Result<Option<BuildPlan>>;
     fn metadata(&self, _app: &App, _env: &Environment) -> Result<ProviderMetadata> {
         Ok(ProviderMetadata::default())
     }
+    /// The versions of the languages and tools this provider can install, listed by `nixpacks search`.
+    fn versions(&self) -> Vec<ProviderVersion> {
+        Vec::new()
+    }
+}
+
+/// A version of a language or tool a provider can install, and the Nix package it is installed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderVersion {
+    /// What the version is of, e.g. `node` or `gradle`.
+    pub tool: String,
+    pub version: String,
+    pub nix_pkg: String,
+    /// The nixpkgs archive the package is installed from.
+    pub archive: String,
+    /// Whether this version is used when the app doesn't ask for one.
+    pub is_default: bool,
+}
+
+impl ProviderVersion {
+    pub fn new(tool: &str, version: &str, nix_pkg: &str, archive: &str) -> Self {
+        ProviderVersion {
+            tool: tool.to_string(),
+            version: version.to_string(),
+            nix_pkg: nix_pkg.to_string(),
+            archive: archive.to_string(),
+            is_default: false,
+        }
+    }
+
+    /// Mark the version as the one used when the app doesn't ask for one.
+    #[must_use]
+    pub fn set_default(mut self, is_default: bool) -> Self {
+        self.is_default = is_default;
+        self
+    }
+}
+
+/// The confidence a provider has in building an app, and the evidence that confidence is based on.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub confidence: u8,
+    pub evidence: Vec<String>,
+}
+
+impl Detection {
+    pub fn new(confidence: u8, evidence: Vec<String>) -> Detection {
+        Detection {
+            confidence,
+            evidence,
+        }
+    }
+
+    /// Detected with the given confidence if any of the files exist, using the files found as evidence.
+    pub fn from_files(app: &App, confidence: u8, files: &[&str]) -> Detection {
+        let evidence = files
+            .iter()
+            .filter(|file| app.includes_file(file))
+            .map(|file| (*file).to_string())
+            .collect::<Vec<_>>();
+
+        if evidence.is_empty() {
+            Detection::default()
+        } else {
+            Detection::new(confidence, evidence)
+        }
+    }
+
+    /// Whether the provider was detected at all.
+    pub fn is_detected(&self) -> bool {
+        self.confidence > 0
+    }
 }
 
 #[derive(Default)]
@@ -67,6 +161,17 @@ impl ProviderMetadata {
     }
 }
 
+#[test]
+fn test_detection_from_files() {
+    let app = App::new("./examples/node").unwrap();
+
+    let detection = Detection::from_files(&app, 60, &["package.json", "requirements.txt"]);
+    assert_eq!(detection.confidence, 60);
+    assert_eq!(detection.evidence, vec!["package.json".to_string()]);
+
+    assert!(!Detection::from_files(&app, 60, &["requirements.txt"]).is_detected());
+}
+
 #[test]
 fn test_join_as_comma_separated() {
     let metadata = ProviderMetadata::from(vec![
@@ -1,5 +1,5 @@
 use self::{moon::Moon, nx::Nx, spa::SpaProvider, turborepo::Turborepo};
-use super::Provider;
+use super::{Detection, Provider, ProviderVersion, DEFAULT_CONFIDENCE};
 use crate::nixpacks::plan::merge::Mergeable;
 use crate::nixpacks::{
     app::App,
@@ -42,6 +42,30 @@ const BUN_CACHE_DIR: &str = "/root/.bun";
 const CYPRESS_CACHE_DIR: &str = "/root/.cache/Cypress";
 const NODE_MODULES_CACHE_DIR: &str = "node_modules/.cache";
 
+/// The files and directories package managers read while installing, besides the package.json files.
+const INSTALL_MANIFESTS: &[&str] = &[
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "pnpm-workspace.yaml",
+    ".pnpmfile.cjs",
+    "bun.lockb",
+    "bun.lock",
+    "bunfig.toml",
+    ".npmrc",
+    ".yarnrc",
+    ".yarnrc.yml",
+    ".yarn",
+    "patches",
+];
+
+/// Lifecycle scripts package managers run while installing, which can use any file of the app.
+const INSTALL_SCRIPTS: &[&str] = &["preinstall", "install", "postinstall", "prepare"];
+
+/// Versions of dependencies that install them from a directory of the app.
+const LOCAL_DEPENDENCY_PREFIXES: &[&str] = &["file:", "link:", "portal:"];
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 struct TsConfigJson {
     #[serde(rename = "compilerOptions")]
@@ -128,10 +152,48 @@ impl Provider for NodeProvider {
         "node"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        AVAILABLE_NODE_VERSIONS
+            .iter()
+            .map(|version| {
+                let archive = if *version <= 16 {
+                    NODE_LT_16_ARCHIVE
+                } else {
+                    NODE_NIXPKGS_ARCHIVE
+                };
+                ProviderVersion::new(
+                    "node",
+                    &version.to_string(),
+                    &version_number_to_pkg(*version),
+                    archive,
+                )
+                .set_default(*version == DEFAULT_NODE_VERSION)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("package.json"))
     }
 
+    fn detect_confidence(&self, app: &App, _env: &Environment) -> Result<Detection> {
+        let mut detection = Detection::from_files(app, DEFAULT_CONFIDENCE, &["package.json"]);
+        if !detection.is_detected() {
+            return Ok(detection);
+        }
+
+        // Without a build script the package.json is likely only used for tooling
+        if NodeProvider::has_script(app, "build")? {
+            detection
+                .evidence
+                .push("build script in package.json".to_string());
+        } else {
+            detection.confidence -= 10;
+        }
+
+        Ok(detection)
+    }
+
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
         // Setup
         let mut setup = Phase::setup(Some(NodeProvider::get_nix_packages(app, env)?));
@@ -184,6 +246,9 @@ impl Provider for NodeProvider {
 
         install.add_cache_directory(NodeProvider::get_package_manager_cache_dir(app));
         install.add_path("/app/node_modules/.bin".to_string());
+        if let Some(manifests) = NodeProvider::get_install_manifests(app)? {
+            install.add_file_dependencies(manifests);
+        }
 
         // Cypress cache directory
         let all_deps = NodeProvider::get_all_deps(app)?;
@@ -474,6 +539,52 @@ impl NodeProvider {
         Some(install_cmd)
     }
 
+    /// The files the install command reads, or `None` when it may need the rest of the app too.
+    ///
+    /// That is the case with lifecycle scripts that run on install, dependencies installed from a directory,
+    /// Prisma generating its client from the schema, and a node_modules directory that would replace the installed one.
+    pub fn get_install_manifests(app: &App) -> Result<Option<Vec<String>>> {
+        if !app.includes_file("package.json")
+            || app.includes_directory("node_modules")
+            || NodeProvider::uses_node_dependency(app, "prisma")
+        {
+            return Ok(None);
+        }
+
+        let mut manifests = app.find_relative_files(&["**/package.json"])?;
+        for file in &manifests {
+            let package_json: PackageJson = app.read_json(file)?;
+            let runs_install_scripts = package_json.scripts.map_or(false, |scripts| {
+                INSTALL_SCRIPTS
+                    .iter()
+                    .any(|script| scripts.contains_key(*script))
+            });
+            let has_local_dependencies = package_json
+                .dependencies
+                .iter()
+                .chain(package_json.dev_dependencies.iter())
+                .flatten()
+                .any(|(_, version)| {
+                    LOCAL_DEPENDENCY_PREFIXES
+                        .iter()
+                        .any(|prefix| version.starts_with(prefix))
+                });
+
+            if runs_install_scripts || has_local_dependencies {
+                return Ok(None);
+            }
+        }
+
+        manifests.extend(
+            INSTALL_MANIFESTS
+                .iter()
+                .filter(|file| app.includes_file(file) || app.includes_directory(file))
+                .map(ToString::to_string),
+        );
+
+        Ok(Some(manifests))
+    }
+
     fn get_package_manager_cache_dir(app: &App) -> String {
         let package_manager = NodeProvider::get_package_manager(app);
         if package_manager == "yarn" {
@@ -1162,4 +1273,36 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_install_manifests() -> Result<()> {
+        assert_eq!(
+            NodeProvider::get_install_manifests(&App::new("examples/node-npm")?)?,
+            Some(vec![
+                "package.json".to_string(),
+                "package-lock.json".to_string()
+            ])
+        );
+        assert_eq!(
+            NodeProvider::get_install_manifests(&App::new("examples/node-monorepo")?)?,
+            Some(vec![
+                "package.json".to_string(),
+                "packages/client/package.json".to_string(),
+                "packages/server/package.json".to_string(),
+                "yarn.lock".to_string()
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_install_manifests_prisma() -> Result<()> {
+        assert_eq!(
+            NodeProvider::get_install_manifests(&App::new("examples/node-prisma-postgres")?)?,
+            None
+        );
+
+        Ok(())
+    }
 }
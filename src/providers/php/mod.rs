@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
@@ -12,7 +12,7 @@ use crate::nixpacks::{
     },
 };
 
-use super::{node::NodeProvider, Provider};
+use super::{node::NodeProvider, Provider, ProviderVersion};
 use anyhow::Result;
 
 const LEGACY_ARCHIVE_VERSION: &str = "5148520bfab61f99fd25fb9ff7bfbb50dad3c9db";
@@ -38,15 +38,26 @@ impl Provider for PhpProvider {
         "php"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        PHP_ARCHIVE_VERSIONS
+            .iter()
+            .map(|(version, (pkg, archive))| {
+                ProviderVersion::new("php", version, pkg, archive)
+                    .set_default(*version == DEFAULT_PHP_VERSION)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("composer.json") || app.includes_file("index.php"))
     }
 
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
         let setup = PhpProvider::get_setup(app, env)?;
-        let install = PhpProvider::get_install(app);
+        let install = PhpProvider::get_install(app)?;
         let build = PhpProvider::get_build(app);
-        let start = PhpProvider::get_start(app);
+        let mut start = PhpProvider::get_start(app);
+        start.expose_port(80);
 
         let mut plan = BuildPlan::new(
             &vec![Some(setup), Some(install), build]
@@ -105,7 +116,7 @@ impl PhpProvider {
         Ok(phase)
     }
 
-    fn get_install(app: &App) -> Phase {
+    fn get_install(app: &App) -> Result<Phase> {
         let mut install = Phase::install(Some(
             "mkdir -p /var/log/nginx && mkdir -p /var/cache/nginx".to_string(),
         ));
@@ -117,8 +128,47 @@ impl PhpProvider {
                 install.add_cmd(install_cmd);
             }
         }
+        if let Some(manifests) = PhpProvider::get_install_manifests(app)? {
+            install.add_file_dependencies(manifests);
+        }
 
-        install
+        Ok(install)
+    }
+
+    /// The files Composer and the Node package manager read while installing, or `None` when they may need the rest of the app too.
+    ///
+    /// Composer runs the scripts of composer.json after installing, and needs the source files for classmap and files autoloading.
+    fn get_install_manifests(app: &App) -> Result<Option<Vec<String>>> {
+        let mut manifests = Vec::new();
+        if app.includes_file("composer.json") {
+            let composer_json: serde_json::Value = app.read_json("composer.json")?;
+            let autoload = composer_json.get("autoload");
+            let needs_app = composer_json.get("scripts").is_some()
+                || ["classmap", "files"]
+                    .iter()
+                    .any(|key| autoload.and_then(|autoload| autoload.get(key)).is_some());
+            if needs_app {
+                return Ok(None);
+            }
+
+            manifests.push("composer.json".to_string());
+            if app.includes_file("composer.lock") {
+                manifests.push("composer.lock".to_string());
+            }
+        }
+        if app.includes_file("package.json") {
+            match NodeProvider::get_install_manifests(app)? {
+                Some(node_manifests) => manifests.extend(node_manifests),
+                None => return Ok(None),
+            }
+        }
+
+        // Without any manifests the install phase copies the app as before, instead of copying nothing
+        if manifests.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(manifests))
     }
 
     fn get_build(app: &App) -> Option<Phase> {
@@ -135,26 +185,30 @@ impl PhpProvider {
         None
     }
 
+    /// PHP-FPM and nginx run side by side under the supervisor.
     fn get_start(app: &App) -> StartPhase {
-        if app.includes_file("nginx.conf") {
-            StartPhase::new(format!(
-                "php-fpm -y {} & nginx -c /app/nginx.conf",
-                app.asset_path("php-fpm.conf")
-            ))
+        let nginx = if app.includes_file("nginx.conf") {
+            "nginx -c /app/nginx.conf".to_string()
         } else if app.includes_file("nginx.template.conf") {
-            StartPhase::new(format!(
-                "node {} /app/nginx.template.conf /nginx.conf && (php-fpm -y {} & nginx -c /nginx.conf)",
+            format!(
+                "node {} /app/nginx.template.conf /nginx.conf && nginx -c /nginx.conf",
                 app.asset_path("scripts/prestart.mjs"),
-                app.asset_path("php-fpm.conf"),
-            ))
+            )
         } else {
-            StartPhase::new(format!(
-                "node {} {} /nginx.conf && (php-fpm -y {} & nginx -c /nginx.conf)",
+            format!(
+                "node {} {} /nginx.conf && nginx -c /nginx.conf",
                 app.asset_path("scripts/prestart.mjs"),
                 app.asset_path("nginx.template.conf"),
-                app.asset_path("php-fpm.conf"),
-            ))
-        }
+            )
+        };
+
+        StartPhase::from_processes(BTreeMap::from([
+            (
+                "php-fpm".to_string(),
+                format!("php-fpm -y {} -F", app.asset_path("php-fpm.conf")),
+            ),
+            ("nginx".to_string(), nginx),
+        ]))
     }
 
     fn static_assets() -> StaticAssets {
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use super::Provider;
 use crate::nixpacks::{
@@ -32,8 +32,7 @@ impl Provider for ProcfileProvider {
             plan.add_phase(release);
         }
 
-        if let Some(start_cmd) = ProcfileProvider::get_start_cmd(app)? {
-            let start_phase = StartPhase::new(start_cmd);
+        if let Some(start_phase) = ProcfileProvider::get_start_phase(app)? {
             plan.set_start_phase(start_phase);
         }
 
@@ -42,23 +41,17 @@ impl Provider for ProcfileProvider {
 }
 
 impl ProcfileProvider {
-    fn get_start_cmd(app: &App) -> Result<Option<String>> {
+    /// A single process is run directly, multiple processes are run side by side by the supervisor.
+    fn get_start_phase(app: &App) -> Result<Option<StartPhase>> {
         if app.includes_file("Procfile") {
-            let mut procfile: HashMap<String, String> =
+            let mut procfile: BTreeMap<String, String> =
                 app.read_yaml("Procfile").context("Reading Procfile")?;
             procfile.remove("release");
 
-            if procfile.is_empty() {
-                Ok(None)
-            } else if let Some(cmd) = procfile.get("web") {
-                Ok(Some(cmd.to_string()))
-            } else if let Some(cmd) = procfile.get("worker") {
-                Ok(Some(cmd.to_string()))
+            if procfile.len() > 1 {
+                Ok(Some(StartPhase::from_processes(procfile)))
             } else {
-                let mut processes: Vec<_> = procfile.iter().collect();
-                processes.sort_by_key(|&(key, _)| key);
-                let process = processes[0].1.to_string();
-                Ok(Some(process))
+                Ok(procfile.into_values().next().map(StartPhase::new))
             }
         } else {
             Ok(None)
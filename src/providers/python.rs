@@ -17,13 +17,23 @@ use serde::Deserialize;
 use std::result::Result::Ok as OkResult;
 use std::{collections::HashMap, fs};
 
-use super::{Provider, ProviderMetadata};
+use super::{Detection, Provider, ProviderMetadata, ProviderVersion, DEFAULT_CONFIDENCE};
 
 const DEFAULT_PYTHON_PKG_NAME: &str = "python3";
 const POETRY_VERSION: &str = "1.3.1";
 const PDM_VERSION: &str = "2.13.3";
 const UV_VERSION: &str = "0.4.30";
 
+/// Options of requirements.txt lines that read other files of the app.
+const REQUIREMENTS_APP_OPTIONS: &[&str] = &[
+    "-r",
+    "-c",
+    "-e",
+    "--requirement",
+    "--constraint",
+    "--editable",
+];
+
 const VENV_LOCATION: &str = "/opt/venv";
 const UV_CACHE_DIR: &str = "/root/.cache/uv";
 const PIP_CACHE_DIR: &str = "/root/.cache/pip";
@@ -33,6 +43,18 @@ const DEFAULT_POETRY_PYTHON_PKG_NAME: &str = "python3";
 const PYTHON_NIXPKGS_ARCHIVE: &str = "bc8f8d1be58e8c8383e683a06e1e1e57893fff87";
 const LEGACY_PYTHON_NIXPKGS_ARCHIVE: &str = "5148520bfab61f99fd25fb9ff7bfbb50dad3c9db";
 
+// (python_version, nix_pkg_name, archive_version)
+const PYTHON_VERSIONS: &[(&str, &str, &str)] = &[
+    ("2.7", "python27", LEGACY_PYTHON_NIXPKGS_ARCHIVE),
+    ("3.7", "python37", LEGACY_PYTHON_NIXPKGS_ARCHIVE),
+    ("3.8", "python38", LEGACY_PYTHON_NIXPKGS_ARCHIVE),
+    ("3.9", "python39", LEGACY_PYTHON_NIXPKGS_ARCHIVE),
+    ("3.10", "python310", PYTHON_NIXPKGS_ARCHIVE),
+    ("3.11", "python311", PYTHON_NIXPKGS_ARCHIVE),
+    ("3.12", "python312", PYTHON_NIXPKGS_ARCHIVE),
+    ("3.13", "python313", PYTHON_NIXPKGS_ARCHIVE),
+];
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum PackageManagerType {
     PipReqs,
@@ -110,6 +132,22 @@ impl Provider for PythonProvider {
         "python"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        let default = ProviderVersion::new(
+            "python",
+            "3",
+            DEFAULT_PYTHON_PKG_NAME,
+            PYTHON_NIXPKGS_ARCHIVE,
+        )
+        .set_default(true);
+
+        PYTHON_VERSIONS
+            .iter()
+            .map(|(version, pkg, archive)| ProviderVersion::new("python", version, pkg, archive))
+            .chain(std::iter::once(default))
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         let has_python = app.includes_file("main.py")
             || app.includes_file("requirements.txt")
@@ -118,6 +156,27 @@ impl Provider for PythonProvider {
         Ok(has_python)
     }
 
+    fn detect_confidence(&self, app: &App, env: &Environment) -> Result<Detection> {
+        let mut detection = Detection::from_files(
+            app,
+            DEFAULT_CONFIDENCE,
+            &["main.py", "requirements.txt", "pyproject.toml", "Pipfile"],
+        );
+
+        // A Django app is built with Python even when Node comes first for its frontend's package.json
+        if detection.is_detected() && PythonProvider::is_django(app, env)? {
+            detection.confidence += 10;
+            detection.evidence.push("Django app".to_string());
+        }
+
+        Ok(detection)
+    }
+
+    fn compatible_providers(&self) -> &[&str] {
+        // e.g. a Django app with a Vite frontend
+        &["node"]
+    }
+
     fn metadata(&self, app: &App, env: &Environment) -> Result<ProviderMetadata> {
         let is_django = PythonProvider::is_django(app, env)?;
         let is_using_postgres = PythonProvider::is_using_postgres(app, env)?;
@@ -297,6 +356,11 @@ impl PythonProvider {
 
                         install_phase.add_path(format!("{VENV_LOCATION}/bin"));
                         install_phase.add_cache_directory(PIP_CACHE_DIR.to_string());
+                        if let Some(manifests) =
+                            PythonProvider::get_install_manifests(app, manager_type)?
+                        {
+                            install_phase.add_file_dependencies(manifests);
+                        }
 
                         Ok(Some(install_phase))
                     }
@@ -343,6 +407,11 @@ impl PythonProvider {
 
                         install_phase.add_path(format!("{VENV_LOCATION}/bin"));
                         install_phase.add_cache_directory(UV_CACHE_DIR.to_string());
+                        if let Some(manifests) =
+                            PythonProvider::get_install_manifests(app, manager_type)?
+                        {
+                            install_phase.add_file_dependencies(manifests);
+                        }
 
                         Ok(Some(install_phase))
                     }
@@ -373,6 +442,11 @@ impl PythonProvider {
 
                         install_phase.add_path(format!("{VENV_LOCATION}/bin"));
                         install_phase.add_cache_directory(PIP_CACHE_DIR.to_string());
+                        if let Some(manifests) =
+                            PythonProvider::get_install_manifests(app, manager_type)?
+                        {
+                            install_phase.add_file_dependencies(manifests);
+                        }
 
                         Ok(Some(install_phase))
                     }
@@ -577,31 +651,29 @@ impl PythonProvider {
         }
 
         let matches = matches.unwrap();
-        let python_version = (as_default(matches.get(1)), as_default(matches.get(2)));
+        let python_version = match (as_default(matches.get(1)), as_default(matches.get(2))) {
+            ("2", "_") => "2.7".to_string(),
+            (major, minor) => format!("{major}.{minor}"),
+        };
 
         // Match major and minor versions
-        match python_version {
-            ("3", "13") => Ok((Pkg::new("python313"), PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "12") => Ok((Pkg::new("python312"), PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "11") => Ok((Pkg::new("python311"), PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "10") => Ok((Pkg::new("python310"), PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "9") => Ok((Pkg::new("python39"), LEGACY_PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "8") => Ok((Pkg::new("python38"), LEGACY_PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("3", "7") => Ok((Pkg::new("python37"), LEGACY_PYTHON_NIXPKGS_ARCHIVE.into())),
-            ("2", "7" | "_") => Ok((Pkg::new("python27"), LEGACY_PYTHON_NIXPKGS_ARCHIVE.into())),
-            _ => {
-                if app.includes_file("poetry.lock") {
-                    return Ok((
-                        Pkg::new(DEFAULT_POETRY_PYTHON_PKG_NAME),
-                        PYTHON_NIXPKGS_ARCHIVE.into(),
-                    ));
-                }
-                Ok((
-                    Pkg::new(DEFAULT_PYTHON_PKG_NAME),
-                    PYTHON_NIXPKGS_ARCHIVE.into(),
-                ))
-            }
+        if let Some((_, pkg, archive)) = PYTHON_VERSIONS
+            .iter()
+            .find(|(version, _, _)| *version == python_version)
+        {
+            return Ok((Pkg::new(pkg), (*archive).into()));
         }
+
+        if app.includes_file("poetry.lock") {
+            return Ok((
+                Pkg::new(DEFAULT_POETRY_PYTHON_PKG_NAME),
+                PYTHON_NIXPKGS_ARCHIVE.into(),
+            ));
+        }
+        Ok((
+            Pkg::new(DEFAULT_PYTHON_PKG_NAME),
+            PYTHON_NIXPKGS_ARCHIVE.into(),
+        ))
     }
 
     fn read_pyproject(app: &App) -> Result<Option<PyProject>> {
@@ -651,6 +723,64 @@ impl PythonProvider {
         ))
     }
 
+    /// The files the package manager reads while installing, or `None` when it may need the rest of the app too.
+    ///
+    /// That is the case when the app itself is built as a package, or dependencies are installed from a directory
+    /// or include other requirement files. Poetry and PDM always install the app itself.
+    fn get_install_manifests(
+        app: &App,
+        manager_type: PackageManagerType,
+    ) -> Result<Option<Vec<String>>> {
+        let (manifests, optional): (&[&str], &[&str]) = match manager_type {
+            PackageManagerType::PipReqs => {
+                let requirements = app.read_file("requirements.txt").unwrap_or_default();
+                let needs_app = requirements.lines().map(str::trim).any(|line| {
+                    REQUIREMENTS_APP_OPTIONS
+                        .iter()
+                        .any(|option| line.starts_with(option))
+                        || line.starts_with('.')
+                        || line.starts_with('/')
+                        || line.contains("file:")
+                });
+                if needs_app {
+                    return Ok(None);
+                }
+                (&["requirements.txt"], &[])
+            }
+            PackageManagerType::Uv => {
+                let pyproject = app.read_file("pyproject.toml").unwrap_or_default();
+                if pyproject.contains("[build-system]")
+                    || pyproject.contains("[tool.uv.workspace]")
+                    || pyproject.contains("path =")
+                {
+                    return Ok(None);
+                }
+                (&["pyproject.toml", "uv.lock"], &[".python-version"])
+            }
+            PackageManagerType::Pipenv => {
+                let pipfile = app.read_file("Pipfile")?;
+                if pipfile.contains("path =") || pipfile.contains("editable =") {
+                    return Ok(None);
+                }
+                (&["Pipfile"], &["Pipfile.lock"])
+            }
+            _ => return Ok(None),
+        };
+
+        // A package manager chosen with NIXPACKS_PYTHON_PACKAGE_MANAGER may be missing its manifest
+        if !manifests.iter().all(|file| app.includes_file(file)) {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            manifests
+                .iter()
+                .chain(optional.iter().filter(|file| app.includes_file(file)))
+                .map(ToString::to_string)
+                .collect(),
+        ))
+    }
+
     // TODO contains on the contents of a toml is not great, could trigger based on comments, etc
     fn uses_dep(app: &App, dep: &str) -> Result<bool> {
         let is_used = ["requirements.txt", "pyproject.toml", "Pipfile"]
@@ -879,4 +1009,35 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_install_manifests() -> Result<()> {
+        assert_eq!(
+            PythonProvider::get_install_manifests(
+                &App::new("./examples/python-uv")?,
+                PackageManagerType::Uv
+            )?,
+            Some(vec![
+                "pyproject.toml".to_string(),
+                "uv.lock".to_string(),
+                ".python-version".to_string()
+            ])
+        );
+        assert_eq!(
+            PythonProvider::get_install_manifests(
+                &App::new("./examples/python-pipfile")?,
+                PackageManagerType::Pipenv
+            )?,
+            Some(vec!["Pipfile".to_string(), "Pipfile.lock".to_string()])
+        );
+        assert_eq!(
+            PythonProvider::get_install_manifests(
+                &App::new("./examples/python-poetry")?,
+                PackageManagerType::Poetry
+            )?,
+            None
+        );
+
+        Ok(())
+    }
 }
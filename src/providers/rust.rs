@@ -71,7 +71,12 @@ impl RustProvider {
 
         // Custom libs for openssl
         if RustProvider::uses_openssl(app)? {
-            setup.add_pkgs_libs(vec!["openssl".to_string(), "openssl.dev".to_string()]);
+            setup.add_nix_libs(&[
+                Pkg::new("openssl")
+                    .set_export("OPENSSL_DIR", "${openssl.dev}")
+                    .set_export("OPENSSL_LIB_DIR", "${openssl.out}/lib"),
+                Pkg::new("openssl.dev"),
+            ]);
         }
 
         if RustProvider::should_use_musl(app, env)? {
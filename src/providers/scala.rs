@@ -1,8 +1,8 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::Environment,
-    nix::pkg::Pkg,
+    nix::{pkg::Pkg, NIXPKGS_ARCHIVE},
     plan::{
         phase::{Phase, StartPhase},
         BuildPlan,
@@ -14,6 +14,16 @@ pub struct ScalaProvider {}
 
 const DEFAULT_JDK_VERSION: u32 = 17;
 
+// (jdk_version, nix_pkg_name, run_image)
+const JDK_VERSIONS: &[(u32, &str, &str)] = &[
+    (8, "jdk8", "eclipse-temurin:8u392-b08-jre-jammy"),
+    (11, "jdk11", "eclipse-temurin:11.0.21_9-jre-jammy"),
+    (17, "jdk17", "eclipse-temurin:17.0.9_9-jre-jammy"),
+    (19, "jdk", "eclipse-temurin:19.0.2_7-jre-jammy"),
+    (20, "jdk20", "eclipse-temurin:20.0.2_9-jre-jammy"),
+    (21, "jdk21", "eclipse-temurin:21.0.1_12-jre-jammy"),
+];
+
 /**
  * Scala provider currently supports sbt.
  * - The sbt project requires sbt-native-packager, a popular packaging
@@ -28,6 +38,16 @@ impl Provider for ScalaProvider {
         "scala"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        JDK_VERSIONS
+            .iter()
+            .map(|(version, pkg, _)| {
+                ProviderVersion::new("jdk", &version.to_string(), pkg, NIXPKGS_ARCHIVE)
+                    .set_default(*version == DEFAULT_JDK_VERSION)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("build.sbt"))
     }
@@ -76,30 +96,25 @@ impl ScalaProvider {
         }
     }
 
+    /// The JDK table entry of the version, using 17 as default because its the latest LTS.
+    fn get_jdk(&self, jdk_version: u32) -> &(u32, &'static str, &'static str) {
+        JDK_VERSIONS
+            .iter()
+            .find(|(version, _, _)| *version == jdk_version)
+            .or_else(|| {
+                JDK_VERSIONS
+                    .iter()
+                    .find(|(version, _, _)| *version == DEFAULT_JDK_VERSION)
+            })
+            .unwrap()
+    }
+
     fn get_jdk_pkg_name(&self, jdk_version: u32) -> &str {
-        match jdk_version {
-            21 => "jdk21",
-            20 => "jdk20",
-            19 => "jdk",
-            11 => "jdk11",
-            8 => "jdk8",
-
-            // Using 17 as default because its the latest LTS
-            _ => "jdk17",
-        }
+        self.get_jdk(jdk_version).1
     }
 
     fn get_jdk_run_image(&self, jdk_version: u32) -> &str {
-        match jdk_version {
-            21 => "eclipse-temurin:21.0.1_12-jre-jammy",
-            20 => "eclipse-temurin:20.0.2_9-jre-jammy",
-            19 => "eclipse-temurin:19.0.2_7-jre-jammy",
-            11 => "eclipse-temurin:11.0.21_9-jre-jammy",
-            8 => "eclipse-temurin:8u392-b08-jre-jammy",
-
-            // Using 17 as default because its the latest LTS
-            _ => "eclipse-temurin:17.0.9_9-jre-jammy",
-        }
+        self.get_jdk(jdk_version).2
     }
 
     fn is_using_sbt(&self, app: &App) -> bool {
@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{Detection, Provider, DEFAULT_CONFIDENCE};
 use crate::nixpacks::{
     app::{App, StaticAssets},
     environment::Environment,
@@ -37,18 +37,49 @@ impl Provider for StaticfileProvider {
             || app.includes_file("index.html"))
     }
 
+    fn detect_confidence(&self, app: &App, _env: &Environment) -> Result<Detection> {
+        if app.includes_file("Staticfile") {
+            return Ok(Detection::new(
+                DEFAULT_CONFIDENCE,
+                vec!["Staticfile".to_string()],
+            ));
+        }
+
+        // These are also common in apps of other languages, so they are weak evidence
+        let evidence = ["public", "index", "dist"]
+            .iter()
+            .filter(|dir| app.includes_directory(dir))
+            .map(|dir| format!("{dir}/ directory"))
+            .chain(
+                app.includes_file("index.html")
+                    .then(|| "index.html".to_string()),
+            )
+            .collect::<Vec<_>>();
+
+        if evidence.is_empty() {
+            Ok(Detection::default())
+        } else {
+            Ok(Detection::new(DEFAULT_CONFIDENCE / 2, evidence))
+        }
+    }
+
     fn get_build_plan(&self, app: &App, env: &Environment) -> Result<Option<BuildPlan>> {
         let mut setup = Phase::setup(Some(vec![Pkg::new("nginx")]));
         setup.add_cmd("mkdir /etc/nginx/ /var/log/nginx/ /var/cache/nginx/");
 
         // shell command to edit 0.0.0.0:80 to $PORT
         let shell_cmd = "[[ -z \"${PORT}\" ]] && echo \"Environment variable PORT not found. Using PORT 80\" || sed -i \"s/0.0.0.0:80/$PORT/g\"";
-        let start = StartPhase::new(format!(
-            "{shell_cmd} {conf_location} && nginx -c {conf_location}",
+        let mut start = StartPhase::new(format!(
+            "{shell_cmd} {conf_location} && exec nginx -c {conf_location}",
             shell_cmd = shell_cmd,
             conf_location = app.asset_path("nginx.conf"),
         ));
 
+        // nginx listens on 80 unless PORT is set, and shuts down gracefully on SIGQUIT. It replaces the shell, so it gets
+        // the signal
+        start.expose_port(80);
+        start.stop_signal = Some("SIGQUIT".to_string());
+
         let static_assets = StaticfileProvider::get_static_assets(app, env)?;
 
         let mut plan = BuildPlan::new(&vec![setup], Some(start));
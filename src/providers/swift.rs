@@ -1,4 +1,4 @@
-use super::Provider;
+use super::{Provider, ProviderVersion};
 use crate::nixpacks::{
     app::App,
     environment::Environment,
@@ -41,6 +41,16 @@ impl Provider for SwiftProvider {
         "swift"
     }
 
+    fn versions(&self) -> Vec<ProviderVersion> {
+        AVAILABLE_SWIFT_VERSIONS
+            .iter()
+            .map(|(version, archive)| {
+                ProviderVersion::new("swift", version, "swift", archive)
+                    .set_default(*version == DEFAULT_SWIFT_VERSION)
+            })
+            .collect()
+    }
+
     fn detect(&self, app: &App, _env: &Environment) -> Result<bool> {
         Ok(app.includes_file("Package.swift"))
     }
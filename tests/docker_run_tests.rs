@@ -445,6 +445,16 @@ async fn test_node() {
     assert!(run_image(&name, None).await.contains("Hello from Node"));
 }
 
+#[tokio::test]
+async fn test_config_closure_only() {
+    let name = simple_build("./examples/config-closure-only")
+        .await
+        .unwrap();
+    assert!(run_image(&name, None)
+        .await
+        .contains("Hello from a closure-only image"));
+}
+
 #[tokio::test]
 async fn test_node_nx_default_app() {
     let name = simple_build("./examples/node-nx").await.unwrap();
@@ -1,4 +1,8 @@
-use nixpacks::{generate_build_plan, nixpacks::plan::generator::GeneratePlanOptions};
+use nixpacks::{
+    explain_build_plan, generate_build_plan, get_plan_providers,
+    nixpacks::plan::generator::GeneratePlanOptions,
+};
+use std::collections::BTreeMap;
 use std::env::consts::ARCH;
 
 test_helper::generate_plan_tests!();
@@ -73,6 +77,27 @@ fn test_rust_rocket_no_musl() {
     assert_plan_snapshot!(plan);
 }
 
+#[test]
+fn test_rust_openssl() {
+    let plan = simple_gen_plan("./examples/rust-openssl");
+    let setup = plan.get_phase("setup").unwrap();
+
+    assert_eq!(
+        setup.nix_libs,
+        Some(vec!["openssl".to_string(), "openssl.dev".to_string()])
+    );
+    assert_eq!(
+        setup.nix_exports,
+        Some(BTreeMap::from([
+            ("OPENSSL_DIR".to_string(), "${openssl.dev}".to_string()),
+            (
+                "OPENSSL_LIB_DIR".to_string(),
+                "${openssl.out}/lib".to_string()
+            ),
+        ]))
+    );
+}
+
 #[test]
 fn test_rust_cargo_workspaces() {
     let plan = simple_gen_plan("./examples/rust-cargo-workspaces");
@@ -117,3 +142,70 @@ fn test_node_turborepo_custom_app() {
     .unwrap();
     assert!(plan.start_phase.unwrap().cmd.unwrap().contains("docs"));
 }
+
+#[test]
+fn test_node_install_all_files() {
+    let plan = generate_build_plan(
+        "./examples/node-npm",
+        vec!["NIXPACKS_INSTALL_ALL_FILES=1"],
+        &GeneratePlanOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(plan.get_phase("install").unwrap().only_include_files, None);
+}
+
+#[test]
+fn test_php_install_without_manifests() {
+    // Apps without composer.json or package.json copy the whole app, as they did before manifests were copied
+    let plan = generate_build_plan(
+        "./examples/php-vanilla",
+        Vec::new(),
+        &GeneratePlanOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(plan.get_phase("install").unwrap().only_include_files, None);
+    assert!(!plan.copies_some_files().unwrap());
+}
+
+#[test]
+fn test_mixed_language_providers() {
+    let providers =
+        |path: &str| get_plan_providers(path, Vec::new(), &GeneratePlanOptions::default()).unwrap();
+
+    // Python files next to another language don't replace the provider that comes first
+    assert_eq!(providers("./examples/node-with-requirements"), vec!["node"]);
+    assert_eq!(providers("./examples/ruby-with-requirements"), vec!["ruby"]);
+    assert_eq!(providers("./examples/php-with-main-py"), vec!["php"]);
+
+    assert_eq!(
+        providers("./examples/python-django-node"),
+        vec!["python", "node"]
+    );
+}
+
+#[test]
+fn test_explain_matches_plan() {
+    let envs = vec!["NIXPACKS_BUILD_CMD=npm run compile", "FOO=bar"];
+    let plan = generate_build_plan(
+        "./examples/node-npm",
+        envs.clone(),
+        &GeneratePlanOptions::default(),
+    )
+    .unwrap();
+    let explanation =
+        explain_build_plan("./examples/node-npm", envs, &GeneratePlanOptions::default()).unwrap();
+
+    let build_cmds = explanation.get("phases.build.cmds").unwrap();
+    assert_eq!(build_cmds.source, "env NIXPACKS_BUILD_CMD");
+    assert_eq!(
+        plan.get_phase("build").unwrap().cmds,
+        Some(vec!["npm run compile".to_string()])
+    );
+
+    let install_cmds = explanation.get("phases.install.cmds").unwrap();
+    assert_eq!(install_cmds.source, "node provider");
+
+    for name in plan.variables.unwrap_or_default().keys() {
+        assert!(explanation.get(&format!("variables.{name}")).is_some());
+    }
+}